@@ -0,0 +1,360 @@
+use crate::motion::*;
+use crate::text::*;
+use std::cmp;
+use std::fs;
+
+/// A word the text may be completed with.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Candidate {
+    pub word: String,
+    /// What kind of thing the word is, shown next to it, e.g. `Function`.
+    pub kind: String,
+}
+
+impl Candidate {
+    pub fn new(word: &str, kind: &str) -> Self {
+        Candidate {
+            word: word.to_string(),
+            kind: kind.to_string(),
+        }
+    }
+}
+
+/// Where candidates for completion come from.
+pub trait Source {
+    /// Column where the text to complete starts in `line`, left of `column`.
+    fn start(&self, line: &[char], column: usize) -> usize;
+    /// The candidates for the text, in the order they are offered.
+    fn candidates(&self, base: &str) -> Vec<Candidate>;
+}
+
+/// Column where the run of characters accepted by `accept` ending at `column` starts.
+fn run_start(line: &[char], column: usize, accept: impl Fn(char) -> bool) -> usize {
+    line[..column]
+        .iter()
+        .rposition(|&c| !accept(c))
+        .map_or(0, |i| i + 1)
+}
+
+/// The words of the buffers, for `Ctrl-n` and `Ctrl-p`: those of the current buffer
+/// from the cursor on, wrapping around, then those of the other buffers.
+pub struct Words<'a> {
+    /// The current buffer first.
+    pub texts: Vec<&'a TextState>,
+    /// Line and column of the cursor in the current buffer. The word being typed there
+    /// is left out.
+    pub cursor: (usize, usize),
+    /// Characters words are made of, from `iskeyword`.
+    pub keyword: Keyword,
+}
+
+impl<'a> Source for Words<'a> {
+    fn start(&self, line: &[char], column: usize) -> usize {
+        run_start(line, column, |c| self.keyword.contains(c))
+    }
+    fn candidates(&self, _base: &str) -> Vec<Candidate> {
+        let mut words: Vec<String> = Vec::new();
+        for (i, text) in self.texts.iter().enumerate() {
+            let first = if i == 0 { self.cursor.0 } else { 0 };
+            let lines = (first..text.len()).chain(0..first);
+            for number in lines {
+                let line = &text[number];
+                let mut column = 0;
+                while column < line.len() {
+                    if !self.keyword.contains(line[column]) {
+                        column += 1;
+                        continue;
+                    }
+                    let end = line[column..]
+                        .iter()
+                        .position(|&c| !self.keyword.contains(c))
+                        .map_or(line.len(), |length| column + length);
+                    let typed = i == 0 && number == self.cursor.0 && end == self.cursor.1;
+                    let word: String = line[column..end].iter().collect();
+                    if !typed && !words.contains(&word) {
+                        words.push(word);
+                    }
+                    column = end;
+                }
+            }
+        }
+        words.iter().map(|word| Candidate::new(word, "")).collect()
+    }
+}
+
+/// File names, for `Ctrl-x Ctrl-f`: the entries of the directory the text names,
+/// relative to the current directory unless it starts with `/`.
+pub struct Paths;
+
+impl Source for Paths {
+    fn start(&self, line: &[char], column: usize) -> usize {
+        run_start(line, column, |c| {
+            !c.is_whitespace() && !"\"'`()<>[]{},;=".contains(c)
+        })
+    }
+    fn candidates(&self, base: &str) -> Vec<Candidate> {
+        let (directory, name) = match base.rfind('/') {
+            Some(i) => base.split_at(i + 1),
+            None => ("", base),
+        };
+        let entries = match fs::read_dir(if directory.is_empty() { "." } else { directory }) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        let mut candidates: Vec<Candidate> = entries
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                // Hidden files only when a name starting with a dot is typed.
+                if file_name.starts_with('.') && !name.starts_with('.') {
+                    return None;
+                }
+                let is_dir = entry.file_type().ok()?.is_dir();
+                let word = format!("{}{}", directory, file_name);
+                Some(if is_dir {
+                    Candidate::new(&(word + "/"), "[dir]")
+                } else {
+                    Candidate::new(&word, "[file]")
+                })
+            })
+            .collect();
+        candidates.sort_by(|a, b| a.word.cmp(&b.word));
+        candidates
+    }
+}
+
+/// Whole lines of the buffers, for `Ctrl-x Ctrl-l`, without their indentation.
+pub struct Lines<'a> {
+    /// The current buffer first.
+    pub texts: Vec<&'a TextState>,
+    /// The cursor line of the current buffer, which is left out.
+    pub line: usize,
+}
+
+impl<'a> Source for Lines<'a> {
+    fn start(&self, line: &[char], column: usize) -> usize {
+        cmp::min(
+            line.iter()
+                .position(|c| !c.is_whitespace())
+                .unwrap_or(column),
+            column,
+        )
+    }
+    fn candidates(&self, _base: &str) -> Vec<Candidate> {
+        let mut lines: Vec<String> = Vec::new();
+        for (i, text) in self.texts.iter().enumerate() {
+            for (number, line) in text.iter().enumerate() {
+                let line: String = line.iter().collect();
+                let line = line.trim();
+                if (i == 0 && number == self.line) || line.is_empty() {
+                    continue;
+                }
+                if !lines.iter().any(|known| known == line) {
+                    lines.push(line.to_string());
+                }
+            }
+        }
+        lines.iter().map(|line| Candidate::new(line, "")).collect()
+    }
+}
+
+/// Candidates known beforehand, such as those a language server sent, completing the
+/// word before the cursor.
+pub struct Items {
+    pub candidates: Vec<Candidate>,
+    /// Characters words are made of, from `iskeyword`.
+    pub keyword: Keyword,
+}
+
+impl Source for Items {
+    fn start(&self, line: &[char], column: usize) -> usize {
+        run_start(line, column, |c| self.keyword.contains(c))
+    }
+    fn candidates(&self, _base: &str) -> Vec<Candidate> {
+        self.candidates.clone()
+    }
+}
+
+/// How well `word` matches `pattern`, whose characters it must contain in order,
+/// ignoring case. Lower is better: words starting with the pattern come first, then
+/// those where it is found in few pieces, early and with little in between.
+pub fn fuzzy_score(pattern: &str, word: &str) -> Option<usize> {
+    if pattern.is_empty() {
+        return Some(0);
+    }
+    let word: Vec<char> = word.chars().flat_map(char::to_lowercase).collect();
+    let mut position = 0;
+    let mut first = None;
+    let mut pieces = 0;
+    let mut matched = 0;
+    for c in pattern.chars().flat_map(char::to_lowercase) {
+        matched += 1;
+        let found = position + word[position..].iter().position(|&w| w == c)?;
+        if first.is_none() || found != position {
+            pieces += 1;
+        }
+        first.get_or_insert(found);
+        position = found + 1;
+    }
+    let first = first.unwrap_or(0);
+    let between = position - first - matched;
+    let prefix = if first == 0 && pieces == 1 { 0 } else { 1 };
+    Some(prefix * 10000 + pieces * 100 + first + between)
+}
+
+/// The completion menu shown while typing in Insert mode.
+pub struct Completion {
+    /// Line being completed.
+    pub line: usize,
+    /// Column where the completed text starts.
+    pub start: usize,
+    candidates: Vec<Candidate>,
+    /// Indices of the candidates matching what was typed, best first.
+    matches: Vec<usize>,
+    /// Position in `matches` of the candidate put in the text. `None` when the text
+    /// is what was typed.
+    pub selected: Option<usize>,
+    /// The text typed from the start, which the candidates are filtered with.
+    pub typed: String,
+}
+
+impl Completion {
+    /// Complete the text before `column` in line `line` of `text` from `source`. `None`
+    /// when nothing matches.
+    pub fn new(source: &dyn Source, text: &TextState, line: usize, column: usize) -> Option<Self> {
+        let start = source.start(&text[line], column);
+        let typed: String = text[line][start..column].iter().collect();
+        let mut completion = Completion {
+            line,
+            start,
+            candidates: source.candidates(&typed),
+            matches: Vec::new(),
+            selected: None,
+            typed: String::new(),
+        };
+        completion.filter(&typed);
+        if completion.matches.is_empty() {
+            None
+        } else {
+            Some(completion)
+        }
+    }
+
+    /// Keep the candidates which match the text typed, and select none of them.
+    pub fn filter(&mut self, typed: &str) {
+        let mut scored: Vec<(usize, usize)> = self
+            .candidates
+            .iter()
+            .enumerate()
+            .filter(|(_, candidate)| candidate.word != typed)
+            .filter_map(|(i, candidate)| Some((fuzzy_score(typed, &candidate.word)?, i)))
+            .collect();
+        scored.sort_by_key(|&(score, _)| score);
+        self.matches = scored.into_iter().map(|(_, i)| i).collect();
+        self.selected = None;
+        self.typed = typed.to_string();
+    }
+
+    /// Whether no candidate matches.
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty()
+    }
+
+    /// The matching candidates, best first.
+    pub fn items(&self) -> Vec<&Candidate> {
+        self.matches.iter().map(|&i| &self.candidates[i]).collect()
+    }
+
+    /// Select the candidate `offset` places down the list, wrapping around through the
+    /// text typed.
+    pub fn select(&mut self, offset: isize) {
+        let states = self.matches.len() as isize + 1;
+        let current = self.selected.map_or(states - 1, |i| i as isize);
+        let next = (current + offset).rem_euclid(states);
+        self.selected = if next == states - 1 {
+            None
+        } else {
+            Some(next as usize)
+        };
+    }
+
+    /// The text to put from the start: the selected candidate or what was typed.
+    pub fn text(&self) -> &str {
+        match self.selected {
+            Some(i) => &self.candidates[self.matches[i]].word,
+            None => &self.typed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(lines: &[&str]) -> TextState {
+        lines.iter().map(|line| line.chars().collect()).collect()
+    }
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert_eq!(fuzzy_score("", "abc"), Some(0));
+        assert_eq!(fuzzy_score("ac", "abc"), Some(10201));
+        assert!(fuzzy_score("ab", "abc") < fuzzy_score("bc", "abc"));
+        assert!(fuzzy_score("bc", "abc") < fuzzy_score("ac", "abc"));
+        assert_eq!(fuzzy_score("AB", "xaYb"), Some(10202));
+        assert_eq!(fuzzy_score("ba", "abc"), None);
+    }
+
+    #[test]
+    fn test_words_and_lines() {
+        let current = text(&["fooBar fob", "fo", "food(foo)"]);
+        let other = text(&["  fooBar baz"]);
+        let words = Words {
+            texts: vec![&current, &other],
+            cursor: (1, 2),
+            keyword: Keyword::default(),
+        };
+        let mut completion = Completion::new(&words, &current, 1, 2).unwrap();
+        assert_eq!(completion.start, 0);
+        let found: Vec<&str> = completion.items().iter().map(|c| c.word.as_str()).collect();
+        assert_eq!(found, ["food", "foo", "fooBar", "fob"]);
+        completion.select(-1);
+        assert_eq!(completion.text(), "fob");
+        completion.select(1);
+        assert_eq!(completion.text(), "fo");
+        completion.filter("fb");
+        let found: Vec<&str> = completion.items().iter().map(|c| c.word.as_str()).collect();
+        assert_eq!(found, ["fob", "fooBar"]);
+        let lines = Lines {
+            texts: vec![&current, &other],
+            line: 1,
+        };
+        let completion = Completion::new(&lines, &current, 1, 2).unwrap();
+        let found: Vec<&str> = completion.items().iter().map(|c| c.word.as_str()).collect();
+        assert_eq!(found, ["fooBar fob", "food(foo)", "fooBar baz"]);
+        assert!(Completion::new(&words, &text(&["zzz"]), 0, 3).is_none());
+        // Words are made of the characters of `iskeyword`.
+        let current = text(&["foo-bar", "f"]);
+        let words = Words {
+            texts: vec![&current],
+            cursor: (1, 1),
+            keyword: Keyword::parse("@,-").unwrap(),
+        };
+        let completion = Completion::new(&words, &current, 1, 1).unwrap();
+        let found: Vec<&str> = completion.items().iter().map(|c| c.word.as_str()).collect();
+        assert_eq!(found, ["foo-bar"]);
+    }
+
+    #[test]
+    fn test_paths() {
+        let line: Vec<char> = "mod \"src/compl".chars().collect();
+        assert_eq!(Paths.start(&line, line.len()), 5);
+        let completion = Completion::new(&Paths, &vec![line.clone()], 0, line.len()).unwrap();
+        assert_eq!(
+            completion.items()[0],
+            &Candidate::new("src/complete.rs", "[file]")
+        );
+        let found = Paths.candidates("sr");
+        assert!(found.contains(&Candidate::new("src/", "[dir]")));
+    }
+}
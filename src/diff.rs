@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use crate::text::*;
+
+/// How a line differs from the other file in diff mode.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LineDiff {
+    Same,
+    /// The line is not in the other file.
+    Added,
+    /// The line replaces a line of the other file.
+    Changed,
+}
+
+/// Compare the lines of `text` with those of `other`, by the shortest edit script of
+/// Myers' algorithm, which takes linear space. In each run of differing lines, lines
+/// facing lines of `other` count as changed and the rest as added.
+pub fn diff_lines(text: &TextState, other: &TextState) -> Vec<LineDiff> {
+    // Lines are compared by number, the same for equal lines.
+    let mut numbers = HashMap::new();
+    let a = number_lines(text, &mut numbers);
+    let b = number_lines(other, &mut numbers);
+    let mut matches = Vec::new();
+    common_lines(&a, &b, (0, 0), &mut matches);
+
+    let mut result = vec![LineDiff::Same; text.len()];
+    let (mut i, mut j) = (0, 0);
+    let end = std::iter::once((text.len(), other.len()));
+    for (x, y) in matches.into_iter().chain(end) {
+        for (k, line) in (i..x).enumerate() {
+            result[line] = if k < y - j {
+                LineDiff::Changed
+            } else {
+                LineDiff::Added
+            };
+        }
+        (i, j) = (x + 1, y + 1);
+    }
+    result
+}
+
+/// The numbers of the lines of `text`, given in `numbers` to each distinct line.
+fn number_lines<'a>(text: &'a TextState, numbers: &mut HashMap<&'a [char], u32>) -> Vec<u32> {
+    text.iter()
+        .map(|line| {
+            let next = numbers.len() as u32;
+            *numbers.entry(line.as_slice()).or_insert(next)
+        })
+        .collect()
+}
+
+/// Add the pairs of lines `a` and `b` have in common to `matches`, in order, numbered
+/// from `start`.
+fn common_lines(a: &[u32], b: &[u32], start: (usize, usize), matches: &mut Vec<(usize, usize)>) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+    let (i, j) = (start.0 + prefix, start.1 + prefix);
+    matches.extend((0..prefix).map(|k| (start.0 + k, start.1 + k)));
+    if !a.is_empty() && !b.is_empty() {
+        let (x, y, end_x, end_y) = middle_snake(a, b);
+        common_lines(&a[..x], &b[..y], (i, j), matches);
+        matches.extend((0..end_x - x).map(|k| (i + x + k, j + y + k)));
+        common_lines(&a[end_x..], &b[end_y..], (i + end_x, j + end_y), matches);
+    }
+    matches.extend((0..suffix).map(|k| (i + a.len() + k, j + b.len() + k)));
+}
+
+/// The middle snake of the shortest edit script from `a` to `b`, which are not empty:
+/// the common lines from `(x, y)` to `(end x, end y)` that the script goes through half
+/// way, found by searching from both ends at once.
+fn middle_snake(a: &[u32], b: &[u32]) -> (usize, usize, usize, usize) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (n + m + 1) / 2;
+    let offset = max + 1;
+    // The furthest x reached on each diagonal, from the start and from the end.
+    let mut forward = vec![0; 2 * offset as usize + 1];
+    let mut backward = forward.clone();
+    let index = |k: isize| (k + offset) as usize;
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && forward[index(k - 1)] < forward[index(k + 1)]) {
+                forward[index(k + 1)]
+            } else {
+                forward[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            let (start_x, start_y) = (x, y);
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[index(k)] = x;
+            // The same diagonal counted from the end.
+            let c = delta - k;
+            if odd && (1 - d..d).contains(&c) && x + backward[index(c)] >= n {
+                return (start_x as usize, start_y as usize, x as usize, y as usize);
+            }
+        }
+        for c in (-d..=d).step_by(2) {
+            let mut x = if c == -d || (c != d && backward[index(c - 1)] < backward[index(c + 1)]) {
+                backward[index(c + 1)]
+            } else {
+                backward[index(c - 1)] + 1
+            };
+            let mut y = x - c;
+            let (start_x, start_y) = (x, y);
+            while x < n && y < m && a[(n - 1 - x) as usize] == b[(m - 1 - y) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[index(c)] = x;
+            let k = delta - c;
+            if !odd && (-d..=d).contains(&k) && forward[index(k)] + x >= n {
+                let (x, y) = ((n - x) as usize, (m - y) as usize);
+                return (x, y, (n - start_x) as usize, (m - start_y) as usize);
+            }
+        }
+    }
+    unreachable!("the searches meet by the middle of the script")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(lines: &[&str]) -> TextState {
+        lines.iter().map(|line| line.chars().collect()).collect()
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        let a = text(&["a", "b", "x", "y", "c", "z"]);
+        let b = text(&["a", "b", "q", "c"]);
+        assert_eq!(
+            diff_lines(&a, &b),
+            [
+                LineDiff::Same,
+                LineDiff::Same,
+                LineDiff::Changed,
+                LineDiff::Added,
+                LineDiff::Same,
+                LineDiff::Added,
+            ]
+        );
+        assert_eq!(diff_lines(&b, &a)[2], LineDiff::Changed);
+        assert_eq!(diff_lines(&a, &a), [LineDiff::Same; 6]);
+    }
+
+    #[test]
+    fn test_diff_long_files() {
+        let lines: Vec<String> = (0..20000).map(|i| i.to_string()).collect();
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let a = text(&lines);
+        let mut b = a.clone();
+        b[0] = vec!['x'];
+        b.remove(10000);
+        b.push(vec!['y']);
+        let diff = diff_lines(&a, &b);
+        assert_eq!(diff[0], LineDiff::Changed);
+        assert_eq!(diff[10000], LineDiff::Added);
+        assert_eq!(diff.iter().filter(|&&d| d != LineDiff::Same).count(), 2);
+        let diff = diff_lines(&b, &a);
+        assert_eq!(diff[19999], LineDiff::Added);
+        assert_eq!(diff.iter().filter(|&&d| d != LineDiff::Same).count(), 2);
+        let c = text(&["p", "a", "q", "b", "r"]);
+        let d = text(&["a", "s", "b", "t", "u"]);
+        assert_eq!(
+            diff_lines(&c, &d),
+            [
+                LineDiff::Added,
+                LineDiff::Same,
+                LineDiff::Changed,
+                LineDiff::Same,
+                LineDiff::Changed,
+            ]
+        );
+    }
+}
@@ -1,371 +1,3578 @@
+use crate::complete::*;
+use crate::diff::*;
+use crate::event::*;
+use crate::json::*;
+use crate::keymap::*;
+use crate::layout::*;
+use crate::lsp::*;
+use crate::mark::*;
+use crate::motion::*;
+use crate::options::*;
+use crate::render::*;
 use crate::screen::*;
+use crate::syntax::*;
+use crate::terminal::*;
 use crate::text::*;
+use crate::textobject::*;
+use crate::theme::*;
 use crate::util::*;
+use crate::window::*;
 use std::fs;
-use std::io::{BufRead, Error, Write};
-use termion::event::{Event, Key, MouseEvent};
-use termion::input::TermRead;
-pub struct Editor<R, W>
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use termion::event::{Event, Key, MouseButton, MouseEvent};
+pub struct Editor<T>
 where
-    R: BufRead,
-    W: Write,
+    T: Terminal,
 {
-    filepath: String,
+    /// The buffer shown in the current window.
     buffer: Buffer,
-    io: IO<R, W>,
+    /// The other buffers of the buffer list, in no particular order.
+    buffers: Vec<Buffer>,
+    /// Number given to the next buffer added to the list.
+    next_number: usize,
+    /// Number of the buffer shown before the current one, for `Ctrl-^`.
+    alternate: Option<usize>,
+    /// The window with the cursor.
+    window: Window,
+    /// The other windows, the one used last at the end.
+    windows: Vec<Window>,
+    /// How the windows divide the terminal.
+    tree: WindowTree,
+    /// Id given to the next window.
+    next_window: usize,
+    /// The other tab pages, in order.
+    tab_pages: Vec<TabPage>,
+    /// Position of the current tab page among all of them.
+    tab_index: usize,
+    /// The files given on the command line, for `:next` and `:previous`.
+    args: Vec<String>,
+    /// Index in `args` of the file being edited.
+    arg_index: usize,
+    /// Files from the command line are opened with `readonly` set, as with `rim -R`.
+    readonly: bool,
+    /// Diff mode, as with `rim -d`: lines differing from the other file are highlighted.
+    diff: bool,
+    terminal: T,
+    renderer: Renderer,
+    /// First key of a multi-key Normal mode command such as `gj` or `Ctrl-w j`.
+    pending: Option<Key>,
+    /// The count typed in Normal mode before a command, e.g. `3` of `3w`.
+    count: Option<usize>,
+    /// The operator typed in Normal mode, `d`, `c` or `y`, waiting for a motion, and the
+    /// count typed before it.
+    operator: Option<(char, usize)>,
+    /// The last `f`, `F`, `t` or `T`, repeated by `;` and `,`.
+    last_find: Option<Find>,
+    /// The characters `;` goes to are highlighted, after a search with `h` in
+    /// `charsearch`.
+    show_targets: bool,
+    grammars: Registry,
+    theme: Theme,
+    options: GlobalOptions,
+    /// The global values of the buffer options, which buffers take when first shown.
+    buffer_options: BufferOptions,
+    /// The global values of the window options, which new windows take.
+    window_options: ScreenState,
+    /// Shown on the status line at the next redraw, e.g. errors of the startup file.
+    /// A message of several lines is shown above the status line until a key is pressed.
+    message: Option<String>,
+    /// A message of several lines is shown and waits for a key.
+    more: bool,
+    mode: Mode,
+    keymap: Keymap,
+    /// Keys typed or produced by mappings which were not handled yet.
+    typed: Vec<TypedKey>,
+    /// The key being handled comes from a `<silent>` mapping.
+    silent: bool,
+    /// `:qa` was given.
+    quit: bool,
+    /// Where Visual mode started, as line and column.
+    visual_start: (usize, usize),
+    /// The text deleted or yanked last, put back by `p` and `P`.
+    register: Option<Register>,
+    /// Time, position and number of the last clicks, to tell double and triple clicks.
+    last_click: Option<(Instant, u16, u16, usize)>,
+    /// The mouse button went down on the text of the current window, so that moving
+    /// the mouse selects text.
+    dragging: bool,
+    /// Command of the language server for each filetype, from `:LspServer`.
+    lsp_commands: Vec<(String, Vec<String>)>,
+    /// The language servers started, one per filetype.
+    servers: Vec<Server>,
+    /// The completion menu shown in Insert mode.
+    completion: Option<Completion>,
+    /// Where the cursor jumped from, for `Ctrl-o` and `Ctrl-i`.
+    jumps: JumpList,
+}
+
+/// Mappings expanded while handling one key before giving up on a recursive mapping.
+const MAX_MAP_DEPTH: usize = 1000;
+
+const NOT_WRITTEN: &str = "No write since last change (add ! to override)";
+
+/// Longest time between the clicks of a double click.
+const DOUBLE_CLICK: Duration = Duration::from_millis(500);
+
+/// Lines scrolled by one step of the mouse wheel.
+const WHEEL_LINES: i32 = 3;
+
+/// Text deleted or yanked.
+struct Register {
+    text: TextState,
+    /// Whole lines were taken, and are put back as lines.
+    linewise: bool,
+}
+
+struct TypedKey {
+    key: Key,
+    /// Whether the key may start a mapping; not for keys from `:noremap` mappings.
+    remap: bool,
+    silent: bool,
 }
 
 pub struct Buffer {
-    screen: ScreenState,
+    /// Identifies the buffer in `:ls` and `:b N`; never reused.
+    number: usize,
+    /// File of the buffer; `None` for text read from stdin and new buffers.
+    filepath: Option<String>,
     text: TextState,
+    /// Line and column of the cursor when a window last left the buffer.
+    cursor: (usize, usize),
+    highlighter: Option<Highlighter>,
+    options: BufferOptions,
+    /// Mappings defined with `<buffer>`.
+    keymap: Keymap,
+    /// The text was changed since it was read or written.
+    modified: bool,
+    /// How the lines differ from the other file in diff mode, once computed.
+    diff: Option<Vec<LineDiff>>,
+    /// The version of the text the language server last got and the position of its
+    /// end, once the buffer was opened on one.
+    synced: Option<(u64, Json)>,
+    /// The lines edited since the text was last sent to the language server.
+    edited: Option<LineChange>,
+    /// The file was written since the language server was last told.
+    written: bool,
+    /// Errors and warnings the language server found, by line.
+    diagnostics: Vec<Diagnostic>,
+    marks: Marks,
+    /// The text was changed since the change list was last added to.
+    changed: bool,
+    /// The buffer was shown in a window, since when its options are its own.
+    shown: bool,
 }
-impl<R, W> Editor<R, W>
+impl Buffer {
+    fn new(
+        number: usize,
+        filepath: Option<String>,
+        text: TextState,
+        highlighter: Option<Highlighter>,
+    ) -> Self {
+        Buffer {
+            number,
+            filepath,
+            text,
+            cursor: (0, 0),
+            highlighter,
+            options: BufferOptions::default(),
+            keymap: Keymap::default(),
+            modified: false,
+            diff: None,
+            synced: None,
+            edited: None,
+            written: false,
+            diagnostics: Vec::new(),
+            marks: Marks::default(),
+            changed: false,
+            shown: false,
+        }
+    }
+
+    /// The file name shown by `:ls`.
+    fn name(&self) -> &str {
+        self.filepath.as_deref().unwrap_or("[No Name]")
+    }
+
+    /// Handle `:w`, `:w!` and `:w {file}`. Writing to a new file name for a buffer
+    /// without one gives the buffer that name.
+    fn write(&mut self, path: Option<String>, force: bool) -> Result<Option<String>, String> {
+        if self.options.readonly && !force {
+            return Err("'readonly' option is set (add ! to override)".to_string());
+        }
+        let path = match (path, &self.filepath) {
+            (Some(path), _) => path,
+            (None, Some(filepath)) => filepath.clone(),
+            (None, None) => return Err(format!("No file name for buffer {}", self.number)),
+        };
+        match save_to_file(&path, &self.text) {
+            Ok(_) => {
+                if self
+                    .filepath
+                    .as_ref()
+                    .is_none_or(|filepath| *filepath == path)
+                {
+                    self.filepath = Some(path);
+                    self.modified = false;
+                    self.written = true;
+                }
+                Ok(Some("Save complete".to_string()))
+            }
+            Err(why) => Err(["Save failed! reason", why.to_string().as_str()].join(": ")),
+        }
+    }
+
+    /// Give the buffer the global values of its options when it is first shown, as the
+    /// files of the command line are read before the rimrc sets them. `readonly` stays.
+    fn show(&mut self, defaults: &BufferOptions) {
+        if !self.shown {
+            self.shown = true;
+            let readonly = self.options.readonly;
+            copy_options(&mut self.options, defaults, OptionScope::Buffer);
+            self.options.readonly = readonly;
+        }
+    }
+
+    /// Name of the grammar highlighting the buffer, e.g. `rust`.
+    fn filetype(&self) -> Option<&'static str> {
+        self.highlighter
+            .as_ref()
+            .map(|highlighter| highlighter.grammar().name())
+    }
+
+    /// The URI language servers know the file of the buffer by.
+    fn uri(&self) -> Option<String> {
+        self.filepath
+            .as_ref()
+            .map(|filepath| path_to_uri(Path::new(filepath)))
+    }
+
+    /// Tell the language server about the buffer: open it on the server the first time,
+    /// then send what was edited or written since.
+    fn sync(&mut self, server: &mut Server) {
+        let uri = self.uri().unwrap_or_default();
+        match &mut self.synced {
+            None => {
+                let document = Json::object(vec![
+                    ("uri", uri.clone().into()),
+                    ("languageId", language_id(&server.filetype).into()),
+                    ("version", 1usize.into()),
+                    ("text", document_text(&self.text).into()),
+                ]);
+                server.notify(
+                    "textDocument/didOpen",
+                    Json::object(vec![("textDocument", document)]),
+                );
+                self.synced = Some((1, end_position(&self.text)));
+            }
+            Some((version, end)) if self.edited.is_some() => {
+                *version += 1;
+                let change = match server.sync {
+                    2 => content_change(&self.text, self.edited.unwrap(), end),
+                    _ => Json::object(vec![("text", document_text(&self.text).into())]),
+                };
+                if server.sync != 0 {
+                    let document = Json::object(vec![
+                        ("uri", uri.clone().into()),
+                        ("version", (*version).into()),
+                    ]);
+                    server.notify(
+                        "textDocument/didChange",
+                        Json::object(vec![
+                            ("textDocument", document),
+                            ("contentChanges", vec![change].into()),
+                        ]),
+                    );
+                }
+                *end = end_position(&self.text);
+            }
+            _ => (),
+        }
+        self.edited = None;
+        if self.written {
+            self.written = false;
+            let document = Json::object(vec![("uri", uri.into())]);
+            server.notify(
+                "textDocument/didSave",
+                Json::object(vec![("textDocument", document)]),
+            );
+        }
+    }
+
+    /// Lay out the part of the text visible in a window, highlighting lines which became
+    /// visible or were edited since the last time.
+    fn layout(&mut self, screen: &ScreenState) -> Layout {
+        if let Some(highlighter) = &mut self.highlighter {
+            highlighter.update(&self.text, screen.row_offset + screen.text_rows());
+        }
+        Layout::new(&self.text, screen, self.highlighter.as_ref())
+    }
+
+    /// Note that `line` was edited, so that it and the lines below are highlighted again.
+    fn invalidate(&mut self, line: usize) {
+        self.record_lines(line, 1, 1);
+        self.modified = true;
+        self.changed = true;
+        self.diff = None;
+        if let Some(highlighter) = &mut self.highlighter {
+            highlighter.invalidate(line);
+        }
+    }
+
+    /// Note that the `removed` lines from `line` were replaced with `added` lines, to send
+    /// only those to the language server.
+    fn record_lines(&mut self, line: usize, removed: usize, added: usize) {
+        let change = LineChange::new(line, removed, added);
+        self.edited = Some(match self.edited {
+            Some(edited) => edited.then(change),
+            None => change,
+        });
+    }
+}
+
+impl<T> Editor<T>
 where
-    R: BufRead,
-    W: Write,
+    T: Terminal,
 {
-    pub fn new(reader: R, writer: W, config: Config) -> Self
-    where
-        R: BufRead,
-        W: Write,
-    {
-        let text = fs::read_to_string(&config.filepath).unwrap();
-        let text: TextState = text.lines().map(|x| x.chars().collect()).collect();
-        let stdin = reader;
-        let mut stdout = writer;
-        write!(stdout, "{}", termion::clear::All).unwrap();
-
-        let buffer = Buffer {
-            screen: ScreenState {
-                terminal_size: termion::terminal_size().unwrap(),
-                ..Default::default()
+    pub fn new(mut terminal: T, config: Config) -> Self {
+        terminal.clear();
+        let (width, height) = terminal.size();
+        let screen = ScreenState {
+            size: (width, height),
+            ..Default::default()
+        };
+        let mut editor = Editor {
+            buffer: Buffer::new(1, None, text_from(""), None),
+            buffers: Vec::new(),
+            next_number: 2,
+            alternate: None,
+            window: Window {
+                id: 1,
+                buffer: 1,
+                screen,
             },
-            text,
+            windows: Vec::new(),
+            tree: WindowTree::Window(1),
+            next_window: 2,
+            tab_pages: Vec::new(),
+            tab_index: 0,
+            args: config.files,
+            arg_index: 0,
+            readonly: config.readonly,
+            diff: config.diff,
+            terminal,
+            renderer: Renderer::new(width, height),
+            pending: None,
+            count: None,
+            operator: None,
+            last_find: None,
+            show_targets: false,
+            jumps: JumpList::default(),
+            grammars: Registry::default(),
+            theme: Theme::default(),
+            options: GlobalOptions::default(),
+            buffer_options: BufferOptions::default(),
+            window_options: ScreenState::default(),
+            message: None,
+            more: false,
+            mode: Mode::Normal,
+            keymap: Keymap::default(),
+            typed: Vec::new(),
+            silent: false,
+            quit: false,
+            visual_start: (0, 0),
+            register: None,
+            last_click: None,
+            dragging: false,
+            lsp_commands: Vec::new(),
+            servers: Vec::new(),
+            completion: None,
+        };
+        if let Some(text) = config.stdin {
+            editor.buffer.text = text_from(&text);
+        } else if let Some((first, rest)) = editor.args.clone().split_first() {
+            let (buffer, message) = editor.read_buffer(1, first.clone());
+            editor.buffer = buffer;
+            editor.message = message;
+            for path in rest {
+                editor.add_buffer(path.clone());
+            }
+        }
+        editor.buffer.shown = true;
+        if editor.diff {
+            // The two files side by side, the first on the left.
+            let _ = editor.switch_to(2, true);
+            let _ = editor.split(true, None);
+            let _ = editor.switch_to(1, true);
+        }
+        editor
+    }
+
+    /// A buffer with the contents of the file. A file which does not exist yet gives an
+    /// empty buffer. Also returns the message to show when it is opened.
+    fn read_buffer(&self, number: usize, path: String) -> (Buffer, Option<String>) {
+        let (contents, message) = match fs::read_to_string(&path) {
+            Ok(contents) => (contents, None),
+            Err(why) if why.kind() == std::io::ErrorKind::NotFound => {
+                (String::new(), Some(format!("\"{}\" [New]", path)))
+            }
+            Err(why) => (String::new(), Some(format!("\"{}\" {}", path, why))),
+        };
+        let text = text_from(&contents);
+        let highlighter = self
+            .grammars
+            .find(Path::new(&path), &text[0])
+            .map(Highlighter::new);
+        let mut buffer = Buffer::new(number, Some(path), text, highlighter);
+        buffer.options.readonly = self.readonly;
+        (buffer, message)
+    }
+
+    /// Add a buffer for the file to the list without showing it. Returns its number and
+    /// the message to show when it is opened.
+    fn add_buffer(&mut self, path: String) -> (usize, Option<String>) {
+        let number = self.next_number;
+        self.next_number += 1;
+        let (buffer, message) = self.read_buffer(number, path);
+        self.buffers.push(buffer);
+        (number, message)
+    }
+
+    /// The current buffer followed by the others.
+    fn all_buffers(&self) -> impl Iterator<Item = &Buffer> {
+        std::iter::once(&self.buffer).chain(self.buffers.iter())
+    }
+
+    fn all_buffers_mut(&mut self) -> impl Iterator<Item = &mut Buffer> {
+        std::iter::once(&mut self.buffer).chain(self.buffers.iter_mut())
+    }
+
+    /// Numbers of all buffers in ascending order.
+    fn buffer_numbers(&self) -> Vec<usize> {
+        let mut numbers: Vec<usize> = self.all_buffers().map(|buffer| buffer.number).collect();
+        numbers.sort_unstable();
+        numbers
+    }
+
+    /// Show the buffer with `number` in the current window, keeping the current one in
+    /// the list. Leaving a modified buffer which no other window shows needs `force`.
+    fn switch_to(&mut self, number: usize, force: bool) -> Result<(), String> {
+        if number == self.buffer.number {
+            return Ok(());
+        }
+        let index = self
+            .buffers
+            .iter()
+            .position(|buffer| buffer.number == number)
+            .ok_or_else(|| format!("Buffer {} does not exist", number))?;
+        if self.buffer.modified && !force && !self.shown_elsewhere(self.buffer.number) {
+            return Err(NOT_WRITTEN.to_string());
+        }
+        self.buffer.cursor = self.cursor();
+        let buffer = self.buffers.remove(index);
+        let previous = std::mem::replace(&mut self.buffer, buffer);
+        self.buffer.show(&self.buffer_options);
+        self.alternate = Some(previous.number);
+        self.buffers.push(previous);
+        self.window.buffer = number;
+        self.restore_cursor();
+        Ok(())
+    }
+
+    /// Put the cursor of the current window where it was when a window last left the buffer.
+    fn restore_cursor(&mut self) {
+        let (line, column) = self.buffer.cursor;
+        let screen = &mut self.window.screen;
+        screen.cursor = Cursor::default();
+        screen.row_offset = 0;
+        screen.col_offset = 0;
+        self.go_to_line(line);
+        let line = std::cmp::min(line, self.buffer.text.len() - 1);
+        self.window.screen.cursor.x = std::cmp::min(column, self.buffer.text[line].len());
+    }
+
+    /// Whether a window other than the current one shows buffer `number`, in any tab page.
+    fn shown_elsewhere(&self, number: usize) -> bool {
+        self.windows
+            .iter()
+            .chain(self.tab_pages.iter().flat_map(|tab| tab.all_windows()))
+            .any(|window| window.buffer == number)
+    }
+
+    /// Make `window` the current window, with its buffer. Returns the window which was
+    /// current before.
+    fn enter(&mut self, window: Window) -> Window {
+        self.buffer.cursor = self.cursor();
+        if window.buffer != self.buffer.number {
+            let index = self
+                .buffers
+                .iter()
+                .position(|buffer| buffer.number == window.buffer)
+                .unwrap();
+            let buffer = self.buffers.remove(index);
+            let previous = std::mem::replace(&mut self.buffer, buffer);
+            self.buffer.show(&self.buffer_options);
+            self.buffers.push(previous);
+        }
+        std::mem::replace(&mut self.window, window)
+    }
+
+    /// Move the cursor to the window with `id`.
+    fn focus(&mut self, id: usize) {
+        if let Some(index) = self.windows.iter().position(|window| window.id == id) {
+            let window = self.windows.remove(index);
+            let previous = self.enter(window);
+            self.windows.push(previous);
+        }
+    }
+
+    /// The part of the terminal the windows divide, below the tab line if there is one.
+    fn window_area(&self) -> Rect {
+        let (width, height) = self.terminal.size();
+        let tab_line = if self.tab_pages.is_empty() { 0 } else { 1 };
+        // A single window reaches the last row, which its status line shares with the
+        // command line. Several windows have status lines of their own above it.
+        let command_line = if self.windows.is_empty() { 0 } else { 1 };
+        Rect {
+            x: 0,
+            y: tab_line,
+            width: width as usize,
+            height: (height as usize).saturating_sub(tab_line + command_line),
+        }
+    }
+
+    /// Fit the window tree to the terminal and give each window its size.
+    fn arrange(&mut self) {
+        let area = self.window_area();
+        self.tree.fit(area.width, area.height);
+        for (id, rect) in self.tree.layout(area) {
+            let window = if id == self.window.id {
+                &mut self.window
+            } else {
+                self.windows.iter_mut().find(|w| w.id == id).unwrap()
+            };
+            let buffer = std::iter::once(&self.buffer)
+                .chain(self.buffers.iter())
+                .find(|buffer| buffer.number == window.buffer)
+                .unwrap();
+            window.screen.size = (rect.width as u16, rect.height as u16);
+            window.screen.move_vert(&buffer.text, 0);
+        }
+    }
+
+    /// The screen of a new window showing what the current one does, with the global
+    /// values of the window options.
+    fn new_screen(&self) -> ScreenState {
+        let mut screen = self.window.screen.clone();
+        copy_options(&mut screen, &self.window_options, OptionScope::Window);
+        screen
+    }
+
+    /// Handle `:split` and `:vsplit`: show the buffer, or `path` if given, in a new window
+    /// above or left of the current one.
+    fn split(&mut self, vertical: bool, path: Option<&str>) -> Result<Option<String>, String> {
+        let area = self.window_area();
+        let rect = self
+            .tree
+            .layout(area)
+            .into_iter()
+            .find(|(id, _)| *id == self.window.id)
+            .unwrap()
+            .1;
+        let size = match (vertical, self.windows.is_empty()) {
+            (true, _) => rect.width,
+            // The command line gets a row of its own.
+            (false, true) => rect.height.saturating_sub(1),
+            (false, false) => rect.height,
+        };
+        let id = self.next_window;
+        if !self.tree.split(self.window.id, id, vertical, size) {
+            return Err("Not enough room".to_string());
+        }
+        self.next_window += 1;
+        let window = Window {
+            id,
+            buffer: self.buffer.number,
+            screen: self.new_screen(),
+        };
+        let previous = std::mem::replace(&mut self.window, window);
+        self.windows.push(previous);
+        self.arrange();
+        match path {
+            Some(path) => self.edit(path, true),
+            None => Ok(None),
+        }
+    }
+
+    /// Close the current window, for `:close` and `Ctrl-w c`. The window used before
+    /// becomes the current one.
+    fn close_window(&mut self) -> Result<Option<String>, String> {
+        let next = self
+            .windows
+            .pop()
+            .ok_or_else(|| "Cannot close last window".to_string())?;
+        self.tree.remove(self.window.id);
+        self.enter(next);
+        self.arrange();
+        Ok(None)
+    }
+
+    /// Handle `:only` and `Ctrl-w o`: close all windows but the current one.
+    fn only_window(&mut self) -> Result<Option<String>, String> {
+        if self.windows.is_empty() {
+            return Err("Already only one window".to_string());
+        }
+        self.windows.clear();
+        self.tree = WindowTree::Window(self.window.id);
+        self.arrange();
+        Ok(None)
+    }
+
+    /// The window next to the current one in the direction of `h`, `j`, `k` or `l`,
+    /// preferring the one beside the cursor.
+    fn neighbour(&self, direction: char) -> Option<usize> {
+        let rects = self.tree.layout(self.window_area());
+        let current = rects.iter().find(|(id, _)| *id == self.window.id)?.1;
+        let (x, y) = self.window.screen.cursor_position(&self.buffer.text);
+        let (x, y) = (current.x + x, current.y + y);
+        let distance = |start: usize, length: usize, position: usize| {
+            if position < start {
+                start - position
+            } else {
+                (position + 1).saturating_sub(start + length)
+            }
+        };
+        rects
+            .iter()
+            .filter(|(_, rect)| match direction {
+                'h' => rect.x + rect.width + 1 == current.x,
+                'l' => current.x + current.width + 1 == rect.x,
+                'k' => rect.y + rect.height == current.y,
+                _ => current.y + current.height == rect.y,
+            })
+            .min_by_key(|(_, rect)| match direction {
+                'h' | 'l' => distance(rect.y, rect.height, y),
+                _ => distance(rect.x, rect.width, x),
+            })
+            .map(|(id, _)| *id)
+    }
+
+    /// Handle the key typed after `Ctrl-w`.
+    fn window_command(&mut self, key: char) {
+        let result = match key {
+            's' | 'S' => self.split(false, None),
+            'v' => self.split(true, None),
+            'c' => self.close_window(),
+            'o' => self.only_window(),
+            'h' | 'j' | 'k' | 'l' => {
+                if let Some(id) = self.neighbour(key) {
+                    self.focus(id);
+                }
+                Ok(None)
+            }
+            'w' => {
+                let ids: Vec<usize> = self
+                    .tree
+                    .layout(self.window_area())
+                    .iter()
+                    .map(|(id, _)| *id)
+                    .collect();
+                let index = ids.iter().position(|&id| id == self.window.id).unwrap();
+                self.focus(ids[(index + 1) % ids.len()]);
+                Ok(None)
+            }
+            'p' => {
+                if let Some(id) = self.windows.last().map(|window| window.id) {
+                    self.focus(id);
+                }
+                Ok(None)
+            }
+            '=' => {
+                let area = self.window_area();
+                self.tree.equalize(area.width, area.height);
+                self.arrange();
+                Ok(None)
+            }
+            '+' | '-' | '>' | '<' => {
+                let vertical = key == '>' || key == '<';
+                let delta = if key == '+' || key == '>' { 1 } else { -1 };
+                self.tree.resize(self.window.id, vertical, delta);
+                self.arrange();
+                Ok(None)
+            }
+            _ => Ok(None),
+        };
+        if let Err(message) = result {
+            self.message = Some(message);
+        }
+    }
+
+    /// Handle `:quit`: close the current window, unless it shows a modified buffer no
+    /// other window shows and `force` is not set. Closing the last window quits.
+    fn quit_window(&mut self, force: bool) -> Result<Option<String>, String> {
+        if self.windows.is_empty() && self.tab_pages.is_empty() {
+            return self.quit_all(force);
+        }
+        if self.buffer.modified && !force && !self.shown_elsewhere(self.buffer.number) {
+            return Err(NOT_WRITTEN.to_string());
+        }
+        if self.windows.is_empty() {
+            self.close_tab()
+        } else {
+            self.close_window()
+        }
+    }
+
+    /// Make the tab page `tab` current. Returns the tab page which was current.
+    fn swap_tab(&mut self, tab: TabPage) -> TabPage {
+        let window = self.enter(tab.window);
+        TabPage {
+            window,
+            windows: std::mem::replace(&mut self.windows, tab.windows),
+            tree: std::mem::replace(&mut self.tree, tab.tree),
+        }
+    }
+
+    /// Go to the tab page at `index` among all of them.
+    fn go_to_tab(&mut self, index: usize) {
+        if index == self.tab_index || index > self.tab_pages.len() {
+            return;
+        }
+        let (position, insert) = if index < self.tab_index {
+            (index, self.tab_index - 1)
+        } else {
+            (index - 1, self.tab_index)
+        };
+        let tab = self.tab_pages.remove(position);
+        let current = self.swap_tab(tab);
+        self.tab_pages.insert(insert, current);
+        self.tab_index = index;
+        self.arrange();
+    }
+
+    /// Go to the tab page `offset` places after the current one, wrapping around,
+    /// for `:tabnext`, `:tabprevious`, `gt` and `gT`.
+    fn cycle_tabs(&mut self, offset: isize) {
+        let count = self.tab_pages.len() as isize + 1;
+        let index = (self.tab_index as isize + offset).rem_euclid(count);
+        self.go_to_tab(index as usize);
+    }
+
+    /// Handle `:tabnew`: open a tab page after the current one, with a window showing
+    /// `path`, or a new empty buffer.
+    fn new_tab(&mut self, path: Option<&str>) -> Result<Option<String>, String> {
+        let (number, message) = match path {
+            Some(path) => self.buffer_for(path),
+            None => (self.add_empty_buffer(), None),
+        };
+        let id = self.next_window;
+        self.next_window += 1;
+        let tab = TabPage {
+            window: Window {
+                id,
+                buffer: number,
+                screen: self.new_screen(),
+            },
+            windows: Vec::new(),
+            tree: WindowTree::Window(id),
+        };
+        let current = self.swap_tab(tab);
+        self.tab_pages.insert(self.tab_index, current);
+        self.tab_index += 1;
+        self.restore_cursor();
+        self.arrange();
+        Ok(message)
+    }
+
+    /// Handle `:tabclose`: close the current tab page with its windows. The tab page
+    /// after it becomes current, or the one before if it was the last.
+    fn close_tab(&mut self) -> Result<Option<String>, String> {
+        if self.tab_pages.is_empty() {
+            return Err("Cannot close last tab page".to_string());
+        }
+        let index = std::cmp::min(self.tab_index, self.tab_pages.len() - 1);
+        let tab = self.tab_pages.remove(index);
+        self.swap_tab(tab);
+        self.tab_index = index;
+        self.arrange();
+        Ok(None)
+    }
+
+    /// Labels of the tab line: the file name of the buffer in each tab page's current
+    /// window, after `+` when the buffer is modified.
+    fn tab_labels(&self) -> Vec<String> {
+        let (before, after) = self.tab_pages.split_at(self.tab_index);
+        before
+            .iter()
+            .map(|tab| &tab.window)
+            .chain(std::iter::once(&self.window))
+            .chain(after.iter().map(|tab| &tab.window))
+            .map(|window| {
+                let buffer = self
+                    .all_buffers()
+                    .find(|buffer| buffer.number == window.buffer)
+                    .unwrap();
+                let name = Path::new(buffer.name())
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| buffer.name().to_string());
+                format!(" {}{} ", if buffer.modified { "+ " } else { "" }, name)
+            })
+            .collect()
+    }
+
+    /// Whether the `mouse` option lets the mouse be used in `mode`.
+    fn mouse_enabled(&self, mode: &Mode) -> bool {
+        let flag = match mode {
+            Mode::Normal => 'n',
+            Mode::Visual | Mode::VisualLine => 'v',
+            Mode::Insert | Mode::Replace(_) => 'i',
+            Mode::Command(_) => 'c',
+        };
+        self.options.mouse.contains('a') || self.options.mouse.contains(flag)
+    }
+
+    /// Handle a mouse event in `mode`. Returns the mode to continue in.
+    fn mouse(&mut self, event: MouseEvent, mode: Mode) -> Mode {
+        let mode = match mode {
+            Mode::Command(_) => return mode,
+            // Backspace only puts back what was replaced where the cursor was.
+            Mode::Replace(_) => Mode::Replace(Vec::new()),
+            mode => mode,
+        };
+        match event {
+            MouseEvent::Press(MouseButton::Left, x, y) => {
+                let clicks = match self.last_click {
+                    Some((time, last_x, last_y, count))
+                        if (last_x, last_y) == (x, y) && time.elapsed() < DOUBLE_CLICK =>
+                    {
+                        count % 3 + 1
+                    }
+                    _ => 1,
+                };
+                self.last_click = Some((Instant::now(), x, y, clicks));
+                let insert = matches!(mode, Mode::Insert | Mode::Replace(_));
+                self.dragging = self.click(x, y, insert && clicks == 1);
+                if !self.dragging {
+                    return if insert { mode } else { Mode::Normal };
+                }
+                let (line, column) = self.cursor();
+                match clicks {
+                    1 if insert => mode,
+                    1 => Mode::Normal,
+                    2 => {
+                        let word = word_at(&self.buffer.text[line], column);
+                        self.visual_start = (line, word.start);
+                        self.window.screen.cursor.x = word.end.saturating_sub(1);
+                        Mode::Visual
+                    }
+                    _ => {
+                        self.visual_start = (line, column);
+                        Mode::VisualLine
+                    }
+                }
+            }
+            MouseEvent::Press(button @ (MouseButton::WheelUp | MouseButton::WheelDown), x, y) => {
+                // The wheel scrolls the window under the pointer, but a selection stays
+                // in its window.
+                if let Some((id, _)) = self.window_at(
+                    (x as usize).saturating_sub(1),
+                    (y as usize).saturating_sub(1),
+                ) {
+                    if !matches!(mode, Mode::Visual | Mode::VisualLine) {
+                        self.focus(id);
+                    }
+                }
+                let lines = if button == MouseButton::WheelUp {
+                    -WHEEL_LINES
+                } else {
+                    WHEEL_LINES
+                };
+                let rows = self.window.screen.scroll_lines(&self.buffer.text, lines);
+                self.scroll_view(rows);
+                mode
+            }
+            MouseEvent::Hold(x, y) if self.dragging => {
+                // A click followed by dragging does not start a double click.
+                self.last_click = None;
+                let rect = self
+                    .tree
+                    .layout(self.window_area())
+                    .into_iter()
+                    .find(|(id, _)| *id == self.window.id)
+                    .unwrap()
+                    .1;
+                // The selection stays in the window, on the rows showing text.
+                let rows = self.window.screen.text_rows();
+                if rect.width == 0 || rows == 0 {
+                    return mode;
+                }
+                let x = (x as usize).saturating_sub(1);
+                let x = x.clamp(rect.x, rect.x + rect.width - 1);
+                let y = (y as usize).saturating_sub(1);
+                let y = y.clamp(rect.y, rect.y + rows - 1);
+                let (line, column) =
+                    self.window
+                        .screen
+                        .position_at(&self.buffer.text, x - rect.x, y - rect.y);
+                let mode = match mode {
+                    Mode::Visual | Mode::VisualLine => mode,
+                    _ => {
+                        let (start_line, start_column) = self.cursor();
+                        let length = self.buffer.text[start_line].len();
+                        self.visual_start = (
+                            start_line,
+                            std::cmp::min(start_column, length.saturating_sub(1)),
+                        );
+                        Mode::Visual
+                    }
+                };
+                let length = self.buffer.text[line].len();
+                self.window.screen.cursor = Cursor {
+                    x: std::cmp::min(column, length.saturating_sub(1)),
+                    y: line - self.window.screen.row_offset,
+                };
+                mode
+            }
+            MouseEvent::Release(_, _) => {
+                self.dragging = false;
+                mode
+            }
+            _ => mode,
+        }
+    }
+
+    /// The window drawn at the zero-based position, and where it is.
+    fn window_at(&self, x: usize, y: usize) -> Option<(usize, Rect)> {
+        self.tree
+            .layout(self.window_area())
+            .into_iter()
+            .find(|(_, rect)| {
+                (rect.x..rect.x + rect.width).contains(&x)
+                    && (rect.y..rect.y + rect.height).contains(&y)
+            })
+    }
+
+    /// Handle a click: on the tab line it goes to the tab page, in a window it moves the
+    /// cursor to the character clicked, or past the end of the line when `past_end`, as
+    /// in Insert mode. Positions are one-based, as termion reports them, though it may
+    /// report zero for the first column or row.
+    /// Returns whether the cursor was put on the text of a window.
+    fn click(&mut self, x: u16, y: u16, past_end: bool) -> bool {
+        let (x, y) = (
+            (x as usize).saturating_sub(1),
+            (y as usize).saturating_sub(1),
+        );
+        if !self.tab_pages.is_empty() && y == 0 {
+            let mut end = 0;
+            for (i, label) in self.tab_labels().iter().enumerate() {
+                end += label.chars().count();
+                if x < end {
+                    self.go_to_tab(i);
+                    break;
+                }
+            }
+            return false;
+        }
+        let (id, rect) = match self.window_at(x, y) {
+            Some(clicked) => clicked,
+            None => return false,
+        };
+        self.focus(id);
+        let screen = &mut self.window.screen;
+        if screen.text_rows() == 0 {
+            return false;
+        }
+        // A click on the status line goes to the last row of text.
+        let y = std::cmp::min(y - rect.y, screen.text_rows() - 1);
+        let (line, column) = screen.position_at(&self.buffer.text, x - rect.x, y);
+        let length = self.buffer.text[line].len();
+        screen.cursor = Cursor {
+            x: if past_end {
+                column
+            } else {
+                std::cmp::min(column, length.saturating_sub(1))
+            },
+            y: line - screen.row_offset,
+        };
+        true
+    }
+
+    /// The start and the end of the selection of Visual mode, in order.
+    fn selection(&self) -> ((usize, usize), (usize, usize)) {
+        let cursor = self.cursor();
+        if cursor < self.visual_start {
+            (cursor, self.visual_start)
+        } else {
+            (self.visual_start, cursor)
+        }
+    }
+
+    /// Take the selected text into the register, removing it from the buffer when `delete`
+    /// is set, and put the cursor at the start of the selection.
+    fn take_selection(&mut self, linewise: bool, delete: bool) {
+        let (start, end) = self.selection();
+        let text = &self.buffer.text;
+        // A selection reaching past the end of a line takes its line break.
+        let end = if linewise {
+            end
+        } else if end.1 < text[end.0].len() {
+            (end.0, end.1 + 1)
+        } else if end.0 + 1 < text.len() {
+            (end.0 + 1, 0)
+        } else {
+            end
+        };
+        self.take(start, end, linewise, delete);
+    }
+
+    /// Take the text from `start` up to `end` into the register, or the lines from the
+    /// line of `start` to that of `end` when `linewise` is set. It is removed from the
+    /// buffer when `delete` is set, and the cursor goes to its start.
+    fn take(&mut self, start: (usize, usize), end: (usize, usize), linewise: bool, delete: bool) {
+        let text = &mut self.buffer.text;
+        let lines = text.len();
+        let taken = if linewise {
+            let lines = text[start.0..=end.0].to_vec();
+            if delete {
+                text.drain(start.0..=end.0);
+                if text.is_empty() {
+                    text.push(Vec::new());
+                }
+            }
+            lines
+        } else if delete {
+            delete_range(text, start, end)
+        } else {
+            copy_range(text, start, end)
+        };
+        let removed = end.0 - start.0 + 1;
+        match (delete, linewise) {
+            (true, true) => {
+                // Deleting every line leaves one empty line.
+                let left = if removed == lines { 1 } else { 0 };
+                self.shift_marks(start.0, removed, left);
+            }
+            (true, false) => self.shift_marks(start.0, removed, 1),
+            _ => (),
+        }
+        let last = if delete {
+            start
+        } else if linewise {
+            (end.0, 0)
+        } else {
+            (end.0, end.1.saturating_sub(1))
+        };
+        self.buffer.marks.set('[', start);
+        self.buffer.marks.set(']', last);
+        self.register = Some(Register {
+            text: taken,
+            linewise,
+        });
+        let line = std::cmp::min(start.0, self.buffer.text.len() - 1);
+        if delete {
+            self.buffer.invalidate(line);
+        }
+        self.move_cursor_to(line, if linewise { 0 } else { start.1 });
+    }
+
+    /// Move the marks and the jumps of the current buffer along with the `removed` lines
+    /// from `line` on replaced with `added` lines.
+    fn shift_marks(&mut self, line: usize, removed: usize, added: usize) {
+        self.buffer.record_lines(line, removed, added);
+        self.buffer.marks.shift(line, removed, added);
+        self.jumps.shift(self.buffer.number, line, removed, added);
+    }
+
+    /// Add the cursor to the change list when the text was changed.
+    fn note_change(&mut self) {
+        if std::mem::take(&mut self.buffer.changed) {
+            let cursor = self.cursor();
+            self.buffer.marks.record_change(cursor);
+        }
+    }
+
+    /// Remember the cursor in the jump list and as the `'` mark before jumping.
+    fn remember_jump(&mut self) {
+        let cursor = self.cursor();
+        self.jumps.push(self.buffer.number, cursor);
+        self.buffer.marks.set('\'', cursor);
+    }
+
+    /// Handle `m{a-zA-Z}`. A mark of a capital letter is set in one buffer at a time.
+    fn set_mark(&mut self, name: char) {
+        let name = if name == '`' { '\'' } else { name };
+        if name.is_ascii_uppercase() {
+            for buffer in self.all_buffers_mut() {
+                buffer.marks.remove(name);
+            }
+        }
+        let cursor = self.cursor();
+        self.buffer.marks.set(name, cursor);
+    }
+
+    /// Handle `'{mark}` and `` `{mark} ``: jump to the line of the mark or to the mark
+    /// itself when `exact`, or act with the pending operator on the text up to it. A
+    /// mark of a capital letter may take the cursor to another buffer.
+    fn go_to_mark(&mut self, name: char, exact: bool) -> Mode {
+        let name = if name == '`' { '\'' } else { name };
+        let other = self
+            .buffers
+            .iter()
+            .find(|buffer| name.is_ascii_uppercase() && buffer.marks.get(name).is_some())
+            .map(|buffer| buffer.number);
+        if let (Some(number), None) = (other, self.operator) {
+            self.remember_jump();
+            if let Err(message) = self.switch_to(number, false) {
+                self.message = Some(message);
+                return Mode::Normal;
+            }
+        }
+        let (line, column) = match self.buffer.marks.get(name) {
+            Some(position) => position,
+            None => {
+                self.message = Some("Mark not set".to_string());
+                self.operator = None;
+                return Mode::Normal;
+            }
+        };
+        if other.is_some() {
+            self.move_cursor_to(line, column);
+            if !exact {
+                self.go_to_line(line);
+            }
+            return Mode::Normal;
+        }
+        let motion = if exact {
+            Motion::GoTo((line, column))
+        } else {
+            Motion::GoToLine(line)
+        };
+        self.motion(motion, None)
+    }
+
+    /// Handle `Ctrl-o`, going `count` entries back in the jump list, and `Ctrl-i` going
+    /// forth when it is negative.
+    fn go_to_jump(&mut self, count: i32) {
+        let current = (self.buffer.number, self.cursor());
+        let (number, (line, column)) = match self.jumps.go(count, current) {
+            Some(jump) => jump,
+            None => return,
+        };
+        if let Err(message) = self.switch_to(number, false) {
+            self.message = Some(message);
+            return;
+        }
+        self.move_cursor_to(line, column);
+    }
+
+    /// Handle `g;`, going `count` entries back in the change list, and `g,` going forth
+    /// when it is negative.
+    fn go_to_change(&mut self, count: i32) {
+        match self.buffer.marks.go_to_change(count) {
+            Ok((line, column)) => self.move_cursor_to(line, column),
+            Err(message) => self.message = Some(message),
+        }
+    }
+
+    /// Handle `p` and `P`: put the text of the register after or before the cursor.
+    /// Lines taken whole go below or above the line of the cursor.
+    fn put(&mut self, after: bool) {
+        let (text, linewise) = match &self.register {
+            Some(register) => (register.text.clone(), register.linewise),
+            None => {
+                self.message = Some("Nothing in register \"".to_string());
+                return;
+            }
+        };
+        let (line, column) = self.cursor();
+        if linewise {
+            let at = if after { line + 1 } else { line };
+            let added = text.len();
+            self.buffer.text.splice(at..at, text);
+            self.shift_marks(at, 0, added);
+            self.buffer.marks.set('[', (at, 0));
+            self.buffer.marks.set(']', (at + added - 1, 0));
+            self.buffer.invalidate(line);
+            self.move_cursor_to(at, 0);
+        } else {
+            let length = self.buffer.text[line].len();
+            let column = std::cmp::min(if after { column + 1 } else { column }, length);
+            let end = insert_text(&mut self.buffer.text, (line, column), &text);
+            self.shift_marks(line, 1, text.len());
+            self.buffer.marks.set('[', (line, column));
+            self.buffer.marks.set(']', (end.0, end.1.saturating_sub(1)));
+            self.buffer.invalidate(line);
+            // The cursor goes to the last character put, or to the first of several lines.
+            if text.len() == 1 {
+                self.move_cursor_to(line, end.1.saturating_sub(1));
+            } else {
+                self.move_cursor_to(line, column);
+            }
+        }
+    }
+
+    /// Handle pasted text all at once rather than key by key. In Insert mode it goes in
+    /// at the cursor, which ends up after it. In Normal mode it goes in before the
+    /// cursor as with `P`, and in Visual mode it replaces the selection. On the command
+    /// line, line breaks become spaces.
+    fn paste(&mut self, text: &str) {
+        self.pending = None;
+        self.count = None;
+        self.operator = None;
+        self.completion = None;
+        self.more = false;
+        let mode = std::mem::replace(&mut self.mode, Mode::Normal);
+        if let Mode::Command(mut command) = mode {
+            command.push_str(&text.replace('\n', " "));
+            self.mode = Mode::Command(command);
+            return;
+        }
+        if let Mode::Visual | Mode::VisualLine = mode {
+            let linewise = matches!(mode, Mode::VisualLine);
+            let (start, end) = self.selection();
+            let remaining = self.buffer.text.len() - (end.0 - start.0 + 1);
+            self.take_selection(linewise, true);
+            // The pasted text takes the place of the lines.
+            if linewise && remaining > 0 {
+                self.buffer.text.insert(start.0, Vec::new());
+                self.shift_marks(start.0, 0, 1);
+                self.move_cursor_to(start.0, 0);
+            }
+        }
+        let lines: TextState = text
+            .split('\n')
+            .map(|line| line.chars().collect())
+            .collect();
+        let (line, column) = self.cursor();
+        let column = std::cmp::min(column, self.buffer.text[line].len());
+        let end = insert_text(&mut self.buffer.text, (line, column), &lines);
+        self.shift_marks(line, 1, lines.len());
+        self.buffer.invalidate(line);
+        self.note_change();
+        if let Mode::Insert | Mode::Replace(_) = mode {
+            self.move_cursor_to(end.0, 0);
+            self.window.screen.cursor.x = end.1;
+            self.mode = match mode {
+                Mode::Replace(_) => Mode::Replace(Vec::new()),
+                _ => Mode::Insert,
+            };
+        } else {
+            self.move_cursor_to(end.0, end.1.saturating_sub(1));
+        }
+    }
+
+    /// Split the line at the cursor and go to the start of the new line, as Enter does in
+    /// Insert and Replace modes.
+    fn break_line(&mut self) {
+        let (line, column) = self.cursor();
+        let rest = self.buffer.text[line].split_off(column);
+        self.buffer.text.insert(line + 1, rest);
+        self.shift_marks(line, 1, 2);
+        self.buffer.invalidate(line);
+        self.window.screen.move_vert(&self.buffer.text, 1);
+        self.window.screen.cursor.x = 0;
+    }
+
+    /// Handle a key in Replace mode, where `replaced` holds what was typed over. Returns
+    /// the mode to continue in.
+    fn replace_key(&mut self, key: Key, mut replaced: Vec<Option<char>>) -> Mode {
+        let (line, column) = self.cursor();
+        match key {
+            Key::Esc => {
+                self.buffer.marks.set('^', (line, column));
+                self.window.screen.move_horiz(&self.buffer.text, 0);
+                return Mode::Normal;
+            }
+            Key::Char('\n') => {
+                self.break_line();
+                replaced.clear();
+            }
+            Key::Char(ch) => {
+                let chars = &mut self.buffer.text[line];
+                if column < chars.len() {
+                    replaced.push(Some(std::mem::replace(&mut chars[column], ch)));
+                } else {
+                    chars.push(ch);
+                    replaced.push(None);
+                }
+                self.buffer.invalidate(line);
+                self.window.screen.cursor.x += 1;
+            }
+            // Backspace puts back what was typed over, or only moves left before that.
+            Key::Backspace | Key::Ctrl('h') if column > 0 => {
+                if let Some(original) = replaced.pop() {
+                    let chars = &mut self.buffer.text[line];
+                    match original {
+                        Some(c) => chars[column - 1] = c,
+                        None => {
+                            chars.remove(column - 1);
+                        }
+                    }
+                    self.buffer.invalidate(line);
+                }
+                self.window.screen.cursor.x -= 1;
+            }
+            _ => (),
+        }
+        Mode::Replace(replaced)
+    }
+
+    /// Handle `r{char}`: put `ch` in place of `count` characters from the cursor, or one
+    /// line break for Enter. Nothing is changed when the line is too short.
+    fn replace_chars(&mut self, ch: char, count: usize) {
+        let (line, column) = self.cursor();
+        let end = column + count;
+        if end > self.buffer.text[line].len() {
+            return;
+        }
+        if ch == '\n' {
+            let rest = self.buffer.text[line].split_off(end);
+            self.buffer.text[line].truncate(column);
+            self.buffer.text.insert(line + 1, rest);
+            self.shift_marks(line, 1, 2);
+            self.buffer.invalidate(line);
+            self.move_cursor_to(line + 1, 0);
+        } else {
+            self.buffer.text[line][column..end].fill(ch);
+            self.buffer.invalidate(line);
+            self.window.screen.cursor.x = end - 1;
+        }
+    }
+
+    /// Handle `~`: switch the case of `count` characters from the cursor and move past
+    /// them, stopping at the last character of the line.
+    fn switch_case(&mut self, count: usize) {
+        let (line, column) = self.cursor();
+        let chars = &mut self.buffer.text[line];
+        if column >= chars.len() {
+            return;
+        }
+        let end = std::cmp::min(column + count, chars.len());
+        for c in &mut chars[column..end] {
+            *c = switch_case(*c);
+        }
+        self.buffer.invalidate(line);
+        self.move_cursor_to(line, end);
+    }
+
+    /// Handle `J`, or `gJ` unless `spaces`: join `count` lines from the line of the
+    /// cursor, and at least two.
+    fn join(&mut self, count: usize, spaces: bool) {
+        let line = self.cursor().0;
+        let lines = self.buffer.text.len();
+        if line + 1 >= lines {
+            return;
+        }
+        let column = join_lines(&mut self.buffer.text, line, std::cmp::max(count, 2), spaces);
+        let joined = lines - self.buffer.text.len();
+        self.shift_marks(line, joined + 1, 1);
+        self.buffer.invalidate(line);
+        self.move_cursor_to(line, column);
+    }
+
+    /// Handle `Ctrl-a` and `Ctrl-x`: add `delta` to the number under or after the cursor
+    /// and go to its last character.
+    fn increment(&mut self, delta: i64) {
+        let (line, column) = self.cursor();
+        if let Some(end) = add_to_number(&mut self.buffer.text[line], column, delta) {
+            self.buffer.invalidate(line);
+            self.window.screen.cursor.x = end;
+        }
+    }
+
+    /// Move the cursor to the line and column, scrolling as needed. The column is
+    /// limited to the last character of the line.
+    fn move_cursor_to(&mut self, line: usize, column: usize) {
+        let current = self.cursor().0;
+        let rows = self
+            .window
+            .screen
+            .move_vert(&self.buffer.text, line as i32 - current as i32);
+        self.scroll_view(rows);
+        let length = self.buffer.text[self.cursor().0].len();
+        self.window.screen.cursor.x = std::cmp::min(column, length.saturating_sub(1));
+    }
+
+    /// Move the cursor with the motion `count` times, or act with the pending operator
+    /// on the text it moves over. Returns the mode to continue in.
+    fn motion(&mut self, motion: Motion, count: Option<usize>) -> Mode {
+        if let Some((operator, before)) = self.operator.take() {
+            return self.operate(operator, motion, before * count.unwrap_or(1));
+        }
+        let from = self.cursor();
+        let target = motion.target(
+            &self.motion_context(motion == Motion::MatchPair),
+            from,
+            count.unwrap_or(1),
+            None,
+        );
+        if let Some(((line, column), _)) = target {
+            if motion.is_jump() {
+                self.remember_jump();
+            }
+            self.move_cursor_to(line, column);
+        }
+        self.show_targets =
+            matches!(motion, Motion::Find(..)) && self.options.charsearch.contains('h');
+        Mode::Normal
+    }
+
+    /// What motions and text objects need to know of the current buffer. The whole text
+    /// is highlighted first when `highlight` is set, for `%`.
+    fn motion_context(&mut self, highlight: bool) -> Context<'_> {
+        let buffer = &mut self.buffer;
+        if let (true, Some(highlighter)) = (highlight, &mut buffer.highlighter) {
+            highlighter.update(&buffer.text, buffer.text.len());
+        }
+        Context {
+            text: &buffer.text,
+            keyword: Keyword::parse(&buffer.options.iskeyword).unwrap_or_default(),
+            highlighter: buffer.highlighter.as_ref(),
+        }
+    }
+
+    /// The motion of a key which needs no other key, given the count typed before it.
+    /// This includes `;` and `,` which repeat the last `f`, `F`, `t` or `T`, the latter
+    /// in the other direction, and `G`, `H`, `M`, `L` and `{count}%` which go to a line
+    /// depending on the count. `H` and `L` stay `scrolloff` lines away from the edges.
+    fn motion_for_key(&self, key: char, count: Option<usize>) -> Option<Motion> {
+        let lines = self.buffer.text.len();
+        let (first, last) = self.window.screen.shown_lines(&self.buffer.text);
+        let (top, bottom) = self.window.screen.cursor_lines(&self.buffer.text);
+        let offset = count.unwrap_or(1) - 1;
+        match key {
+            ';' | ',' => {
+                let mut find = self.last_find?;
+                find.forward ^= key == ',';
+                Some(Motion::Find(find, true))
+            }
+            'G' => Some(Motion::GoToLine(count.map_or(lines - 1, |count| count - 1))),
+            'H' => Some(Motion::GoToLine(std::cmp::min(
+                std::cmp::max(first + offset, top),
+                bottom,
+            ))),
+            'M' => Some(Motion::GoToLine((first + last) / 2)),
+            'L' => Some(Motion::GoToLine(std::cmp::max(
+                std::cmp::min(last.saturating_sub(offset), bottom),
+                top,
+            ))),
+            '%' => match count {
+                Some(percent) if percent > 100 => None,
+                Some(percent) => Some(Motion::GoToLine((percent * lines).div_ceil(100) - 1)),
+                None => Some(Motion::MatchPair),
+            },
+            _ => Motion::from_key(key),
+        }
+    }
+
+    /// Handle the key after an operator: a motion, or the operator again for the line of
+    /// the cursor. Other keys cancel the operator.
+    fn operator_key(&mut self, key: Key, count: Option<usize>) -> Mode {
+        let motion = match key {
+            Key::Char('g' | 'f' | 'F' | 't' | 'T' | 'i' | 'a' | '\'' | '`') => {
+                self.pending = Some(key);
+                self.count = count;
+                return Mode::Normal;
+            }
+            Key::Char(ch) if Some(ch) == self.operator.map(|(operator, _)| operator) => {
+                Some(Motion::Lines)
+            }
+            Key::Char(ch) => self.motion_for_key(ch, count),
+            _ => None,
+        };
+        match motion {
+            Some(motion) => self.motion(motion, count),
+            None => {
+                self.operator = None;
+                Mode::Normal
+            }
+        }
+    }
+
+    /// Delete (`d`), change (`c`) or yank (`y`) the text the motion moves over `count`
+    /// times, putting it in the register. Returns the mode to continue in.
+    fn operate(&mut self, operator: char, motion: Motion, count: usize) -> Mode {
+        let from = self.cursor();
+        let context = self.motion_context(motion == Motion::MatchPair);
+        let target = motion.target(&context, from, count, Some(operator));
+        let (to, kind) = match target {
+            Some(target) => target,
+            None => return Mode::Normal,
+        };
+        let text = &self.buffer.text;
+        let (start, mut end) = if to < from { (to, from) } else { (from, to) };
+        let mut linewise = kind == MotionKind::Linewise;
+        match kind {
+            MotionKind::Inclusive => end.1 = std::cmp::min(end.1 + 1, text[end.0].len()),
+            // An exclusive motion to the start of a line stops at the end of the line
+            // before, and takes whole lines when it starts in the indent, as with `dw` on
+            // an empty line.
+            MotionKind::Exclusive if end.1 == 0 && end.0 > start.0 => {
+                end.0 -= 1;
+                if text[start.0][..start.1].iter().all(|c| c.is_whitespace()) {
+                    linewise = true;
+                } else {
+                    end.1 = text[end.0].len();
+                }
+            }
+            _ => (),
+        }
+        let selection = Selection {
+            start,
+            end,
+            linewise,
+        };
+        self.operate_on(operator, selection)
+    }
+
+    /// Apply the operator to the text object at the cursor, as in `diw` or `ca(`.
+    fn operate_on_object(
+        &mut self,
+        operator: char,
+        object: TextObject,
+        count: usize,
+        around: bool,
+    ) -> Mode {
+        let cursor = self.cursor();
+        match object.select(&self.motion_context(false), (cursor, cursor), count, around) {
+            Some(selection) => self.operate_on(operator, selection),
+            None => Mode::Normal,
+        }
+    }
+
+    /// Delete, change or yank the selected text. Returns the mode to continue in.
+    fn operate_on(&mut self, operator: char, selection: Selection) -> Mode {
+        let Selection {
+            start,
+            end,
+            linewise,
+        } = selection;
+        let from = self.cursor();
+        let lines = self.buffer.text.len();
+        self.take(start, end, linewise, operator != 'y');
+        match operator {
+            'c' if linewise => {
+                // The lines are changed to one empty line.
+                if end.0 - start.0 + 1 < lines {
+                    self.buffer.text.insert(start.0, Vec::new());
+                    self.shift_marks(start.0, 0, 1);
+                }
+                self.move_cursor_to(start.0, 0);
+                Mode::Insert
+            }
+            'c' => {
+                self.window.screen.cursor.x = start.1;
+                Mode::Insert
+            }
+            'd' if linewise => {
+                let line = &self.buffer.text[self.cursor().0];
+                let indent = line.iter().take_while(|c| c.is_whitespace()).count();
+                self.window.screen.cursor.x = std::cmp::min(indent, line.len().saturating_sub(1));
+                Mode::Normal
+            }
+            'y' if linewise => {
+                self.move_cursor_to(start.0, from.1);
+                Mode::Normal
+            }
+            _ => Mode::Normal,
+        }
+    }
+
+    /// Handle a key in Visual mode, which is `Mode::Visual` or `Mode::VisualLine`.
+    /// Returns the mode to continue in.
+    fn visual_key(&mut self, key: Key, mode: Mode) -> Mode {
+        let linewise = matches!(mode, Mode::VisualLine);
+        let length = self.buffer.text[self.cursor().0].len();
+        if let Some(Key::Char(kind)) = self.pending.take() {
+            return match key {
+                Key::Char(ch) => match TextObject::from_key(ch) {
+                    Some(object) => self.select_object(object, kind == 'a', mode),
+                    None => mode,
+                },
+                _ => mode,
+            };
+        }
+        match key {
+            Key::Esc | Key::Ctrl('c') => Mode::Normal,
+            Key::Char('i' | 'a') => {
+                self.pending = Some(key);
+                mode
+            }
+            Key::Char('h') => {
+                self.window.screen.move_horiz(&self.buffer.text, -1);
+                mode
+            }
+            Key::Char('l') => {
+                let x = self.window.screen.cursor.x + 1;
+                self.window.screen.cursor.x = std::cmp::min(x, length.saturating_sub(1));
+                mode
+            }
+            Key::Char('j') => {
+                let rows = self.window.screen.move_vert(&self.buffer.text, 1);
+                self.scroll_view(rows);
+                mode
+            }
+            Key::Char('k') => {
+                let rows = self.window.screen.move_vert(&self.buffer.text, -1);
+                self.scroll_view(rows);
+                mode
+            }
+            Key::Char('0') => {
+                self.window.screen.cursor.x = 0;
+                mode
+            }
+            Key::Char('$') => {
+                self.window.screen.cursor.x = length.saturating_sub(1);
+                mode
+            }
+            Key::Char(ch) if "wWbBeEGHML{}()%;,".contains(ch) => {
+                if let Some(motion) = self.motion_for_key(ch, None) {
+                    self.motion(motion, None);
+                }
+                mode
+            }
+            // Go to the other end of the selection.
+            Key::Char('o') => {
+                let (line, column) = self.visual_start;
+                self.visual_start = self.cursor();
+                self.move_cursor_to(line, column);
+                mode
+            }
+            Key::Char('v') if linewise => Mode::Visual,
+            Key::Char('V') if !linewise => Mode::VisualLine,
+            Key::Char('v') | Key::Char('V') => Mode::Normal,
+            Key::Char('y') => {
+                self.take_selection(linewise, false);
+                Mode::Normal
+            }
+            Key::Char('d') | Key::Char('x') => {
+                self.take_selection(linewise, true);
+                Mode::Normal
+            }
+            Key::Char('~') => {
+                let (start, end) = self.selection();
+                for line in start.0..=end.0 {
+                    let chars = &mut self.buffer.text[line];
+                    let from = if linewise || line > start.0 {
+                        0
+                    } else {
+                        start.1
+                    };
+                    let to = if linewise || line < end.0 {
+                        chars.len()
+                    } else {
+                        std::cmp::min(end.1 + 1, chars.len())
+                    };
+                    for c in chars.iter_mut().take(to).skip(from) {
+                        *c = switch_case(*c);
+                    }
+                    self.buffer.invalidate(line);
+                }
+                self.move_cursor_to(start.0, if linewise { 0 } else { start.1 });
+                Mode::Normal
+            }
+            Key::Char('J') => {
+                let (start, end) = self.selection();
+                self.move_cursor_to(start.0, start.1);
+                self.join(end.0 - start.0 + 1, true);
+                Mode::Normal
+            }
+            Key::Char(':') => Mode::Command(String::new()),
+            _ => mode,
+        }
+    }
+
+    /// Select the text object for the selection of Visual mode, switching to linewise or
+    /// characterwise Visual mode as the object is. Stays in `mode` when there is none.
+    fn select_object(&mut self, object: TextObject, around: bool, mode: Mode) -> Mode {
+        let range = self.selection();
+        let selection = match object.select(&self.motion_context(false), range, 1, around) {
+            Some(selection) if selection.start != selection.end || selection.linewise => selection,
+            _ => return mode,
+        };
+        let text = &self.buffer.text;
+        // The selection of Visual mode takes the character under the cursor.
+        let last = if selection.linewise || selection.end.1 > 0 {
+            (selection.end.0, selection.end.1.saturating_sub(1))
+        } else {
+            (selection.end.0 - 1, text[selection.end.0 - 1].len())
+        };
+        self.visual_start = selection.start;
+        self.move_cursor_to(last.0, last.1);
+        if selection.linewise {
+            Mode::VisualLine
+        } else {
+            Mode::Visual
+        }
+    }
+
+    /// Show the completion menu for the text before the cursor and put the first
+    /// candidate in place of it, or the last one if `backward`. The candidates come from
+    /// the source `key` stands for, typed after `Ctrl-x` or alone: the words of the
+    /// buffers for `n` and `p`, file names for `f`, lines for `l` and the language
+    /// server for `o`.
+    fn complete(&mut self, key: char, backward: bool) {
+        let (line, column) = self.cursor();
+        let offered = if key == 'o' {
+            match self.lsp_completion() {
+                Ok(items) => items,
+                Err(message) => {
+                    self.message = Some(message);
+                    return;
+                }
+            }
+        } else {
+            Vec::new()
+        };
+        let keyword = Keyword::parse(&self.buffer.options.iskeyword).unwrap_or_default();
+        let texts: Vec<&TextState> = self.all_buffers().map(|buffer| &buffer.text).collect();
+        let (words, lines, items);
+        let source: &dyn Source = match key {
+            'f' => &Paths,
+            'l' => {
+                lines = Lines { texts, line };
+                &lines
+            }
+            'o' => {
+                items = Items {
+                    candidates: offered,
+                    keyword,
+                };
+                &items
+            }
+            _ => {
+                words = Words {
+                    texts,
+                    cursor: (line, column),
+                    keyword,
+                };
+                &words
+            }
+        };
+        match Completion::new(source, &self.buffer.text, line, column) {
+            Some(mut completion) => {
+                completion.select(if backward { -1 } else { 1 });
+                self.completion = Some(completion);
+                self.put_completion();
+            }
+            None => self.message = Some("Pattern not found".to_string()),
+        }
+    }
+
+    /// The candidates the language server offers at the cursor, for `Ctrl-x Ctrl-o`.
+    fn lsp_completion(&mut self) -> Result<Vec<Candidate>, String> {
+        let params = self.position_params(vec![]);
+        let result = self.lsp_request("textDocument/completion", params)?;
+        Ok(completion_items(&result))
+    }
+
+    /// Put the text chosen in the completion menu in place of the text being completed,
+    /// which ends at the cursor.
+    fn put_completion(&mut self) {
+        let completion = match &self.completion {
+            Some(completion) => completion,
+            None => return,
+        };
+        let (line, column) = self.cursor();
+        let chars: Vec<char> = completion.text().chars().collect();
+        let start = completion.start;
+        self.buffer.text[line].splice(start..column, chars.iter().copied());
+        self.buffer.invalidate(line);
+        self.window.screen.cursor.x = start + chars.len();
+    }
+
+    /// Handle a key while the completion menu is shown. Returns whether the key was
+    /// used up. Other keys are handled as usual, and close the menu unless they type
+    /// or delete characters.
+    fn completion_key(&mut self, key: Key) -> bool {
+        let completion = self.completion.as_mut().unwrap();
+        match key {
+            Key::Ctrl('n') | Key::Down => completion.select(1),
+            Key::Ctrl('p') | Key::Up => completion.select(-1),
+            Key::Ctrl('y') => {
+                self.completion = None;
+                return true;
+            }
+            Key::Ctrl('e') => {
+                completion.selected = None;
+                self.put_completion();
+                self.completion = None;
+                return true;
+            }
+            Key::Char(ch) if ch != '\n' => return false,
+            Key::Backspace | Key::Ctrl('h') => return false,
+            _ => {
+                self.completion = None;
+                return false;
+            }
+        }
+        self.put_completion();
+        true
+    }
+
+    /// Filter the completion menu with the text typed since it was shown, closing it
+    /// when the cursor left that text or nothing matches.
+    fn filter_completion(&mut self) {
+        let (line, column) = self.cursor();
+        let completion = match &mut self.completion {
+            Some(completion) => completion,
+            None => return,
+        };
+        if !matches!(self.mode, Mode::Insert)
+            || line != completion.line
+            || column < completion.start
+        {
+            self.completion = None;
+            return;
+        }
+        let typed: String = self.buffer.text[line][completion.start..column]
+            .iter()
+            .collect();
+        if typed == completion.text() {
+            return;
+        }
+        completion.filter(&typed);
+        if completion.is_empty() {
+            self.completion = None;
+        }
+    }
+
+    /// Scroll the view for `Ctrl-d`/`Ctrl-u` by half a screen or `count` lines,
+    /// for `Ctrl-f`/`Ctrl-b` by `count` pages, and for `Ctrl-e`/`Ctrl-y` by `count` lines.
+    fn scroll_key(&mut self, key: char, count: Option<usize>) {
+        let text = &self.buffer.text;
+        let screen = &mut self.window.screen;
+        let half = std::cmp::max(screen.text_rows() / 2, 1);
+        let rows = match key {
+            'd' => screen.scroll_half_page(text, count.unwrap_or(half) as i32),
+            'u' => screen.scroll_half_page(text, -(count.unwrap_or(half) as i32)),
+            'f' => screen.scroll_pages(text, count.unwrap_or(1) as i32),
+            'b' => screen.scroll_pages(text, -(count.unwrap_or(1) as i32)),
+            'e' => screen.scroll_lines(text, count.unwrap_or(1) as i32),
+            _ => screen.scroll_lines(text, -(count.unwrap_or(1) as i32)),
+        };
+        self.scroll_view(rows);
+    }
+
+    /// Scroll the text rows of the window on the terminal along with its view, when the
+    /// window is as wide as the terminal: a scroll region moves whole rows.
+    fn scroll_view(&mut self, rows: i32) {
+        let rects = self.tree.layout(self.window_area());
+        let rect = match rects.iter().find(|(id, _)| *id == self.window.id) {
+            Some((_, rect)) => *rect,
+            None => return,
+        };
+        if rect.x == 0 && rect.width == self.terminal.size().0 as usize {
+            let height = std::cmp::min(self.window.screen.text_rows(), rect.height);
+            self.renderer
+                .scroll(&mut self.terminal, rect.y..rect.y + height, rows);
+        }
+    }
+
+    /// Handle `:edit {file}`: show the buffer of the file, adding one if there is none.
+    fn edit(&mut self, path: &str, force: bool) -> Result<Option<String>, String> {
+        if self.buffer.filepath.as_deref() == Some(path) {
+            return Ok(None);
+        }
+        if self.buffer.modified && !force && !self.shown_elsewhere(self.buffer.number) {
+            return Err(NOT_WRITTEN.to_string());
+        }
+        let (number, message) = self.buffer_for(path);
+        self.switch_to(number, true)?;
+        Ok(message)
+    }
+
+    /// Number of the buffer of the file, adding one if there is none, and the message
+    /// to show when it is opened.
+    fn buffer_for(&mut self, path: &str) -> (usize, Option<String>) {
+        let existing = self
+            .all_buffers()
+            .find(|buffer| buffer.filepath.as_deref() == Some(path))
+            .map(|buffer| buffer.number);
+        match existing {
+            Some(number) => (number, None),
+            None => self.add_buffer(path.to_string()),
+        }
+    }
+
+    /// Add an empty buffer without a file to the list. Returns its number.
+    fn add_empty_buffer(&mut self) -> usize {
+        let number = self.next_number;
+        self.next_number += 1;
+        self.buffers
+            .push(Buffer::new(number, None, text_from(""), None));
+        number
+    }
+
+    /// Handle `:edit` without a file: read the file of the buffer again, keeping the
+    /// cursor line.
+    fn reload(&mut self, force: bool) -> Result<Option<String>, String> {
+        if self.buffer.modified && !force {
+            return Err(NOT_WRITTEN.to_string());
+        }
+        let path = self
+            .buffer
+            .filepath
+            .clone()
+            .ok_or_else(|| "No file name".to_string())?;
+        let (line, _) = self.cursor();
+        let (mut buffer, message) = self.read_buffer(self.buffer.number, path);
+        buffer.options = std::mem::take(&mut self.buffer.options);
+        buffer.shown = true;
+        buffer.keymap = std::mem::take(&mut self.buffer.keymap);
+        buffer.marks = std::mem::take(&mut self.buffer.marks);
+        self.buffer = buffer;
+        self.go_to_line(line);
+        Ok(message)
+    }
+
+    /// Find a buffer for `:b`, by number or by a part of its file name.
+    fn find_buffer(&self, argument: &str) -> Result<usize, String> {
+        if let Ok(number) = argument.parse::<usize>() {
+            return Ok(number);
+        }
+        let matches: Vec<usize> = self
+            .all_buffers()
+            .filter(|buffer| buffer.name().contains(argument))
+            .map(|buffer| buffer.number)
+            .collect();
+        match matches.as_slice() {
+            [number] => Ok(*number),
+            [] => Err(format!("No matching buffer for {}", argument)),
+            _ => Err(format!("More than one match for {}", argument)),
+        }
+    }
+
+    /// Show the buffer `offset` places after the current one in the list, wrapping
+    /// around, for `:bnext` and `:bprevious`.
+    fn cycle_buffers(&mut self, offset: isize, force: bool) -> Result<Option<String>, String> {
+        let numbers = self.buffer_numbers();
+        let index = numbers
+            .iter()
+            .position(|&n| n == self.buffer.number)
+            .unwrap();
+        let next = (index as isize + offset).rem_euclid(numbers.len() as isize);
+        self.switch_to(numbers[next as usize], force)?;
+        Ok(None)
+    }
+
+    /// Handle `:bdelete`: remove the buffer from the list. When it is the current one,
+    /// the alternate buffer or the next one is shown, or an empty buffer if it was the last.
+    fn delete_buffer(&mut self, number: usize, force: bool) -> Result<Option<String>, String> {
+        let buffer = self
+            .all_buffers()
+            .find(|buffer| buffer.number == number)
+            .ok_or_else(|| "No buffers were deleted".to_string())?;
+        if buffer.modified && !force {
+            return Err(format!(
+                "No write since last change for buffer {} (add ! to override)",
+                number
+            ));
+        }
+        // Other tab pages lose the windows showing the buffer, and close without windows.
+        let mut i = 0;
+        while i < self.tab_pages.len() {
+            if self.tab_pages[i].remove_buffer(number) {
+                i += 1;
+                continue;
+            }
+            self.tab_pages.remove(i);
+            if i < self.tab_index {
+                self.tab_index -= 1;
+            }
+        }
+        // Windows showing the buffer are closed, as long as one window is left.
+        let closing: Vec<usize> = self
+            .windows
+            .iter()
+            .filter(|window| window.buffer == number)
+            .map(|window| window.id)
+            .collect();
+        for id in closing {
+            self.windows.retain(|window| window.id != id);
+            self.tree.remove(id);
+        }
+        if number == self.buffer.number && !self.windows.is_empty() {
+            self.close_window()?;
+        }
+        if number == self.buffer.number {
+            let numbers = self.buffer_numbers();
+            let index = numbers.iter().position(|&n| n == number).unwrap();
+            let next = self
+                .alternate
+                .or_else(|| numbers.get(index + 1).copied())
+                .or_else(|| index.checked_sub(1).map(|i| numbers[i]));
+            match next {
+                Some(next) => self.switch_to(next, true)?,
+                None => {
+                    let empty = self.add_empty_buffer();
+                    self.switch_to(empty, true)?;
+                }
+            }
+        }
+        if let Some(buffer) = self.buffers.iter().find(|buffer| buffer.number == number) {
+            let filetype = buffer.filetype();
+            let server = self
+                .servers
+                .iter_mut()
+                .find(|server| Some(server.filetype.as_str()) == filetype);
+            if let (Some(server), Some(uri), Some(_)) = (server, buffer.uri(), &buffer.synced) {
+                let document = Json::object(vec![("uri", uri.into())]);
+                server.notify(
+                    "textDocument/didClose",
+                    Json::object(vec![("textDocument", document)]),
+                );
+            }
+        }
+        self.buffers.retain(|buffer| buffer.number != number);
+        if self.alternate == Some(number) {
+            self.alternate = None;
+        }
+        self.arrange();
+        Ok(None)
+    }
+
+    /// The `:ls` listing, e.g. `  1 %a + "src/main.rs"   line 3`.
+    fn list_buffers(&self) -> String {
+        let mut buffers: Vec<&Buffer> = self.all_buffers().collect();
+        buffers.sort_by_key(|buffer| buffer.number);
+        buffers
+            .iter()
+            .map(|buffer| {
+                let current = buffer.number == self.buffer.number;
+                let shown = current || self.shown_elsewhere(buffer.number);
+                let line = if current {
+                    self.cursor().0
+                } else {
+                    buffer.cursor.0
+                };
+                format!(
+                    "{:>3} {}{} {} {:<30} line {}",
+                    buffer.number,
+                    if current {
+                        '%'
+                    } else if self.alternate == Some(buffer.number) {
+                        '#'
+                    } else {
+                        ' '
+                    },
+                    if shown { 'a' } else { 'h' },
+                    if buffer.modified { '+' } else { ' ' },
+                    format!("\"{}\"", buffer.name()),
+                    line + 1
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Handle `:wall`: write every modified buffer.
+    fn write_all(&mut self) -> Result<Option<String>, String> {
+        for buffer in self.all_buffers_mut().filter(|buffer| buffer.modified) {
+            buffer.write(None, false)?;
+        }
+        Ok(None)
+    }
+
+    /// Handle `:qall`: quit unless a buffer is modified.
+    fn quit_all(&mut self, force: bool) -> Result<Option<String>, String> {
+        if let Some(buffer) = self.all_buffers().find(|buffer| buffer.modified && !force) {
+            return Err(format!(
+                "No write since last change for buffer \"{}\"",
+                buffer.name()
+            ));
+        }
+        self.quit = true;
+        Ok(None)
+    }
+
+    /// Run the commands given with `+...` and `-c` on the command line.
+    pub fn run_commands(&mut self, commands: &[String]) {
+        for command in commands {
+            if let Err(message) = self.execute(command) {
+                self.message = Some(message);
+            }
+        }
+    }
+
+    /// Make a grammar available for syntax highlighting, and use it for the
+    /// files which match.
+    pub fn register_grammar(&mut self, grammar: Rc<dyn Grammar>) {
+        self.grammars.register(grammar);
+        let grammars = &self.grammars;
+        let buffers = std::iter::once(&mut self.buffer).chain(self.buffers.iter_mut());
+        for buffer in buffers {
+            let path = Path::new(buffer.filepath.as_deref().unwrap_or(""));
+            if let Some(grammar) = grammars.find(path, &buffer.text[0]) {
+                buffer.highlighter = Some(Highlighter::new(grammar));
+            }
+        }
+    }
+
+    /// Run an ex command, returning the message to show.
+    fn execute(&mut self, command: &str) -> Result<Option<String>, String> {
+        match command {
+            "w" | "w!" => self.buffer.write(None, command == "w!"),
+            command if command.starts_with("w ") || command.starts_with("w! ") => {
+                let (name, path) = command.split_once(' ').unwrap();
+                self.buffer
+                    .write(Some(path.trim().to_string()), name == "w!")
+            }
+            "wall" | "wa" => self.write_all(),
+            "qall" | "qa" | "qall!" | "qa!" => self.quit_all(command.ends_with('!')),
+            "wqall" | "wqa" | "xall" | "xa" => {
+                self.write_all()?;
+                self.quit_all(false)
+            }
+            "edit" | "e" | "edit!" | "e!" => self.reload(command.ends_with('!')),
+            command if command.starts_with("e ") || command.starts_with("edit ") => {
+                self.edit(command.split_once(' ').unwrap().1.trim(), false)
+            }
+            command if command.starts_with("e! ") || command.starts_with("edit! ") => {
+                self.edit(command.split_once(' ').unwrap().1.trim(), true)
+            }
+            command if command.starts_with("badd ") || command.starts_with("bad ") => {
+                let path = command.split_once(' ').unwrap().1.trim();
+                if !self
+                    .all_buffers()
+                    .any(|b| b.filepath.as_deref() == Some(path))
+                {
+                    self.add_buffer(path.to_string());
+                }
+                Ok(None)
+            }
+            "ls" | "buffers" | "files" => Ok(Some(self.list_buffers())),
+            "q" | "quit" | "q!" | "quit!" => self.quit_window(command.ends_with('!')),
+            "close" | "clo" => self.close_window(),
+            "only" | "on" => self.only_window(),
+            "tabnew" | "tabe" | "tabedit" => self.new_tab(None),
+            command
+                if command.starts_with("tabnew ")
+                    || command.starts_with("tabe ")
+                    || command.starts_with("tabedit ") =>
+            {
+                self.new_tab(Some(command.split_once(' ').unwrap().1.trim()))
+            }
+            "tabclose" | "tabc" => self.close_tab(),
+            "tabnext" | "tabn" => {
+                self.cycle_tabs(1);
+                Ok(None)
+            }
+            command if command.starts_with("tabnext ") || command.starts_with("tabn ") => {
+                let argument = command.split_once(' ').unwrap().1.trim();
+                match argument.parse::<usize>() {
+                    Ok(number) if (1..=self.tab_pages.len() + 1).contains(&number) => {
+                        self.go_to_tab(number - 1);
+                        Ok(None)
+                    }
+                    _ => Err(format!("Invalid argument: {}", argument)),
+                }
+            }
+            "tabprevious" | "tabp" | "tabNext" | "tabN" => {
+                self.cycle_tabs(-1);
+                Ok(None)
+            }
+            "split" | "sp" => self.split(false, None),
+            "vsplit" | "vs" => self.split(true, None),
+            command if command.starts_with("split ") || command.starts_with("sp ") => {
+                self.split(false, Some(command.split_once(' ').unwrap().1.trim()))
+            }
+            command if command.starts_with("vsplit ") || command.starts_with("vs ") => {
+                self.split(true, Some(command.split_once(' ').unwrap().1.trim()))
+            }
+            "bnext" | "bn" | "bnext!" | "bn!" => self.cycle_buffers(1, command.ends_with('!')),
+            "bprevious" | "bp" | "bNext" | "bN" | "bprevious!" | "bp!" | "bNext!" | "bN!" => {
+                self.cycle_buffers(-1, command.ends_with('!'))
+            }
+            command if command.starts_with("bdelete") || command.starts_with("bd") => {
+                let (name, argument) = command.split_once(' ').unwrap_or((command, ""));
+                if !["bdelete", "bd", "bdelete!", "bd!"].contains(&name) {
+                    return Err(format!("Not an editor command: {}", command));
+                }
+                let number = match argument.trim() {
+                    "" => self.buffer.number,
+                    argument => self.find_buffer(argument)?,
+                };
+                self.delete_buffer(number, name.ends_with('!'))
+            }
+            command if command.starts_with("buffer") || command.starts_with('b') => {
+                let (name, argument) = command.split_once(' ').unwrap_or((command, ""));
+                if !["buffer", "b", "buffer!", "b!"].contains(&name) {
+                    return Err(format!("Not an editor command: {}", command));
+                }
+                if argument.trim().is_empty() {
+                    return Ok(None);
+                }
+                let number = self.find_buffer(argument.trim())?;
+                self.switch_to(number, name.ends_with('!'))?;
+                Ok(None)
+            }
+            "next" | "n" | "next!" | "n!" => {
+                self.edit_arg(self.arg_index + 1, command.ends_with('!'))
+            }
+            "previous" | "prev" | "Next" | "N" | "previous!" | "prev!" | "Next!" | "N!" => {
+                match self.arg_index.checked_sub(1) {
+                    Some(index) => self.edit_arg(index, command.ends_with('!')),
+                    None => Err("Cannot go before first file".to_string()),
+                }
+            }
+            "args" => Ok(Some(
+                self.args
+                    .iter()
+                    .enumerate()
+                    .map(|(i, arg)| {
+                        if i == self.arg_index {
+                            format!("[{}]", arg)
+                        } else {
+                            arg.clone()
+                        }
+                    })
+                    .collect::<Vec<String>>()
+                    .join(" "),
+            )),
+            "$" => {
+                self.remember_jump();
+                self.go_to_line(self.buffer.text.len() - 1);
+                Ok(None)
+            }
+            command if command.chars().all(|c| c.is_ascii_digit()) && !command.is_empty() => {
+                let line: usize = command.parse().map_err(|_| "Invalid range".to_string())?;
+                self.remember_jump();
+                self.go_to_line(line.saturating_sub(1));
+                Ok(None)
+            }
+            command if command.starts_with('/') => {
+                let pattern: Vec<char> = command[1..].trim_end_matches('/').chars().collect();
+                match self.find_forward(&pattern) {
+                    Some((line, column)) => {
+                        self.remember_jump();
+                        self.go_to_line(line);
+                        self.window.screen.cursor.x = column;
+                        Ok(None)
+                    }
+                    None => Err(format!("Pattern not found: {}", &command[1..])),
+                }
+            }
+            "" => Ok(None),
+            command if command == "LspServer" || command.starts_with("LspServer ") => {
+                self.lsp_server(command["LspServer".len()..].trim())
+            }
+            command if command == "LspRename" || command.starts_with("LspRename ") => {
+                self.rename(command["LspRename".len()..].trim())
+            }
+            command if command.starts_with("colorscheme ") || command.starts_with("colo ") => {
+                let name = command.split_once(' ').unwrap().1.trim();
+                self.theme = Theme::load(name)?;
+                Ok(None)
+            }
+            "colorscheme" | "colo" => Ok(Some(self.theme.name.clone())),
+            command if command.starts_with("highlight ") || command.starts_with("hi ") => {
+                self.theme.highlight(command.split_once(' ').unwrap().1)
+            }
+            command if command.starts_with("set ") || command.starts_with("se ") => {
+                self.set_options(command.split_once(' ').unwrap().1)
+            }
+            command => {
+                let (name, arguments) = command.split_once(' ').unwrap_or((command, ""));
+                match map_command(name) {
+                    Some((mode, kind)) => self.map(mode, kind, arguments),
+                    None => Err(format!("Not an editor command: {}", command)),
+                }
+            }
+        }
+    }
+
+    /// Edit the file at `index` in the argument list, for `:next` and `:previous`.
+    fn edit_arg(&mut self, index: usize, force: bool) -> Result<Option<String>, String> {
+        if index >= self.args.len() {
+            return Err("Cannot go beyond last file".to_string());
+        }
+        let message = self.edit(&self.args[index].clone(), force)?;
+        self.arg_index = index;
+        Ok(message)
+    }
+
+    /// Move the cursor to the first non-blank character of `line`, as `:{number}` does.
+    fn go_to_line(&mut self, line: usize) {
+        let text = &self.buffer.text;
+        let line = std::cmp::min(line, text.len() - 1);
+        let current = self.window.screen.cursor.y + self.window.screen.row_offset;
+        self.window
+            .screen
+            .move_vert(text, line as i32 - current as i32);
+        self.window.screen.cursor.x = text[line]
+            .iter()
+            .position(|c| !c.is_whitespace())
+            .unwrap_or(0);
+    }
+
+    /// Position of the next occurrence of `pattern` after the cursor, wrapping around
+    /// the end of the text. Case is ignored with `ignorecase`, unless `smartcase` is set
+    /// and the pattern has upper case letters.
+    fn find_forward(&self, pattern: &[char]) -> Option<(usize, usize)> {
+        let text = &self.buffer.text;
+        let line = self.window.screen.cursor.y + self.window.screen.row_offset;
+        let column = self.window.screen.cursor.x;
+        let ignore_case = self.options.ignorecase
+            && !(self.options.smartcase && pattern.iter().any(|c| c.is_uppercase()));
+        let same =
+            |a: char, b: char| a == b || ignore_case && a.to_lowercase().eq(b.to_lowercase());
+        let matches = |chars: &[char]| {
+            chars.len() >= pattern.len() && chars.iter().zip(pattern).all(|(&a, &b)| same(a, b))
+        };
+        let find = |line: usize, from: usize| {
+            (from..text[line].len()).find(|&i| matches(&text[line][i..]))
+        };
+        if pattern.is_empty() {
+            return None;
+        }
+        if let Some(found) = find(line, column + 1) {
+            return Some((line, found));
+        }
+        (1..=text.len())
+            .map(|i| (line + i) % text.len())
+            .find_map(|l| find(l, 0).map(|found| (l, found)))
+    }
+
+    /// Handle the arguments of `:map`, `:noremap` and `:unmap` in their variants for
+    /// each mode: `[<buffer>] [<silent>] {lhs} {rhs}`. Without `{rhs}` the mappings
+    /// starting with `{lhs}` are listed, and without `{lhs}` all of them.
+    fn map(
+        &mut self,
+        mode: MapMode,
+        kind: MapKind,
+        arguments: &str,
+    ) -> Result<Option<String>, String> {
+        let mut arguments = arguments.trim_start();
+        let mut buffer = false;
+        let mut silent = false;
+        loop {
+            if let Some(rest) = arguments.strip_prefix("<buffer>") {
+                buffer = true;
+                arguments = rest.trim_start();
+            } else if let Some(rest) = arguments.strip_prefix("<silent>") {
+                silent = true;
+                arguments = rest.trim_start();
+            } else {
+                break;
+            }
+        }
+        let (lhs, rhs) = match arguments.split_once(char::is_whitespace) {
+            Some((lhs, rhs)) => (lhs, rhs.trim_start()),
+            None => (arguments, ""),
+        };
+        let lhs = parse_keys(lhs, &self.options.mapleader);
+        let keymap = if buffer {
+            &mut self.buffer.keymap
+        } else {
+            &mut self.keymap
+        };
+        if kind == MapKind::Unmap {
+            return if keymap.unmap(mode, &lhs) {
+                Ok(None)
+            } else {
+                Err("No such mapping".to_string())
+            };
+        }
+        if rhs.is_empty() {
+            let mut lines: Vec<String> = self
+                .buffer
+                .keymap
+                .list(mode, &lhs)
+                .map(|mapping| mapping.describe(true))
+                .collect();
+            if !buffer {
+                lines.extend(self.keymap.list(mode, &lhs).map(|m| m.describe(false)));
+            }
+            return if lines.is_empty() {
+                Ok(Some("No mapping found".to_string()))
+            } else {
+                Ok(Some(lines.join("\n")))
+            };
+        }
+        keymap.map(Mapping {
+            mode,
+            lhs,
+            rhs: parse_keys(rhs, &self.options.mapleader),
+            noremap: kind == MapKind::Noremap,
+            silent,
+        });
+        Ok(None)
+    }
+
+    /// Handle the arguments of `:set`, each of which goes to the options of its scope.
+    fn set_options(&mut self, arguments: &str) -> Result<Option<String>, String> {
+        let mut shown = Vec::new();
+        for argument in split_arguments(arguments) {
+            let (info, action) = parse_setting(&argument)?;
+            if let (OptionScope::Buffer, "iskeyword", Action::Set(Value::String(value))) =
+                (info.scope, info.name, &action)
+            {
+                Keyword::parse(value)?;
+            }
+            // The global value of a window or buffer option is set along with the local one.
+            let message = match info.scope {
+                OptionScope::Global => {
+                    apply_setting(&mut self.options, &GlobalOptions::default(), info, action)
+                }
+                OptionScope::Window => {
+                    let screen = &mut self.window.screen;
+                    let message = apply_setting(screen, &ScreenState::default(), info, action);
+                    if let Some(value) = screen.get(info.name) {
+                        self.window_options.set(info.name, value);
+                    }
+                    message
+                }
+                OptionScope::Buffer => {
+                    let options = &mut self.buffer.options;
+                    let message = apply_setting(options, &BufferOptions::default(), info, action);
+                    if let Some(value) = options.get(info.name) {
+                        self.buffer_options.set(info.name, value);
+                    }
+                    message
+                }
+            };
+            if info.name == "mouse" {
+                self.terminal.set_mouse(!self.options.mouse.is_empty());
+            }
+            shown.extend(message);
+        }
+        Ok(if shown.is_empty() {
+            None
+        } else {
+            Some(shown.join(" "))
+        })
+    }
+
+    /// Run the ex commands of a file such as `rimrc`, one per line. Lines starting
+    /// with `"` are comments. Errors are shown on the status line when the editor starts.
+    pub fn source(&mut self, path: &Path) {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(why) => {
+                self.message = Some(format!("{}: {}", path.display(), why));
+                return;
+            }
+        };
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim().trim_start_matches(':');
+            if line.is_empty() || line.starts_with('"') {
+                continue;
+            }
+            if let Err(message) = self.execute(line) {
+                self.message = Some(format!("{}:{}: {}", path.display(), i + 1, message));
+            }
+        }
+    }
+
+    /// Handle `:LspServer {filetype} {command}`: run the command as the language server
+    /// of the buffers of the filetype. Without a command, show the configured commands.
+    fn lsp_server(&mut self, arguments: &str) -> Result<Option<String>, String> {
+        let words: Vec<String> = arguments.split_whitespace().map(String::from).collect();
+        match words.split_first() {
+            Some((filetype, command)) if !command.is_empty() => {
+                self.lsp_commands.retain(|(name, _)| name != filetype);
+                self.lsp_commands.push((filetype.clone(), command.to_vec()));
+                Ok(None)
+            }
+            filetype => {
+                let listed: Vec<String> = self
+                    .lsp_commands
+                    .iter()
+                    .filter(|(name, _)| filetype.is_none_or(|(filetype, _)| name == filetype))
+                    .map(|(name, command)| format!("{} {}", name, command.join(" ")))
+                    .collect();
+                match (listed.is_empty(), filetype) {
+                    (true, Some((filetype, _))) => {
+                        Err(format!("No language server for {}", filetype))
+                    }
+                    (true, None) => Ok(None),
+                    (false, _) => Ok(Some(listed.join("\n"))),
+                }
+            }
+        }
+    }
+
+    /// Keep the language servers up to date: open the buffers of filetypes with a
+    /// server once it is ready, starting it when needed, send what was edited or written,
+    /// and take in the diagnostics the servers published.
+    fn update_servers(&mut self) {
+        if self.lsp_commands.is_empty() {
+            return;
+        }
+        let mut notifications = Vec::new();
+        let mut index = 0;
+        while index < self.servers.len() {
+            match self.servers[index].poll() {
+                Ok(polled) => {
+                    notifications.extend(polled);
+                    index += 1;
+                }
+                Err(message) => {
+                    self.stop_server(index);
+                    self.message = Some(message);
+                }
+            }
+        }
+        let root = std::env::current_dir().unwrap_or_default();
+        let buffers = std::iter::once(&mut self.buffer).chain(self.buffers.iter_mut());
+        for buffer in buffers {
+            let filetype = match (buffer.filetype(), &buffer.filepath) {
+                (Some(filetype), Some(_)) => filetype,
+                _ => continue,
+            };
+            let index = match self
+                .servers
+                .iter()
+                .position(|server| server.filetype == filetype)
+            {
+                Some(index) => index,
+                None => {
+                    let command = match self.lsp_commands.iter().find(|(name, _)| name == filetype)
+                    {
+                        Some((_, command)) => command,
+                        None => continue,
+                    };
+                    match Server::start(filetype, command, &root, self.terminal.waker()) {
+                        Ok(server) => {
+                            self.servers.push(server);
+                            self.servers.len() - 1
+                        }
+                        Err(message) => {
+                            // Not tried again at every redraw.
+                            self.lsp_commands.retain(|(name, _)| name != filetype);
+                            self.message = Some(message);
+                            continue;
+                        }
+                    }
+                }
+            };
+            // The response to `initialize` is announced like the other messages, and the
+            // buffer is opened at the redraw after it.
+            if self.servers[index].ready() {
+                buffer.sync(&mut self.servers[index]);
+            }
+        }
+        for notification in notifications {
+            self.notified(notification);
+        }
+    }
+
+    /// Stop the server at `index`, which failed, without starting it again at every
+    /// redraw.
+    fn stop_server(&mut self, index: usize) {
+        let server = self.servers.remove(index);
+        self.lsp_commands
+            .retain(|(name, _)| *name != server.filetype);
+    }
+
+    /// Act on a notification of a language server: diagnostics are kept with the buffer
+    /// of their document.
+    fn notified(&mut self, notification: Json) {
+        let method = notification.get("method").and_then(Json::as_str);
+        if method != Some("textDocument/publishDiagnostics") {
+            return;
+        }
+        let params = notification.get("params").unwrap_or(&Json::Null);
+        let uri = params.get("uri").and_then(Json::as_str);
+        for buffer in self.all_buffers_mut() {
+            if buffer.uri().as_deref() == uri {
+                buffer.diagnostics = diagnostics(params, &buffer.text);
+            }
+        }
+    }
+
+    /// Send a request about the current buffer to its language server, once the server
+    /// is ready and up to date, and wait for the result.
+    fn lsp_request(&mut self, method: &str, params: Json) -> Result<Json, String> {
+        self.update_servers();
+        let filetype = self.buffer.filetype();
+        let no_server = || "No language server for this buffer".to_string();
+        let index = self
+            .servers
+            .iter()
+            .position(|server| Some(server.filetype.as_str()) == filetype)
+            .ok_or_else(no_server)?;
+        let mut notifications = Vec::new();
+        let server = &mut self.servers[index];
+        if let Err(message) =
+            server.wait_ready(&mut |notification| notifications.push(notification))
+        {
+            self.stop_server(index);
+            return Err(message);
+        }
+        if self.buffer.filepath.is_some() {
+            self.buffer.sync(server);
+        }
+        if self.buffer.synced.is_none() {
+            return Err(no_server());
+        }
+        let result = server.request(method, params, &mut |notification| {
+            notifications.push(notification)
+        });
+        for notification in notifications {
+            self.notified(notification);
+        }
+        result
+    }
+
+    /// The current buffer and the cursor position in it, the parameters of most requests,
+    /// followed by `extra`.
+    fn position_params(&self, extra: Vec<(&str, Json)>) -> Json {
+        let (line, column) = self.cursor();
+        let document = Json::object(vec![("uri", self.buffer.uri().unwrap_or_default().into())]);
+        let mut members = vec![
+            ("textDocument", document),
+            ("position", position(&self.buffer.text, line, column)),
+        ];
+        members.extend(extra);
+        Json::object(members)
+    }
+
+    /// Handle `K`: show what the language server says about the symbol under the cursor.
+    fn hover(&mut self) -> Result<Option<String>, String> {
+        let result = self.lsp_request("textDocument/hover", self.position_params(vec![]))?;
+        match hover_text(&result) {
+            Some(text) => Ok(Some(text)),
+            None => Err("No information available".to_string()),
+        }
+    }
+
+    /// Handle `gd`: jump to where the symbol under the cursor is defined.
+    fn go_to_definition(&mut self) -> Result<Option<String>, String> {
+        let params = self.position_params(vec![]);
+        let result = self.lsp_request("textDocument/definition", params)?;
+        match locations(&result).first() {
+            Some((uri, position)) => self.jump_to_location(uri, position),
+            None => Err("No definition found".to_string()),
+        }
+    }
+
+    /// Handle `gr`: jump to the only reference to the symbol under the cursor, or list
+    /// all of them.
+    fn references(&mut self) -> Result<Option<String>, String> {
+        let context = Json::object(vec![("includeDeclaration", true.into())]);
+        let params = self.position_params(vec![("context", context)]);
+        let result = self.lsp_request("textDocument/references", params)?;
+        match locations(&result).as_slice() {
+            [] => Err("No references found".to_string()),
+            [(uri, position)] => self.jump_to_location(uri, position),
+            found => Ok(Some(
+                found
+                    .iter()
+                    .map(|(uri, position)| self.describe_location(uri, position))
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+            )),
+        }
+    }
+
+    /// Show the document of `uri` in the current window, with the cursor at the position.
+    fn jump_to_location(&mut self, uri: &str, position: &Json) -> Result<Option<String>, String> {
+        self.remember_jump();
+        let existing = self
+            .all_buffers()
+            .find(|buffer| buffer.uri().as_deref() == Some(uri))
+            .map(|buffer| buffer.number);
+        let message = match existing {
+            Some(number) => {
+                self.switch_to(number, false)?;
+                None
+            }
+            None => {
+                let path = uri_to_path(uri).ok_or_else(|| format!("Cannot open {}", uri))?;
+                self.edit(&path.to_string_lossy(), false)?
+            }
+        };
+        let (line, column) = from_position(&self.buffer.text, position);
+        self.go_to_line(line);
+        self.window.screen.cursor.x = column;
+        Ok(message)
+    }
+
+    /// A location as `file:line:column: text`, with the file relative to the current
+    /// directory.
+    fn describe_location(&self, uri: &str, position: &Json) -> String {
+        let path = uri_to_path(uri).unwrap_or_default();
+        let read;
+        let text = match self
+            .all_buffers()
+            .find(|buffer| buffer.uri().as_deref() == Some(uri))
+        {
+            Some(buffer) => &buffer.text,
+            None => {
+                read = text_from(&fs::read_to_string(&path).unwrap_or_default());
+                &read
+            }
+        };
+        let (line, column) = from_position(text, position);
+        let directory = std::env::current_dir().unwrap_or_default();
+        let name = path.strip_prefix(&directory).unwrap_or(&path);
+        let contents: String = text[line].iter().collect();
+        format!(
+            "{}:{}:{}: {}",
+            name.display(),
+            line + 1,
+            column + 1,
+            contents.trim()
+        )
+    }
+
+    /// Handle `:LspRename {name}`: rename the symbol under the cursor wherever the
+    /// language server finds it. Files which are not in the buffer list are added to it.
+    fn rename(&mut self, name: &str) -> Result<Option<String>, String> {
+        if name.is_empty() {
+            return Err("Argument required".to_string());
+        }
+        let params = self.position_params(vec![("newName", name.into())]);
+        let result = self.lsp_request("textDocument/rename", params)?;
+        let changes = workspace_edits(&result);
+        if changes.is_empty() {
+            return Err("Nothing to rename".to_string());
+        }
+        for (uri, edits) in &changes {
+            let existing = self
+                .all_buffers()
+                .find(|buffer| buffer.uri().as_deref() == Some(uri))
+                .map(|buffer| buffer.number);
+            let number = match existing {
+                Some(number) => number,
+                None => {
+                    let path = uri_to_path(uri).ok_or_else(|| format!("Cannot open {}", uri))?;
+                    self.add_buffer(path.to_string_lossy().to_string()).0
+                }
+            };
+            let buffer = self
+                .all_buffers_mut()
+                .find(|buffer| buffer.number == number)
+                .unwrap();
+            let lines = buffer.text.len();
+            if let Some(line) = apply_edits(&mut buffer.text, edits) {
+                buffer.record_lines(line, lines - line, buffer.text.len() - line);
+                buffer.invalidate(line);
+            }
+        }
+        self.window.screen.move_vert(&self.buffer.text, 0);
+        Ok(None)
+    }
+
+    /// In diff mode, compare the two files again after either of them changed.
+    fn update_diffs(&mut self) {
+        if !self.diff || self.args.len() != 2 {
+            return;
+        }
+        let find = |path: &str| {
+            self.all_buffers()
+                .find(|buffer| buffer.filepath.as_deref() == Some(path))
+        };
+        let (first, second) = match (find(&self.args[0]), find(&self.args[1])) {
+            (Some(first), Some(second)) => (first, second),
+            _ => return,
+        };
+        if first.diff.is_some() && second.diff.is_some() {
+            return;
+        }
+        let diffs = [
+            diff_lines(&first.text, &second.text),
+            diff_lines(&second.text, &first.text),
+        ];
+        let args = self.args.clone();
+        for (path, diff) in args.iter().zip(diffs) {
+            let buffer = self
+                .all_buffers_mut()
+                .find(|buffer| buffer.filepath.as_ref() == Some(path));
+            if let Some(buffer) = buffer {
+                buffer.diff = Some(diff);
+            }
+        }
+    }
+
+    /// Bring the terminal up to date after an event: the windows, the command line and
+    /// the cursor.
+    fn redraw(&mut self) {
+        self.update_servers();
+        self.update_diffs();
+        let several = !self.windows.is_empty();
+        let (start, end) = self.selection();
+        let position = self.cursor();
+        let selection = match self.mode {
+            Mode::Visual => Some((start, end, false)),
+            Mode::VisualLine => Some((start, end, true)),
+            _ => None,
+        };
+        let mut windows = Vec::new();
+        let mut cursor = (0, 0);
+        for (id, rect) in self.tree.layout(self.window_area()) {
+            let current = id == self.window.id;
+            let window = if current {
+                &mut self.window
+            } else {
+                self.windows.iter_mut().find(|w| w.id == id).unwrap()
+            };
+            let buffer = std::iter::once(&mut self.buffer)
+                .chain(self.buffers.iter_mut())
+                .find(|buffer| buffer.number == window.buffer)
+                .unwrap();
+            window.screen.wrap.tabstop = buffer.options.tabstop;
+            if !current {
+                // The text may have been edited in another window.
+                window.screen.move_vert(&buffer.text, 0);
+            }
+            window.screen.signs = !buffer.diagnostics.is_empty();
+            window.screen.scroll_horizontally(&buffer.text);
+            let layout = buffer.layout(&window.screen);
+            let mut grid = Grid::new(rect.width, rect.height);
+            draw_layout(&mut grid, &buffer.text, &layout, &self.theme);
+            if let Some(diff) = &buffer.diff {
+                draw_diff(&mut grid, &layout, diff, &self.theme);
+            }
+            draw_diagnostics(
+                &mut grid,
+                &buffer.text,
+                &layout,
+                &buffer.diagnostics,
+                &self.theme,
+            );
+            if let (true, true, Some(find)) = (current, self.show_targets, &self.last_find) {
+                let first = layout.rows.iter().flatten().map(|row| row.line).min();
+                let last = layout.rows.iter().flatten().map(|row| row.line).max();
+                let shown = first.unwrap_or(0)..=last.unwrap_or(0);
+                let targets: Vec<_> = find
+                    .positions(&buffer.text, position)
+                    .take_while(|(line, _)| shown.contains(line))
+                    .collect();
+                draw_targets(&mut grid, &layout, &targets, &self.theme);
+            }
+            if let (true, Some((start, end, linewise))) = (current, selection) {
+                draw_selection(
+                    &mut grid,
+                    &buffer.text,
+                    &layout,
+                    start,
+                    end,
+                    linewise,
+                    &self.theme,
+                );
+            }
+            if several {
+                draw_window_status(
+                    &mut grid,
+                    buffer.name(),
+                    buffer.modified,
+                    current,
+                    &self.theme,
+                );
+            }
+            if current {
+                let (x, y) = window.screen.cursor_position(&buffer.text);
+                cursor = (rect.x + x, rect.y + y);
+            }
+            windows.push((rect, grid));
+        }
+        let tab_labels = if self.tab_pages.is_empty() {
+            Vec::new()
+        } else {
+            self.tab_labels()
         };
+        let frame = self.renderer.frame();
+        if !tab_labels.is_empty() {
+            draw_tab_line(frame, &tab_labels, self.tab_index, &self.theme);
+        }
+        for (rect, grid) in &windows {
+            frame.blit(rect.x, rect.y, grid);
+            if rect.x + rect.width < frame.width {
+                draw_border(frame, rect.x + rect.width, rect.y, rect.height, &self.theme);
+            }
+        }
+        frame.cursor = cursor;
+        if let (Mode::Insert, Some(completion)) = (&self.mode, &self.completion) {
+            // The menu lines up with the start of the text being completed.
+            let column = self.window.screen.cursor.x;
+            let x = cursor
+                .0
+                .saturating_sub(column.saturating_sub(completion.start));
+            let bottom = frame.height - 1;
+            draw_popup(
+                frame,
+                (x, cursor.1),
+                bottom,
+                &completion.items(),
+                completion.selected,
+                &self.theme,
+            );
+        }
+        match self.message.take() {
+            Some(message) if message.contains('\n') => {
+                frame.cursor = draw_message(frame, &message, &self.theme);
+                self.more = true;
+            }
+            Some(message) => draw_status(frame, &self.mode, vec![message], &self.theme),
+            None => {
+                let status = vec![
+                    self.mode.to_string(),
+                    (self.window.screen.cursor.y + self.window.screen.row_offset + 1).to_string(),
+                    (self.window.screen.cursor.x + 1).to_string(),
+                ];
+                draw_status(frame, &self.mode, status, &self.theme);
+            }
+        }
+        self.renderer.present(&mut self.terminal);
+    }
+
+    pub fn terminal(&self) -> &T {
+        &self.terminal
+    }
+
+    pub fn terminal_mut(&mut self) -> &mut T {
+        &mut self.terminal
+    }
+
+    pub fn text(&self) -> &TextState {
+        &self.buffer.text
+    }
 
-        Editor {
-            filepath: config.filepath,
-            buffer,
-            io: IO { stdin, stdout },
+    /// File of the buffer; `None` for text read from stdin.
+    pub fn filepath(&self) -> Option<&str> {
+        self.buffer.filepath.as_deref()
+    }
+
+    /// Line and column of the cursor in the text, both zero-based.
+    pub fn cursor(&self) -> (usize, usize) {
+        let screen = &self.window.screen;
+        (screen.cursor.y + screen.row_offset, screen.cursor.x)
+    }
+
+    /// Handle events until the editor is quit or the terminal has no more input.
+    pub fn editor_loop(&mut self) {
+        // The commands run at startup may have quit.
+        if self.quit {
+            return;
+        }
+        self.renderer.invalidate(&mut self.terminal);
+        self.redraw();
+        loop {
+            let timeout = if self.typed.is_empty() {
+                None
+            } else {
+                Some(Duration::from_millis(self.options.timeoutlen as u64))
+            };
+            let event = match self.terminal.next_event(timeout) {
+                Some(event) => event,
+                None => break,
+            };
+            let running = match event {
+                EditorEvent::Input(Event::Key(key)) => {
+                    self.typed.push(TypedKey {
+                        key,
+                        remap: true,
+                        silent: false,
+                    });
+                    self.resolve_keys(false)
+                }
+                EditorEvent::Input(evt) => self.resolve_keys(true) && self.handle_event(evt),
+                EditorEvent::Timeout => self.resolve_keys(true),
+                EditorEvent::Paste(text) => {
+                    let running = self.resolve_keys(true);
+                    if running {
+                        self.paste(&text);
+                    }
+                    running
+                }
+                // The messages are taken in when redrawing.
+                EditorEvent::Lsp => true,
+                EditorEvent::Resize(_, _) => {
+                    self.arrange();
+                    self.renderer.invalidate(&mut self.terminal);
+                    true
+                }
+            };
+            if !running {
+                break;
+            }
+            self.redraw();
+        }
+    }
+
+    /// Run the typed keys through the mappings of the current mode and handle the
+    /// resulting keys. Keys which may start a longer mapping are kept until more keys
+    /// come, unless `timed_out` is set. Returns `false` when the editor was quit.
+    fn resolve_keys(&mut self, timed_out: bool) -> bool {
+        let mut expansions = 0;
+        while !self.typed.is_empty() {
+            let mode = match self.mode {
+                Mode::Normal => MapMode::Normal,
+                Mode::Insert | Mode::Replace(_) => MapMode::Insert,
+                Mode::Visual | Mode::VisualLine => MapMode::Visual,
+                Mode::Command(_) => MapMode::Command,
+            };
+            let keys: Vec<Key> = self
+                .typed
+                .iter()
+                .take_while(|typed| typed.remap)
+                .map(|typed| typed.key)
+                .collect();
+            // The second key of a command such as `gj` is not mapped.
+            let mapping = if keys.is_empty() || self.pending.is_some() {
+                None
+            } else {
+                match lookup(&[&self.buffer.keymap, &self.keymap], mode, &keys, timed_out) {
+                    Lookup::Incomplete => return true,
+                    Lookup::Mapped(mapping) => Some(mapping.clone()),
+                    Lookup::Unmapped => None,
+                }
+            };
+            match mapping {
+                Some(mapping) => {
+                    expansions += 1;
+                    if expansions > MAX_MAP_DEPTH {
+                        self.typed.clear();
+                        self.message = Some("Recursive mapping".to_string());
+                        return true;
+                    }
+                    let silent = mapping.silent || self.typed[0].silent;
+                    self.typed.drain(..mapping.lhs.len());
+                    // The keys of a mapping starting with its own keys are not mapped again.
+                    let own_keys = if mapping.rhs.starts_with(&mapping.lhs) {
+                        mapping.lhs.len()
+                    } else {
+                        0
+                    };
+                    let expanded = mapping.rhs.iter().enumerate().map(|(i, &key)| TypedKey {
+                        key,
+                        remap: !mapping.noremap && i >= own_keys,
+                        silent,
+                    });
+                    self.typed.splice(0..0, expanded);
+                }
+                None => {
+                    let typed = self.typed.remove(0);
+                    self.silent = typed.silent;
+                    let running = self.handle_event(Event::Key(typed.key));
+                    self.silent = false;
+                    if !running {
+                        return false;
+                    }
+                }
+            }
         }
+        true
     }
 
-    pub fn editor_loop(mut self) -> () {
-        self.buffer
-            .text
-            .rewrite_entire_screen(&mut self.io.stdout, 0);
-        let mut mode = Mode::Normal;
-        for c in self.io.stdin.events() {
-            let evt = c.unwrap();
-            let mut line_to_rewrite: Option<usize> = None;
-            let mut rewrite_all_lines = false;
-            let mut error_message: Option<String> = None;
-            mode = match mode {
-                Mode::Normal => match evt {
+    /// Act on one key or mouse event in the current mode. Returns `false` when the
+    /// editor was quit.
+    fn handle_event(&mut self, evt: Event) -> bool {
+        if self.more {
+            self.more = false;
+            if matches!(
+                evt,
+                Event::Key(Key::Char('\n')) | Event::Key(Key::Char(' ')) | Event::Key(Key::Esc)
+            ) {
+                return true;
+            }
+        }
+        self.show_targets = false;
+        let mode = std::mem::replace(&mut self.mode, Mode::Normal);
+        if let Event::Mouse(event) = evt {
+            self.pending = None;
+            self.count = None;
+            self.operator = None;
+            self.completion = None;
+            self.mode = if self.mouse_enabled(&mode) {
+                self.mouse(event, mode)
+            } else {
+                mode
+            };
+            return true;
+        }
+        self.mode = match mode {
+            Mode::Normal => {
+                let count = self.count.take();
+                match evt {
+                    Event::Key(Key::Char(ch @ '0'..='9'))
+                        if self.pending.is_none() && (ch != '0' || count.is_some()) =>
+                    {
+                        let digit = ch.to_digit(10).unwrap() as usize;
+                        self.count =
+                            Some(count.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+                        Mode::Normal
+                    }
+                    Event::Key(key) if self.pending.is_some() => {
+                        let operator = self.operator.take();
+                        let mut mode = Mode::Normal;
+                        match (self.pending.take().unwrap(), key) {
+                            (Key::Char('g'), Key::Char(ch @ ('e' | 'E'))) => {
+                                self.operator = operator;
+                                mode = self.motion(Motion::WordEndBackward(ch == 'E'), count);
+                            }
+                            (Key::Char('g'), Key::Char('g')) => {
+                                self.operator = operator;
+                                let line = count.map_or(0, |count| count - 1);
+                                mode = self.motion(Motion::GoToLine(line), None);
+                            }
+                            (Key::Char(command @ ('f' | 'F' | 't' | 'T')), Key::Char(ch)) => {
+                                let find = Find {
+                                    c: ch,
+                                    forward: command.is_lowercase(),
+                                    till: command.eq_ignore_ascii_case(&'t'),
+                                    multiline: self.options.charsearch.contains('m'),
+                                };
+                                self.last_find = Some(find);
+                                self.operator = operator;
+                                mode = self.motion(Motion::Find(find, false), count);
+                            }
+                            (Key::Char(kind @ ('\'' | '`')), Key::Char(name)) => {
+                                self.operator = operator;
+                                mode = self.go_to_mark(name, kind == '`');
+                            }
+                            (Key::Char(kind @ ('i' | 'a')), Key::Char(ch)) => {
+                                if let (Some((operator, before)), Some(object)) =
+                                    (operator, TextObject::from_key(ch))
+                                {
+                                    let count = before * count.unwrap_or(1);
+                                    mode = self.operate_on_object(
+                                        operator,
+                                        object,
+                                        count,
+                                        kind == 'a',
+                                    );
+                                }
+                            }
+                            _ if operator.is_some() => (),
+                            (Key::Char('r'), Key::Char(ch)) => {
+                                self.replace_chars(ch, count.unwrap_or(1))
+                            }
+                            (Key::Char('g'), Key::Char('J')) => {
+                                self.join(count.unwrap_or(2), false)
+                            }
+                            (Key::Char('g'), Key::Char('j')) => {
+                                let rows = self.window.screen.move_screen_row(&self.buffer.text, 1);
+                                self.scroll_view(rows);
+                            }
+                            (Key::Char('g'), Key::Char('k')) => {
+                                let rows =
+                                    self.window.screen.move_screen_row(&self.buffer.text, -1);
+                                self.scroll_view(rows);
+                            }
+                            (Key::Char('z'), Key::Char('h')) => {
+                                self.window.screen.scroll_columns(&self.buffer.text, -1)
+                            }
+                            (Key::Char('z'), Key::Char('l')) => {
+                                self.window.screen.scroll_columns(&self.buffer.text, 1)
+                            }
+                            (Key::Char('z'), Key::Char('s')) => {
+                                self.window.screen.scroll_cursor_to_left(&self.buffer.text)
+                            }
+                            (Key::Char('z'), Key::Char('e')) => {
+                                self.window.screen.scroll_cursor_to_right(&self.buffer.text)
+                            }
+                            (Key::Char('z'), Key::Char('t')) => {
+                                let rows =
+                                    self.window.screen.scroll_cursor_to_top(&self.buffer.text);
+                                self.scroll_view(rows);
+                            }
+                            (Key::Char('z'), Key::Char('z')) => {
+                                let rows = self
+                                    .window
+                                    .screen
+                                    .scroll_cursor_to_middle(&self.buffer.text);
+                                self.scroll_view(rows);
+                            }
+                            (Key::Char('z'), Key::Char('b')) => {
+                                let rows = self
+                                    .window
+                                    .screen
+                                    .scroll_cursor_to_bottom(&self.buffer.text);
+                                self.scroll_view(rows);
+                            }
+                            (Key::Char('g'), Key::Char('d')) => {
+                                self.message = self.go_to_definition().unwrap_or_else(Some)
+                            }
+                            (Key::Char('g'), Key::Char('r')) => {
+                                self.message = self.references().unwrap_or_else(Some)
+                            }
+                            (Key::Char('m'), Key::Char(name))
+                                if name.is_ascii_alphabetic() || "'`[]<>".contains(name) =>
+                            {
+                                self.set_mark(name)
+                            }
+                            (Key::Char('g'), Key::Char(';')) => {
+                                self.go_to_change(count.unwrap_or(1) as i32)
+                            }
+                            (Key::Char('g'), Key::Char(',')) => {
+                                self.go_to_change(-(count.unwrap_or(1) as i32))
+                            }
+                            (Key::Char('g'), Key::Char('t')) => self.cycle_tabs(1),
+                            (Key::Char('g'), Key::Char('T')) => self.cycle_tabs(-1),
+                            (Key::Ctrl('w'), Key::Char(ch)) | (Key::Ctrl('w'), Key::Ctrl(ch)) => {
+                                self.window_command(ch)
+                            }
+                            _ => (),
+                        }
+                        mode
+                    }
+                    Event::Key(key) if self.operator.is_some() => self.operator_key(key, count),
                     Event::Key(key) => match key {
                         Key::Char(ch) => match ch {
-                            'q' => break,
+                            'q' => {
+                                if let Err(message) = self.quit_window(false) {
+                                    self.message = Some(message);
+                                }
+                                if self.quit {
+                                    return false;
+                                }
+                                Mode::Normal
+                            }
                             'h' => {
-                                self.buffer.screen.move_horiz(&self.buffer.text, -1);
+                                self.window.screen.move_horiz(&self.buffer.text, -1);
                                 Mode::Normal
                             }
                             'j' => {
-                                if self.buffer.screen.cursor.y + self.buffer.screen.row_offset + 1
-                                    < self.buffer.text.len()
-                                {
-                                    if let Some(line) =
-                                        self.buffer.screen.move_vert(&self.buffer.text, 1)
-                                    {
-                                        line_to_rewrite = Some(line);
-                                        write!(self.io.stdout, "{}", termion::scroll::Up(1))
-                                            .unwrap();
-                                    }
-                                }
+                                let rows = self.window.screen.move_vert(&self.buffer.text, 1);
+                                self.scroll_view(rows);
                                 Mode::Normal
                             }
                             'k' => {
-                                if self.buffer.screen.cursor.y + self.buffer.screen.row_offset >= 1
-                                {
-                                    if let Some(line) =
-                                        self.buffer.screen.move_vert(&self.buffer.text, -1)
-                                    {
-                                        line_to_rewrite = Some(line);
-                                        write!(self.io.stdout, "{}", termion::scroll::Down(1))
-                                            .unwrap();
-                                    };
-                                }
+                                let rows = self.window.screen.move_vert(&self.buffer.text, -1);
+                                self.scroll_view(rows);
                                 Mode::Normal
                             }
-                            'l' => {
-                                self.buffer.screen.move_horiz(&self.buffer.text, 1);
+                            // Ctrl-i, which terminals send as Tab.
+                            '\t' => {
+                                self.go_to_jump(-(count.unwrap_or(1) as i32));
                                 Mode::Normal
                             }
-                            '0' => {
-                                self.buffer.screen.cursor.x = 0;
+                            'g' | 'z' | 'f' | 'F' | 't' | 'T' | 'm' | 'r' | '\'' | '`' => {
+                                self.pending = Some(key);
+                                self.count = count;
+                                Mode::Normal
+                            }
+                            'l' => {
+                                self.window.screen.move_horiz(&self.buffer.text, 1);
                                 Mode::Normal
                             }
-                            '$' => {
-                                self.buffer.screen.cursor.x = self.buffer.text
-                                    [self.buffer.screen.cursor.y + self.buffer.screen.row_offset]
-                                    .len()
-                                    - 2;
+                            '0' => {
+                                self.window.screen.cursor.x = 0;
                                 Mode::Normal
                             }
-                            'w' => {
-                                let mut has_seen_space = false;
-                                loop {
-                                    if self.buffer.screen.cursor.x + 1
-                                        >= self.buffer.text[self.buffer.screen.cursor.y
-                                            + self.buffer.screen.row_offset]
-                                            .len()
-                                    {
-                                        break;
-                                    }
-                                    match self.buffer.text[self.buffer.screen.cursor.y
-                                        + self.buffer.screen.row_offset]
-                                        [self.buffer.screen.cursor.x]
-                                    {
-                                        'a'..='z' => {
-                                            if has_seen_space {
-                                                break;
-                                            }
-                                            self.buffer.screen.cursor.x += 1;
-                                        }
-                                        _ => break,
-                                    }
+                            '$' | 'w' | 'W' | 'b' | 'B' | 'e' | 'E' | ';' | ',' | 'G' | 'H'
+                            | 'M' | 'L' | '{' | '}' | '(' | ')' | '%' => {
+                                match self.motion_for_key(ch, count) {
+                                    Some(motion) => self.motion(motion, count),
+                                    None => Mode::Normal,
                                 }
+                            }
+                            'd' | 'c' | 'y' => {
+                                self.operator = Some((ch, count.unwrap_or(1)));
                                 Mode::Normal
                             }
                             'x' => {
-                                if self.buffer.text
-                                    [self.buffer.screen.cursor.y + self.buffer.screen.row_offset]
-                                    .len()
-                                    >= 1
+                                if !self.buffer.text
+                                    [self.window.screen.cursor.y + self.window.screen.row_offset]
+                                    .is_empty()
                                 {
-                                    self.buffer.text[self.buffer.screen.cursor.y
-                                        + self.buffer.screen.row_offset]
-                                        .remove(self.buffer.screen.cursor.x);
-                                    if self.buffer.screen.cursor.x
-                                        >= self.buffer.text[self.buffer.screen.cursor.y
-                                            + self.buffer.screen.row_offset]
+                                    self.buffer.text[self.window.screen.cursor.y
+                                        + self.window.screen.row_offset]
+                                        .remove(self.window.screen.cursor.x);
+                                    self.buffer.invalidate(
+                                        self.window.screen.cursor.y + self.window.screen.row_offset,
+                                    );
+                                    if self.window.screen.cursor.x
+                                        >= self.buffer.text[self.window.screen.cursor.y
+                                            + self.window.screen.row_offset]
                                             .len()
-                                        && self.buffer.screen.cursor.x > 0
+                                        && self.window.screen.cursor.x > 0
                                     {
-                                        self.buffer.screen.cursor.x -= 1;
+                                        self.window.screen.cursor.x -= 1;
                                     }
                                 }
-                                line_to_rewrite = Some(self.buffer.screen.cursor.y);
                                 Mode::Normal
                             }
                             'i' => Mode::Insert,
+                            'R' => Mode::Replace(Vec::new()),
+                            '~' => {
+                                self.switch_case(count.unwrap_or(1));
+                                Mode::Normal
+                            }
+                            'J' => {
+                                self.join(count.unwrap_or(2), true);
+                                Mode::Normal
+                            }
                             'a' => {
-                                self.buffer.screen.move_horiz(&self.buffer.text, 1);
-                                line_to_rewrite = Some(self.buffer.screen.cursor.y);
+                                self.window.screen.move_horiz(&self.buffer.text, 1);
                                 Mode::Insert
                             }
                             'o' => {
-                                self.buffer.text.insert(
-                                    self.buffer.screen.cursor.y + self.buffer.screen.row_offset + 1,
-                                    Vec::new(),
+                                let line = self.cursor().0;
+                                self.buffer.text.insert(line + 1, Vec::new());
+                                self.shift_marks(line + 1, 0, 1);
+                                self.buffer.invalidate(
+                                    self.window.screen.cursor.y + self.window.screen.row_offset,
                                 );
-                                self.buffer.screen.move_vert(&self.buffer.text, 1);
-                                rewrite_all_lines = true;
+                                self.window.screen.move_vert(&self.buffer.text, 1);
                                 Mode::Insert
                             }
                             'I' => {
-                                self.buffer.screen.cursor.x = 0;
-                                line_to_rewrite = Some(self.buffer.screen.cursor.y);
+                                self.window.screen.cursor.x = 0;
                                 Mode::Insert
                             }
                             'A' => {
-                                self.buffer.screen.cursor.x = self.buffer.text
-                                    [self.buffer.screen.cursor.y + self.buffer.screen.row_offset]
+                                self.window.screen.cursor.x = self.buffer.text
+                                    [self.window.screen.cursor.y + self.window.screen.row_offset]
                                     .len();
-                                line_to_rewrite = Some(self.buffer.screen.cursor.y);
                                 Mode::Insert
                             }
-                            ':' => Mode::Command(String::new()),
-                            _ => Mode::Normal,
-                        },
-                        Key::Ctrl(ch) => match ch {
-                            'l' => {
-                                rewrite_all_lines = true;
+                            'v' | 'V' => {
+                                let (line, column) = self.cursor();
+                                let length = self.buffer.text[line].len();
+                                self.visual_start =
+                                    (line, std::cmp::min(column, length.saturating_sub(1)));
+                                if ch == 'v' {
+                                    Mode::Visual
+                                } else {
+                                    Mode::VisualLine
+                                }
+                            }
+                            'p' | 'P' => {
+                                self.put(ch == 'p');
+                                Mode::Normal
+                            }
+                            'K' => {
+                                self.message = self.hover().unwrap_or_else(Some);
                                 Mode::Normal
                             }
+                            ':' => Mode::Command(String::new()),
                             _ => Mode::Normal,
                         },
+                        Key::Ctrl('l') => {
+                            self.renderer.invalidate(&mut self.terminal);
+                            Mode::Normal
+                        }
+                        Key::Ctrl(ch @ ('d' | 'u' | 'f' | 'b' | 'e' | 'y')) => {
+                            self.scroll_key(ch, count);
+                            Mode::Normal
+                        }
+                        Key::Ctrl(ch @ ('a' | 'x')) => {
+                            let delta = count.unwrap_or(1) as i64;
+                            self.increment(if ch == 'a' { delta } else { -delta });
+                            Mode::Normal
+                        }
+                        Key::Ctrl('o') => {
+                            self.go_to_jump(count.unwrap_or(1) as i32);
+                            Mode::Normal
+                        }
+                        Key::Ctrl('i') => {
+                            self.go_to_jump(-(count.unwrap_or(1) as i32));
+                            Mode::Normal
+                        }
+                        Key::Ctrl('w') => {
+                            self.pending = Some(key);
+                            Mode::Normal
+                        }
+                        // Ctrl-^
+                        Key::Ctrl('6') => {
+                            let result = match self.alternate {
+                                Some(number) => self.switch_to(number, false),
+                                None => Err("No alternate file".to_string()),
+                            };
+                            if let Err(message) = result {
+                                self.message = Some(message);
+                            }
+                            Mode::Normal
+                        }
                         _ => Mode::Normal,
                     },
-                    Event::Mouse(me) => {
-                        if let MouseEvent::Press(_, x, y) = me {
-                            self.buffer.screen.cursor = Cursor {
-                                x: x as usize - 1,
-                                y: y as usize - 1,
-                            };
-                        };
+                    _ => Mode::Normal,
+                }
+            }
+            Mode::Insert => match evt {
+                Event::Key(key) if self.pending.is_some() => {
+                    if let (Some(Key::Ctrl('x')), Key::Ctrl(ch)) = (self.pending.take(), key) {
+                        if "flonp".contains(ch) {
+                            self.complete(ch, ch == 'p');
+                        }
+                    }
+                    Mode::Insert
+                }
+                Event::Key(key) if self.completion.is_some() && self.completion_key(key) => {
+                    Mode::Insert
+                }
+                Event::Key(key) => match key {
+                    Key::Esc => {
+                        let cursor = self.cursor();
+                        self.buffer.marks.set('^', cursor);
+                        self.window.screen.move_horiz(&self.buffer.text, 0);
                         Mode::Normal
                     }
-                    _ => Mode::Normal,
-                },
-                Mode::Insert => match evt {
-                    Event::Key(key) => match key {
-                        Key::Esc => {
-                            self.buffer.screen.move_horiz(&self.buffer.text, 0);
-                            Mode::Normal
+                    Key::Char('\n') => {
+                        self.break_line();
+                        Mode::Insert
+                    }
+                    Key::Char('\t') if self.buffer.options.expandtab => {
+                        let (line, column) = self.cursor();
+                        let tabstop = std::cmp::max(self.buffer.options.tabstop, 1);
+                        let columns = columns(&self.buffer.text[line][..column], tabstop);
+                        let blanks = tabstop - columns[column] % tabstop;
+                        self.buffer.text[line]
+                            .splice(column..column, std::iter::repeat_n(' ', blanks));
+                        self.buffer.invalidate(line);
+                        self.window.screen.cursor.x += blanks;
+                        Mode::Insert
+                    }
+                    Key::Char(ch) => {
+                        self.buffer.text
+                            [self.window.screen.cursor.y + self.window.screen.row_offset]
+                            .insert(self.window.screen.cursor.x, ch);
+                        self.buffer.invalidate(
+                            self.window.screen.cursor.y + self.window.screen.row_offset,
+                        );
+                        self.window.screen.cursor.x += 1;
+                        Mode::Insert
+                    }
+                    Key::Ctrl(ch) => match ch {
+                        'n' | 'p' => {
+                            self.complete(ch, ch == 'p');
+                            Mode::Insert
                         }
-                        Key::Char('\n') => {
-                            let right_of_cursor_text = self.buffer.text
-                                [self.buffer.screen.cursor.y + self.buffer.screen.row_offset]
-                                .split_off(self.buffer.screen.cursor.x);
-                            self.buffer.text.insert(
-                                self.buffer.screen.cursor.y + self.buffer.screen.row_offset + 1,
-                                right_of_cursor_text,
-                            );
-                            self.buffer.screen.move_vert(&self.buffer.text, 1);
-                            self.buffer.screen.cursor.x = 0;
-                            rewrite_all_lines = true;
+                        'x' => {
+                            self.pending = Some(key);
                             Mode::Insert
                         }
-                        Key::Char(ch) => {
+                        'u' => {
                             self.buffer.text
-                                [self.buffer.screen.cursor.y + self.buffer.screen.row_offset]
-                                .insert(self.buffer.screen.cursor.x, ch);
-                            self.buffer.screen.cursor.x += 1;
-                            line_to_rewrite = Some(self.buffer.screen.cursor.y);
+                                [self.window.screen.cursor.y + self.window.screen.row_offset] =
+                                self.buffer.text
+                                    [self.window.screen.cursor.y + self.window.screen.row_offset]
+                                    .split_off(self.window.screen.cursor.x);
+                            self.buffer.invalidate(
+                                self.window.screen.cursor.y + self.window.screen.row_offset,
+                            );
+                            self.window.screen.cursor.x = 0;
                             Mode::Insert
                         }
-                        Key::Ctrl(ch) => match ch {
-                            'u' => {
-                                self.buffer.text
-                                    [self.buffer.screen.cursor.y + self.buffer.screen.row_offset] =
-                                    self.buffer.text[self.buffer.screen.cursor.y
-                                        + self.buffer.screen.row_offset]
-                                        .split_off(self.buffer.screen.cursor.x);
-                                self.buffer.screen.cursor.x = 0;
-                                line_to_rewrite = Some(self.buffer.screen.cursor.y);
-                                Mode::Insert
-                            }
-                            'a' => {
-                                self.buffer.screen.cursor.x = 0;
-                                Mode::Insert
-                            }
-                            'e' => {
-                                self.buffer.screen.cursor.x = self.buffer.text
-                                    [self.buffer.screen.cursor.y + self.buffer.screen.row_offset]
-                                    .len();
-                                Mode::Insert
-                            }
-                            'h' if self.buffer.screen.cursor.x >= 1 => {
-                                self.buffer.text
-                                    [self.buffer.screen.cursor.y + self.buffer.screen.row_offset]
-                                    .remove(self.buffer.screen.cursor.x - 1);
-                                self.buffer.screen.cursor.x -= 1;
-                                line_to_rewrite = Some(self.buffer.screen.cursor.y);
-                                Mode::Insert
-                            }
-                            _ => Mode::Insert,
-                        },
-                        Key::Backspace if self.buffer.screen.cursor.x >= 1 => {
+                        'a' => {
+                            self.window.screen.cursor.x = 0;
+                            Mode::Insert
+                        }
+                        'e' => {
+                            self.window.screen.cursor.x = self.buffer.text
+                                [self.window.screen.cursor.y + self.window.screen.row_offset]
+                                .len();
+                            Mode::Insert
+                        }
+                        'h' if self.window.screen.cursor.x >= 1 => {
                             self.buffer.text
-                                [self.buffer.screen.cursor.y + self.buffer.screen.row_offset]
-                                .remove(self.buffer.screen.cursor.x - 1);
-                            self.buffer.screen.cursor.x -= 1;
-                            line_to_rewrite = Some(self.buffer.screen.cursor.y);
+                                [self.window.screen.cursor.y + self.window.screen.row_offset]
+                                .remove(self.window.screen.cursor.x - 1);
+                            self.buffer.invalidate(
+                                self.window.screen.cursor.y + self.window.screen.row_offset,
+                            );
+                            self.window.screen.cursor.x -= 1;
                             Mode::Insert
                         }
                         _ => Mode::Insert,
                     },
-                    Event::Mouse(me) => {
-                        if let MouseEvent::Press(_, x, y) = me {
-                            self.buffer.screen.cursor = Cursor {
-                                x: x as usize - 1,
-                                y: y as usize - 1,
-                            };
-                        }
+                    Key::Backspace if self.window.screen.cursor.x >= 1 => {
+                        self.buffer.text
+                            [self.window.screen.cursor.y + self.window.screen.row_offset]
+                            .remove(self.window.screen.cursor.x - 1);
+                        self.buffer.invalidate(
+                            self.window.screen.cursor.y + self.window.screen.row_offset,
+                        );
+                        self.window.screen.cursor.x -= 1;
                         Mode::Insert
                     }
                     _ => Mode::Insert,
                 },
-                Mode::Command(mut command_buffer) => match evt {
-                    Event::Key(key) => match key {
-                        Key::Esc => Mode::Normal,
-                        Key::Char('\n') => match command_buffer.as_str() {
-                            "q" => break,
-                            "w" => {
-                                match save_to_file(&self.filepath, &self.buffer.text) {
-                                    Ok(_) => error_message = Some("Save complete".to_string()),
-                                    Err(why) => {
-                                        error_message = Some(
-                                            ["Save failed! reason", why.to_string().as_str()]
-                                                .join(": "),
-                                        )
-                                    }
-                                }
-                                Mode::Normal
-                            }
-                            "" => Mode::Normal,
-                            _ => {
-                                error_message = Some("Sorry, that command is not implemented for now! Go back to Normal mode with C-c.".to_string());
-                                Mode::Command(String::new())
-                            }
-                        },
-                        Key::Char(key) => {
-                            command_buffer.push(key);
-                            Mode::Command(command_buffer)
+                _ => Mode::Insert,
+            },
+            Mode::Replace(replaced) => match evt {
+                Event::Key(key) => self.replace_key(key, replaced),
+                _ => Mode::Replace(replaced),
+            },
+            mode @ (Mode::Visual | Mode::VisualLine) => match evt {
+                Event::Key(key) => {
+                    let (start, end) = self.selection();
+                    let mode = self.visual_key(key, mode);
+                    if !matches!(mode, Mode::Visual | Mode::VisualLine) {
+                        self.buffer.marks.set('<', start);
+                        self.buffer.marks.set('>', end);
+                    }
+                    mode
+                }
+                _ => mode,
+            },
+            Mode::Command(mut command_buffer) => match evt {
+                Event::Key(key) => match key {
+                    Key::Esc => Mode::Normal,
+                    Key::Char('\n') => {
+                        match self.execute(&command_buffer) {
+                            Ok(message) if !self.silent => self.message = message,
+                            Ok(_) => (),
+                            Err(message) => self.message = Some(message),
                         }
-                        Key::Backspace => {
-                            if command_buffer.len() > 0 {
-                                command_buffer.pop();
-                                Mode::Command(command_buffer)
-                            } else {
-                                Mode::Normal
-                            }
+                        if self.quit {
+                            return false;
                         }
-                        Key::Ctrl(key) => match key {
-                            'c' => Mode::Normal,
-                            _ => Mode::Command(command_buffer),
-                        },
-                        _ => Mode::Command(command_buffer),
-                    },
+                        Mode::Normal
+                    }
+                    Key::Char(key) => {
+                        command_buffer.push(key);
+                        Mode::Command(command_buffer)
+                    }
+                    Key::Backspace => {
+                        if !command_buffer.is_empty() {
+                            command_buffer.pop();
+                            Mode::Command(command_buffer)
+                        } else {
+                            Mode::Normal
+                        }
+                    }
+                    Key::Ctrl('c') => Mode::Normal,
                     _ => Mode::Command(command_buffer),
                 },
-            };
-            if rewrite_all_lines {
-                self.buffer
-                    .text
-                    .rewrite_entire_screen(&mut self.io.stdout, self.buffer.screen.row_offset);
-            }
-            match line_to_rewrite {
-                Some(line) => self.buffer.text.rewrite_single_line(
-                    &mut self.io.stdout,
-                    line,
-                    self.buffer.screen.row_offset,
-                ),
-                None => (),
-            }
-            match error_message {
-                None => print_status(
-                    &mut self.io.stdout,
-                    &mode,
-                    vec![
-                        mode.to_string(),
-                        (self.buffer.screen.cursor.y + self.buffer.screen.row_offset + 1)
-                            .to_string(),
-                        (self.buffer.screen.cursor.x + 1).to_string(),
-                    ],
-                ),
-                Some(message) => print_status(&mut self.io.stdout, &mode, vec![message]),
-            }
-            write!(
-                self.io.stdout,
-                "{}",
-                termion::cursor::Goto(
-                    self.buffer.screen.cursor.x as u16 + 1,
-                    self.buffer.screen.cursor.y as u16 + 1
-                )
-            )
-            .unwrap();
-            self.io.stdout.flush().unwrap();
+                _ => Mode::Command(command_buffer),
+            },
+        };
+        self.note_change();
+        self.filter_completion();
+        true
+    }
+}
+
+/// Split the contents of a file into lines. There is always at least one line.
+fn text_from(contents: &str) -> TextState {
+    let mut text: TextState = contents.lines().map(|x| x.chars().collect()).collect();
+    if text.is_empty() {
+        text.push(Vec::new());
+    }
+    text
+}
+
+/// The characters around `column` which are alike: letters, digits and underscores,
+/// blanks, or other characters, as a double click selects them.
+fn word_at(line: &[char], column: usize) -> std::ops::Range<usize> {
+    let class = |c: char| {
+        if c.is_alphanumeric() || c == '_' {
+            2
+        } else if c.is_whitespace() {
+            0
+        } else {
+            1
         }
+    };
+    if column >= line.len() {
+        return column..column;
     }
+    let kind = class(line[column]);
+    let start = line[..column]
+        .iter()
+        .rposition(|&c| class(c) != kind)
+        .map_or(0, |i| i + 1);
+    let end = line[column..]
+        .iter()
+        .position(|&c| class(c) != kind)
+        .map_or(line.len(), |i| column + i);
+    start..end
 }
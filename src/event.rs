@@ -0,0 +1,179 @@
+use std::io::Read;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use termion::event::Event;
+use termion::input::TermReadEventsAndRaw;
+
+/// Everything the event loop reacts to.
+pub enum EditorEvent {
+    Input(Event),
+    /// The terminal was resized to the given number of columns and rows.
+    Resize(u16, u16),
+    /// Nothing happened within the time waited for, e.g. `timeoutlen` after a key
+    /// which may start a mapping.
+    Timeout,
+    /// Text pasted into the terminal, with line breaks as `\n`.
+    Paste(String),
+    /// A language server sent a message.
+    Lsp,
+}
+
+/// What the terminal sends before and after pasted text in bracketed paste mode.
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// Gathers the bytes of pasted text, which come in as events of their own.
+#[derive(Default)]
+struct Paste {
+    /// The bytes since the start of a paste, if one is going on.
+    bytes: Option<Vec<u8>>,
+}
+
+impl Paste {
+    /// Take an event read with the bytes it was read from. Returns what to deliver: the
+    /// event itself, or all of the pasted text once it ends.
+    fn feed(&mut self, event: Event, raw: Vec<u8>) -> Option<EditorEvent> {
+        let bytes = match &mut self.bytes {
+            None if raw == PASTE_START => {
+                self.bytes = Some(Vec::new());
+                return None;
+            }
+            None => return Some(EditorEvent::Input(event)),
+            Some(bytes) => bytes,
+        };
+        // The end is looked for in the bytes, as an escape character in the pasted text
+        // may be read together with the start of it.
+        bytes.extend(raw);
+        if !bytes.ends_with(PASTE_END) {
+            return None;
+        }
+        let mut bytes = self.bytes.take().unwrap();
+        bytes.truncate(bytes.len() - PASTE_END.len());
+        let text = String::from_utf8_lossy(&bytes)
+            .replace("\r\n", "\n")
+            .replace('\r', "\n");
+        Some(EditorEvent::Paste(text))
+    }
+}
+
+/// Write end of the pipe the SIGWINCH handler signals through, or -1.
+static RESIZE_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn on_sigwinch(_: libc::c_int) {
+    let fd = RESIZE_PIPE.load(Ordering::Relaxed);
+    if fd >= 0 {
+        // Only async-signal-safe calls are allowed here.
+        unsafe {
+            libc::write(fd, [0u8].as_ptr() as *const libc::c_void, 1);
+        }
+    }
+}
+
+/// A sender other threads can deliver events through, as long as there is input.
+pub type Waker = Arc<Mutex<Option<Sender<EditorEvent>>>>;
+
+/// Deliver keys and mouse events read from `reader`, and terminal resizes, through
+/// one channel. The channel is closed when `reader` reaches its end, once the senders
+/// taken from the returned waker are gone.
+pub fn spawn_event_sources<R>(reader: R) -> (Receiver<EditorEvent>, Waker)
+where
+    R: Read + Send + 'static,
+{
+    let (sender, receiver) = channel();
+    watch_resize(sender.clone());
+    let waker = Arc::new(Mutex::new(Some(sender.clone())));
+    let reader_waker = Arc::clone(&waker);
+    thread::spawn(move || {
+        let mut paste = Paste::default();
+        for event in reader.events_and_raw() {
+            match event {
+                Ok((event, raw)) => {
+                    let event = match paste.feed(event, raw) {
+                        Some(event) => event,
+                        None => continue,
+                    };
+                    if sender.send(event).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+        // Let the resize watcher finish too, so that the channel is closed.
+        reader_waker.lock().unwrap().take();
+        let fd = RESIZE_PIPE.swap(-1, Ordering::Relaxed);
+        if fd >= 0 {
+            unsafe {
+                libc::close(fd);
+            }
+        }
+    });
+    (receiver, waker)
+}
+
+/// Install a SIGWINCH handler and send the new terminal size whenever it fires.
+fn watch_resize(sender: Sender<EditorEvent>) {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return;
+    }
+    RESIZE_PIPE.store(fds[1], Ordering::Relaxed);
+    unsafe {
+        libc::signal(
+            libc::SIGWINCH,
+            on_sigwinch as *const () as libc::sighandler_t,
+        );
+    }
+    thread::spawn(move || {
+        let mut byte = [0u8; 1];
+        loop {
+            let read = unsafe { libc::read(fds[0], byte.as_mut_ptr() as *mut libc::c_void, 1) };
+            if read < 0 && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted
+            {
+                continue;
+            }
+            if read <= 0 {
+                break;
+            }
+            if let Ok((width, height)) = termion::terminal_size() {
+                if sender.send(EditorEvent::Resize(width, height)).is_err() {
+                    break;
+                }
+            }
+        }
+        unsafe {
+            libc::close(fds[0]);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use termion::event::Key;
+
+    #[test]
+    fn test_paste() {
+        let input: &[u8] = b"a\x1b[200~x\r\ny\x1b\x1b[201~b";
+        let mut paste = Paste::default();
+        let events: Vec<EditorEvent> = input
+            .events_and_raw()
+            .filter_map(|event| {
+                let (event, raw) = event.unwrap();
+                paste.feed(event, raw)
+            })
+            .collect();
+        assert_eq!(events.len(), 3);
+        assert!(matches!(
+            &events[0],
+            EditorEvent::Input(Event::Key(Key::Char('a')))
+        ));
+        assert!(matches!(&events[1], EditorEvent::Paste(text) if text == "x\ny\x1b"));
+        assert!(matches!(
+            &events[2],
+            EditorEvent::Input(Event::Key(Key::Char('b')))
+        ));
+    }
+}
@@ -0,0 +1,349 @@
+use termion::event::Key;
+
+/// The modes a mapping applies in.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MapMode {
+    Normal,
+    Insert,
+    Visual,
+    Command,
+}
+
+impl MapMode {
+    fn letter(self) -> char {
+        match self {
+            MapMode::Normal => 'n',
+            MapMode::Insert => 'i',
+            MapMode::Visual => 'v',
+            MapMode::Command => 'c',
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MapKind {
+    /// `:map`: the keys of the right hand side are mapped again.
+    Map,
+    /// `:noremap`
+    Noremap,
+    /// `:unmap`
+    Unmap,
+}
+
+/// Mapping commands with their abbreviations.
+const MAP_COMMANDS: &[(&str, &str, MapMode, MapKind)] = &[
+    ("map", "map", MapMode::Normal, MapKind::Map),
+    ("nmap", "nm", MapMode::Normal, MapKind::Map),
+    ("imap", "im", MapMode::Insert, MapKind::Map),
+    ("vmap", "vm", MapMode::Visual, MapKind::Map),
+    ("xmap", "xm", MapMode::Visual, MapKind::Map),
+    ("cmap", "cm", MapMode::Command, MapKind::Map),
+    ("noremap", "no", MapMode::Normal, MapKind::Noremap),
+    ("nnoremap", "nn", MapMode::Normal, MapKind::Noremap),
+    ("inoremap", "ino", MapMode::Insert, MapKind::Noremap),
+    ("vnoremap", "vn", MapMode::Visual, MapKind::Noremap),
+    ("xnoremap", "xn", MapMode::Visual, MapKind::Noremap),
+    ("cnoremap", "cno", MapMode::Command, MapKind::Noremap),
+    ("unmap", "unm", MapMode::Normal, MapKind::Unmap),
+    ("nunmap", "nun", MapMode::Normal, MapKind::Unmap),
+    ("iunmap", "iu", MapMode::Insert, MapKind::Unmap),
+    ("vunmap", "vu", MapMode::Visual, MapKind::Unmap),
+    ("xunmap", "xu", MapMode::Visual, MapKind::Unmap),
+    ("cunmap", "cu", MapMode::Command, MapKind::Unmap),
+];
+
+/// The mode and the kind of a mapping command such as `nnoremap`, or `None` if
+/// `name` is not one.
+pub fn map_command(name: &str) -> Option<(MapMode, MapKind)> {
+    MAP_COMMANDS
+        .iter()
+        .find(|(full, short, _, _)| name == *full || name == *short)
+        .map(|&(_, _, mode, kind)| (mode, kind))
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Mapping {
+    pub mode: MapMode,
+    pub lhs: Vec<Key>,
+    pub rhs: Vec<Key>,
+    pub noremap: bool,
+    /// Messages of the commands run by the mapping are not shown.
+    pub silent: bool,
+}
+
+impl Mapping {
+    /// A line of the `:map` listing, e.g. `n  <Leader>w   * :w<CR>`.
+    /// `@` marks buffer-local mappings.
+    pub fn describe(&self, buffer: bool) -> String {
+        format!(
+            "{}  {:<12} {}{}{}",
+            self.mode.letter(),
+            format_keys(&self.lhs).replace(' ', "<Space>"),
+            if self.noremap { '*' } else { ' ' },
+            if buffer { '@' } else { ' ' },
+            format_keys(&self.rhs)
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct Keymap {
+    mappings: Vec<Mapping>,
+}
+
+impl Keymap {
+    /// Add a mapping, replacing the one with the same keys in the same mode.
+    pub fn map(&mut self, mapping: Mapping) {
+        self.mappings
+            .retain(|m| m.mode != mapping.mode || m.lhs != mapping.lhs);
+        self.mappings.push(mapping);
+    }
+
+    /// Remove a mapping. Returns whether there was one.
+    pub fn unmap(&mut self, mode: MapMode, lhs: &[Key]) -> bool {
+        let count = self.mappings.len();
+        self.mappings.retain(|m| m.mode != mode || m.lhs != lhs);
+        self.mappings.len() != count
+    }
+
+    /// Mappings of the mode whose keys start with `prefix`.
+    pub fn list(&self, mode: MapMode, prefix: &[Key]) -> impl Iterator<Item = &Mapping> {
+        let prefix = prefix.to_vec();
+        self.mappings
+            .iter()
+            .filter(move |m| m.mode == mode && m.lhs.starts_with(&prefix))
+    }
+}
+
+/// Result of matching typed keys against the mappings.
+#[derive(PartialEq, Debug)]
+pub enum Lookup<'a> {
+    /// The keys start a longer mapping; wait for more of them.
+    Incomplete,
+    /// The mapping whose keys begin the typed keys.
+    Mapped(&'a Mapping),
+    Unmapped,
+}
+
+/// Match `keys` against the mappings of the keymaps, the first keymap taking precedence.
+/// When `complete` is set no more keys will come, so the longest mapping found is taken
+/// even if the keys could become a longer one.
+pub fn lookup<'a>(
+    keymaps: &[&'a Keymap],
+    mode: MapMode,
+    keys: &[Key],
+    complete: bool,
+) -> Lookup<'a> {
+    let mappings = || {
+        keymaps
+            .iter()
+            .flat_map(|keymap| keymap.mappings.iter())
+            .filter(move |m| m.mode == mode && !m.lhs.is_empty())
+    };
+    if !complete && mappings().any(|m| m.lhs.len() > keys.len() && m.lhs.starts_with(keys)) {
+        return Lookup::Incomplete;
+    }
+    let mut found: Option<&Mapping> = None;
+    for mapping in mappings() {
+        if keys.starts_with(&mapping.lhs) && found.is_none_or(|f| mapping.lhs.len() > f.lhs.len()) {
+            found = Some(mapping);
+        }
+    }
+    match found {
+        Some(mapping) => Lookup::Mapped(mapping),
+        None => Lookup::Unmapped,
+    }
+}
+
+/// Parse key notation such as `<C-w>j` or `:w<CR>`. `<Leader>` becomes `leader`.
+pub fn parse_keys(notation: &str, leader: &str) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut rest = notation;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some(end) = rest.find('>') {
+                let name = &rest[1..end];
+                let named = if name.eq_ignore_ascii_case("leader") {
+                    Some(leader.chars().map(Key::Char).collect())
+                } else {
+                    named_key(name).map(|key| key.into_iter().collect())
+                };
+                if let Some(named) = named {
+                    keys.extend::<Vec<Key>>(named);
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+            }
+        }
+        keys.push(match c {
+            '\r' => Key::Char('\n'),
+            '\x1b' => Key::Esc,
+            c => Key::Char(c),
+        });
+        rest = &rest[c.len_utf8()..];
+    }
+    keys
+}
+
+/// The key for a name between `<` and `>`. `<Nop>` is no key at all.
+fn named_key(name: &str) -> Option<Option<Key>> {
+    let lower = name.to_ascii_lowercase();
+    let key = match lower.as_str() {
+        "nop" => return Some(None),
+        "esc" => Key::Esc,
+        "cr" | "enter" | "return" | "nl" => Key::Char('\n'),
+        "tab" => Key::Char('\t'),
+        "bs" => Key::Backspace,
+        "del" => Key::Delete,
+        "space" => Key::Char(' '),
+        "lt" => Key::Char('<'),
+        "bar" => Key::Char('|'),
+        "bslash" => Key::Char('\\'),
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        "insert" => Key::Insert,
+        _ => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next(), chars.next(), chars.next()) {
+                (Some(m), Some('-'), Some(c), None) => match m.to_ascii_lowercase() {
+                    // termion reports Ctrl-^ as Ctrl-6.
+                    'c' if c == '^' => Key::Ctrl('6'),
+                    'c' => Key::Ctrl(c.to_ascii_lowercase()),
+                    'a' | 'm' => Key::Alt(c),
+                    _ => return None,
+                },
+                (Some('f') | Some('F'), ..) => Key::F(lower[1..].parse().ok()?),
+                _ => return None,
+            }
+        }
+    };
+    Some(Some(key))
+}
+
+/// Key notation for the keys, the inverse of `parse_keys`.
+pub fn format_keys(keys: &[Key]) -> String {
+    keys.iter()
+        .map(|key| match key {
+            Key::Char('\n') => "<CR>".to_string(),
+            Key::Char('\t') => "<Tab>".to_string(),
+            Key::Char('<') => "<lt>".to_string(),
+            Key::Char(c) => c.to_string(),
+            Key::Esc => "<Esc>".to_string(),
+            Key::Backspace => "<BS>".to_string(),
+            Key::Delete => "<Del>".to_string(),
+            Key::Up => "<Up>".to_string(),
+            Key::Down => "<Down>".to_string(),
+            Key::Left => "<Left>".to_string(),
+            Key::Right => "<Right>".to_string(),
+            Key::Home => "<Home>".to_string(),
+            Key::End => "<End>".to_string(),
+            Key::PageUp => "<PageUp>".to_string(),
+            Key::PageDown => "<PageDown>".to_string(),
+            Key::Insert => "<Insert>".to_string(),
+            Key::F(n) => format!("<F{}>", n),
+            Key::Ctrl('6') => "<C-^>".to_string(),
+            Key::Ctrl(c) => format!("<C-{}>", c),
+            Key::Alt(c) => format!("<M-{}>", c),
+            _ => String::new(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(lhs: &str, rhs: &str) -> Mapping {
+        Mapping {
+            mode: MapMode::Normal,
+            lhs: parse_keys(lhs, "\\"),
+            rhs: parse_keys(rhs, "\\"),
+            noremap: true,
+            silent: false,
+        }
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        assert_eq!(
+            parse_keys("<Leader>w:w<CR><c-W>j<lt><Nop><F5><bogus>", ","),
+            [
+                Key::Char(','),
+                Key::Char('w'),
+                Key::Char(':'),
+                Key::Char('w'),
+                Key::Char('\n'),
+                Key::Ctrl('w'),
+                Key::Char('j'),
+                Key::Char('<'),
+                Key::F(5),
+                Key::Char('<'),
+                Key::Char('b'),
+                Key::Char('o'),
+                Key::Char('g'),
+                Key::Char('u'),
+                Key::Char('s'),
+                Key::Char('>'),
+            ]
+        );
+        assert_eq!(format_keys(&parse_keys("<C-w>j <Esc>", "")), "<C-w>j <Esc>");
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut global = Keymap::default();
+        global.map(mapping("gh", "0"));
+        global.map(mapping("ghi", "$"));
+        let mut local = Keymap::default();
+        local.map(mapping("gh", "j"));
+        let keymaps = [&local, &global];
+        let keys = parse_keys("gh", "");
+        assert_eq!(
+            lookup(&keymaps, MapMode::Normal, &keys, false),
+            Lookup::Incomplete
+        );
+        assert_eq!(
+            lookup(&keymaps, MapMode::Normal, &keys, true),
+            Lookup::Mapped(&local.mappings[0])
+        );
+        let keys = parse_keys("ghix", "");
+        assert_eq!(
+            lookup(&keymaps, MapMode::Normal, &keys, false),
+            Lookup::Mapped(&global.mappings[1])
+        );
+        assert_eq!(
+            lookup(&keymaps, MapMode::Insert, &keys, false),
+            Lookup::Unmapped
+        );
+        assert!(global.unmap(MapMode::Normal, &parse_keys("ghi", "")));
+        assert!(!global.unmap(MapMode::Normal, &parse_keys("ghi", "")));
+    }
+
+    #[test]
+    fn test_map_command() {
+        assert_eq!(
+            map_command("nnoremap"),
+            Some((MapMode::Normal, MapKind::Noremap))
+        );
+        assert_eq!(
+            map_command("noremap"),
+            Some((MapMode::Normal, MapKind::Noremap))
+        );
+        assert_eq!(
+            map_command("ino"),
+            Some((MapMode::Insert, MapKind::Noremap))
+        );
+        assert_eq!(
+            map_command("cunmap"),
+            Some((MapMode::Command, MapKind::Unmap))
+        );
+        assert_eq!(map_command("map"), Some((MapMode::Normal, MapKind::Map)));
+        assert_eq!(map_command("nohlsearch"), None);
+    }
+}
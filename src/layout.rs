@@ -0,0 +1,276 @@
+use crate::screen::*;
+use crate::syntax::*;
+use crate::text::*;
+use std::cmp;
+use std::ops::Range;
+
+/// Characters after which `linebreak` may wrap a line, as in Vim's default `breakat`.
+const BREAKAT: &[char] = &[
+    ' ', '\t', '!', '@', '*', '-', '+', ';', ':', ',', '.', '/', '?',
+];
+
+#[derive(Clone)]
+pub struct WrapOptions {
+    pub wrap: bool,
+    pub linebreak: bool,
+    pub showbreak: String,
+    /// Minimal number of columns to scroll horizontally with `nowrap`.
+    /// Zero puts the cursor in the middle of the screen.
+    pub sidescroll: usize,
+    /// Minimal number of columns to keep left and right of the cursor with `nowrap`.
+    pub sidescrolloff: usize,
+    /// Tabs reach the next multiple of this many columns. The `tabstop` of the buffer.
+    pub tabstop: usize,
+}
+
+impl Default for WrapOptions {
+    fn default() -> Self {
+        WrapOptions {
+            wrap: true,
+            linebreak: false,
+            showbreak: String::new(),
+            sidescroll: 0,
+            sidescrolloff: 0,
+            tabstop: 8,
+        }
+    }
+}
+
+/// The column of a line where each character starts, counted from the start of the line,
+/// and the column past its end. Tabs reach the next multiple of `tabstop`.
+pub fn columns(line: &[char], tabstop: usize) -> Vec<usize> {
+    let tabstop = cmp::max(tabstop, 1);
+    let mut columns = Vec::with_capacity(line.len() + 1);
+    let mut column = 0;
+    for &c in line {
+        columns.push(column);
+        column += if c == '\t' {
+            tabstop - column % tabstop
+        } else {
+            1
+        };
+    }
+    columns.push(column);
+    columns
+}
+
+/// The cells of a screen row showing the characters `range` of a line whose `columns`
+/// are given, where each character starts and where the last one ends.
+fn cells_of(columns: &[usize], range: &Range<usize>) -> Vec<usize> {
+    let start = columns[range.start];
+    columns[range.start..=range.end]
+        .iter()
+        .map(|column| column - start)
+        .collect()
+}
+
+/// The character of the `range` of a line drawn in cell `x` of a screen row showing it,
+/// or the end of `range` past its last character.
+pub fn char_at(columns: &[usize], range: &Range<usize>, x: usize) -> usize {
+    let start = columns[range.start];
+    (range.start..range.end)
+        .find(|&i| columns[i + 1] - start > x)
+        .unwrap_or(range.end)
+}
+
+/// Split a line into the ranges of characters shown on each screen row.
+/// Continuation rows are narrower by the width of `showbreak`. A row takes at least one
+/// character, even a tab wider than the row.
+pub fn wrap_line(line: &[char], width: usize, options: &WrapOptions) -> Vec<Range<usize>> {
+    let columns = columns(line, options.tabstop);
+    if !options.wrap || columns[line.len()] <= width {
+        return std::iter::once(0..line.len()).collect();
+    }
+    let continued_width = width
+        .saturating_sub(options.showbreak.chars().count())
+        .max(1);
+    let mut segments = Vec::new();
+    let mut start = 0;
+    while start < line.len() {
+        let capacity = if start == 0 { width } else { continued_width };
+        let mut end = start + 1;
+        while end < line.len() && columns[end + 1] - columns[start] <= capacity {
+            end += 1;
+        }
+        if options.linebreak && end < line.len() {
+            if let Some(i) = line[start..end].iter().rposition(|c| BREAKAT.contains(c)) {
+                if i > 0 {
+                    end = start + i + 1;
+                }
+            }
+        }
+        segments.push(start..end);
+        start = end;
+    }
+    segments
+}
+
+/// Number of screen rows the line occupies.
+pub fn line_height(line: &[char], width: usize, options: &WrapOptions) -> usize {
+    wrap_line(line, width, options).len()
+}
+
+/// Index of the segment the column `x` is drawn in.
+/// The position just past the end of the line belongs to the last segment.
+pub fn segment_of(segments: &[Range<usize>], x: usize) -> usize {
+    segments
+        .iter()
+        .position(|segment| x < segment.end)
+        .unwrap_or(segments.len() - 1)
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ScreenLine {
+    pub line: usize,
+    pub range: Range<usize>,
+    pub continuation: bool,
+    /// Label for the line number gutter; blank on continuation rows.
+    pub number: String,
+    /// With `nowrap`, the line continues left of the screen and `<` is drawn.
+    pub precedes: bool,
+    /// With `nowrap`, the line continues right of the screen and `>` is drawn.
+    pub extends: bool,
+    /// The cell where each character of `range` starts and where the last one ends,
+    /// right of the gutter and `showbreak`. Tabs take several cells.
+    pub cells: Vec<usize>,
+    /// Syntax highlighting of the characters in `range`.
+    pub tokens: Vec<Token>,
+}
+
+/// The tokens which overlap `range`, clipped to it.
+fn clip_tokens(tokens: &[Token], range: &Range<usize>) -> Vec<Token> {
+    tokens
+        .iter()
+        .filter(|token| token.range.start < range.end && range.start < token.range.end)
+        .map(|token| Token {
+            range: cmp::max(token.range.start, range.start)..cmp::min(token.range.end, range.end),
+            scope: token.scope,
+        })
+        .collect()
+}
+
+/// What is drawn on each text row of the terminal, starting from `row_offset`.
+pub struct Layout {
+    pub rows: Vec<Option<ScreenLine>>,
+    pub showbreak: String,
+}
+
+impl Layout {
+    pub fn new(text: &TextState, screen: &ScreenState, highlighter: Option<&Highlighter>) -> Self {
+        let tokens = |line: usize, range: &Range<usize>| match highlighter {
+            Some(highlighter) => clip_tokens(highlighter.tokens(line), range),
+            None => Vec::new(),
+        };
+        let height = screen.text_rows();
+        let width = screen.text_width(text);
+        let blank_number = " ".repeat(screen.gutter_width(text));
+        let mut rows = Vec::with_capacity(height);
+        let mut line = screen.row_offset;
+        while rows.len() < height && line < text.len() {
+            let columns = columns(&text[line], screen.wrap.tabstop);
+            if !screen.wrap.wrap {
+                let len = text[line].len();
+                let start = cmp::min(screen.col_offset, len);
+                let mut end = start;
+                while end < len && columns[end + 1] - columns[start] <= width {
+                    end += 1;
+                }
+                let range = start..end;
+                rows.push(Some(ScreenLine {
+                    line,
+                    tokens: tokens(line, &range),
+                    cells: cells_of(&columns, &range),
+                    range,
+                    continuation: false,
+                    number: screen.line_number(text, line),
+                    precedes: screen.col_offset > 0 && len > screen.col_offset,
+                    extends: end < len,
+                }));
+                line += 1;
+                continue;
+            }
+            for (i, range) in wrap_line(&text[line], width, &screen.wrap)
+                .into_iter()
+                .enumerate()
+            {
+                if rows.len() == height {
+                    break;
+                }
+                rows.push(Some(ScreenLine {
+                    line,
+                    tokens: tokens(line, &range),
+                    cells: cells_of(&columns, &range),
+                    range,
+                    continuation: i > 0,
+                    number: if i > 0 {
+                        blank_number.clone()
+                    } else {
+                        screen.line_number(text, line)
+                    },
+                    precedes: false,
+                    extends: false,
+                }));
+            }
+            line += 1;
+        }
+        rows.resize(height, None);
+        Layout {
+            rows,
+            showbreak: screen.wrap.showbreak.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    #[test]
+    fn test_wrap_line() {
+        let options = WrapOptions::default();
+        assert_eq!(
+            wrap_line(&chars("abcdefgh"), 3, &options),
+            vec![0..3, 3..6, 6..8]
+        );
+        assert_eq!(line_height(&chars(""), 3, &options), 1);
+        let options = WrapOptions {
+            wrap: false,
+            ..Default::default()
+        };
+        assert_eq!(line_height(&chars("abcdefgh"), 3, &options), 1);
+    }
+
+    #[test]
+    fn test_linebreak_and_showbreak() {
+        let options = WrapOptions {
+            linebreak: true,
+            showbreak: "> ".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            wrap_line(&chars("foo bar baz"), 6, &options),
+            vec![0..4, 4..8, 8..11]
+        );
+        assert_eq!(segment_of(&[0..4, 4..8, 8..11], 11), 2);
+    }
+
+    #[test]
+    fn test_tabs() {
+        let line = chars("a\tbc\td");
+        let columns = columns(&line, 4);
+        assert_eq!(columns, [0, 1, 4, 5, 6, 8, 9]);
+        assert_eq!(char_at(&columns, &(0..6), 2), 1);
+        assert_eq!(char_at(&columns, &(0..6), 4), 2);
+        assert_eq!(char_at(&columns, &(0..6), 20), 6);
+        let options = WrapOptions {
+            tabstop: 4,
+            ..Default::default()
+        };
+        assert_eq!(wrap_line(&line, 5, &options), vec![0..3, 3..6]);
+        assert_eq!(wrap_line(&chars("\t\t"), 3, &options), vec![0..1, 1..2]);
+    }
+}
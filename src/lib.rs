@@ -1,31 +1,910 @@
+pub mod complete;
+pub mod diff;
 pub mod editor;
+pub mod event;
+pub mod json;
+pub mod keymap;
+pub mod layout;
+pub mod lsp;
+pub mod mark;
+pub mod motion;
+pub mod options;
+pub mod render;
 pub mod screen;
+pub mod syntax;
+pub mod terminal;
 pub mod text;
+pub mod textobject;
+pub mod theme;
 pub mod util;
-use std::fs;
-use std::io::{BufRead, Error, Write};
-use termion;
-use termion::event::{Event, Key, MouseEvent};
-use termion::input::TermRead;
+pub mod window;
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-    // use std::io::{stdin, stdout, Stdout};
-    // use termion::input::MouseTerminal;
-    // use termion::raw::IntoRawMode;
-    // use termion::screen::AlternateScreen;
-    #[test]
-    fn test_basic_cursor_move() {}
-    // fn test_basic_cursor_move() {
-    //     let config = Config {
-    //         filepath: "./test.txt".to_string(),
-    //     };
-    //     let stdio = stdin();
-    //     let input = stdio.lock();
-    //     // let output = stdout();
-    //     let mut output =
-    //         AlternateScreen::from(MouseTerminal::from(stdout().into_raw_mode().unwrap()));
-    //     let editor = EditorState::new("w".into(), output, config);
-    // }
+    use crate::editor::Editor;
+    use crate::event::EditorEvent;
+    use crate::terminal::VirtualTerminal;
+    use crate::theme::{Color, Style};
+    use crate::util::Config;
+    use std::ops::{Deref, DerefMut};
+    use std::path::PathBuf;
+    use termion::event::{Event, MouseButton, MouseEvent};
+
+    /// A file in the temporary directory, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("rim-{}-{}", std::process::id(), name));
+            std::fs::write(&path, contents).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// An editor of a temporary file, which is removed with it.
+    struct Fixture {
+        editor: Editor<VirtualTerminal>,
+        _file: TempFile,
+    }
+
+    impl Deref for Fixture {
+        type Target = Editor<VirtualTerminal>;
+        fn deref(&self) -> &Self::Target {
+            &self.editor
+        }
+    }
+
+    impl DerefMut for Fixture {
+        fn deref_mut(&mut self) -> &mut Self::Target {
+            &mut self.editor
+        }
+    }
+
+    fn editor(name: &str, contents: &str) -> Fixture {
+        let file = TempFile::new(name, contents);
+        let config = Config {
+            files: vec![file.0.to_str().unwrap().to_string()],
+            ..Default::default()
+        };
+        Fixture {
+            editor: Editor::new(VirtualTerminal::new(80, 5), config),
+            _file: file,
+        }
+    }
+
+    /// Type `keys` and let the editor handle them.
+    fn feed<'a>(
+        editor: &'a mut Editor<VirtualTerminal>,
+        keys: &str,
+    ) -> &'a Editor<VirtualTerminal> {
+        editor.terminal_mut().feed(keys);
+        editor.editor_loop();
+        editor
+    }
+
+    /// The lines of the text.
+    fn lines(editor: &Editor<VirtualTerminal>) -> Vec<String> {
+        editor
+            .text()
+            .iter()
+            .map(|line| line.iter().collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_basic_cursor_move() {
+        let mut editor = editor("cursor", "first\nsecond\nthird");
+        feed(&mut editor, "jjlk");
+        assert_eq!(editor.cursor(), (1, 1));
+        let terminal = editor.terminal();
+        assert_eq!(terminal.row_text(0), "first");
+        assert_eq!(terminal.row_text(2), "third");
+        assert_eq!(terminal.row_text(4), "NORMAL, 2, 2");
+        assert_eq!(terminal.cursor, (1, 1));
+    }
+
+    #[test]
+    fn test_insert_and_resize() {
+        let mut editor = editor("insert", "abc");
+        editor.terminal_mut().feed("ixy\x1b");
+        editor.terminal_mut().resize(15, 3);
+        editor.editor_loop();
+        assert_eq!(editor.text()[0], "xyabc".chars().collect::<Vec<char>>());
+        assert_eq!(editor.cursor(), (0, 2));
+        assert_eq!(editor.terminal().row_text(0), "xyabc");
+        assert_eq!(editor.terminal().row_text(2), "NORMAL, 1, 3");
+    }
+
+    #[test]
+    fn test_scrolled_screen_matches_text() {
+        let text: Vec<String> = (1..=10).map(|i| format!("line {}", i)).collect();
+        let mut editor = editor("scroll", &text.join("\n"));
+        feed(&mut editor, "jjjjjj$");
+        let terminal = editor.terminal();
+        let rows: Vec<String> = (0..4).map(|y| terminal.row_text(y)).collect();
+        assert_eq!(rows, ["line 4", "line 5", "line 6", "line 7"]);
+        assert_eq!(terminal.row_text(4), "NORMAL, 7, 6");
+        assert_eq!(terminal.cursor, (5, 3));
+        // `$` on an empty line stays at its start.
+        feed(&mut editor, "o\x1b$");
+        assert_eq!(editor.cursor(), (7, 0));
+        assert_eq!(editor.terminal().row_text(4), "NORMAL, 8, 1");
+    }
+
+    #[test]
+    fn test_wrapped_rows() {
+        let mut editor = editor("wrap", "aaaa bbbbbb cc\nx");
+        editor.terminal_mut().feed(":set sbr=+\r");
+        editor.terminal_mut().resize(10, 5);
+        editor.editor_loop();
+        let terminal = editor.terminal();
+        assert_eq!(terminal.row_text(0), "aaaa bbbbb");
+        assert_eq!(terminal.row_text(1), "+b cc");
+        assert_eq!(terminal.row_text(2), "x");
+        // `linebreak` wraps after the last blank which fits.
+        feed(&mut editor, ":set lbr\r$");
+        let terminal = editor.terminal();
+        assert_eq!(terminal.row_text(0), "aaaa");
+        assert_eq!(terminal.row_text(1), "+bbbbbb cc");
+        assert_eq!(terminal.cursor, (9, 1));
+        // `j` goes to the next line, `gk` to the row above.
+        feed(&mut editor, "gk");
+        assert_eq!(editor.cursor(), (0, 4));
+        feed(&mut editor, "j");
+        assert_eq!(editor.cursor(), (1, 0));
+        assert_eq!(editor.terminal().cursor, (0, 2));
+    }
+
+    #[test]
+    fn test_nowrap_markers() {
+        let mut editor = editor("nowrap", "0123456789abcdef\nxy");
+        editor.terminal_mut().feed(":set nowrap\r");
+        editor.terminal_mut().resize(10, 5);
+        editor.editor_loop();
+        assert_eq!(editor.terminal().row_text(0), "012345678>");
+        assert_eq!(editor.terminal().row_text(1), "xy");
+        feed(&mut editor, "lllllzs");
+        let terminal = editor.terminal();
+        assert_eq!(terminal.row_text(0), "<6789abcd>");
+        assert_eq!(terminal.row_text(1), "");
+        assert_eq!(terminal.cells[0][0].style, terminal.cells[0][9].style);
+        assert_ne!(terminal.cells[0][0].style, terminal.cells[0][1].style);
+        assert_eq!(terminal.cursor, (0, 0));
+        // With `sidescroll` zero, the cursor leaving the screen is put in its middle.
+        feed(&mut editor, "$");
+        assert_eq!(editor.terminal().row_text(0), "<bcdef");
+        assert_eq!(editor.terminal().cursor, (5, 0));
+    }
+
+    #[test]
+    fn test_number_gutter() {
+        let text: Vec<String> = (1..=1000).map(|i| format!("line {}", i)).collect();
+        let mut editor = editor("gutter", &text.join("\n"));
+        feed(&mut editor, ":set nu rnu\rjj");
+        let terminal = editor.terminal();
+        let rows: Vec<String> = (0..4).map(|y| terminal.row_text(y)).collect();
+        assert_eq!(
+            rows,
+            ["   2 line 1", "   1 line 2", "3    line 3", "   1 line 4"]
+        );
+        assert_eq!(terminal.cursor, (5, 2));
+        // Clicks are taken right of the gutter, and clicks on it go to the start of the line.
+        editor.terminal_mut().push_event(press(8, 2));
+        editor.editor_loop();
+        assert_eq!(editor.cursor(), (1, 2));
+        assert_eq!(editor.terminal().cursor, (7, 1));
+        assert_eq!(editor.terminal().row_text(0), "   1 line 1");
+        editor.terminal_mut().push_event(press(2, 4));
+        editor.editor_loop();
+        assert_eq!(editor.cursor(), (3, 0));
+        // The gutter is as wide as the largest number needs.
+        feed(&mut editor, ":set nonu\rG");
+        assert_eq!(editor.terminal().row_text(3), "   0 line 1000");
+        feed(&mut editor, "gg999dd");
+        assert_eq!(editor.terminal().row_text(0), "  0 line 1000");
+    }
+
+    #[test]
+    fn test_syntax_highlighting() {
+        let mut editor = editor("syntax.rs", "fn main() {}");
+        editor.editor_loop();
+        let terminal = editor.terminal();
+        let keyword = &terminal.cells[0][0].style;
+        assert_eq!(keyword.fg, Some(Color(0x91, 0xac, 0xd1)));
+        assert!(keyword.bold);
+        assert_eq!(terminal.cells[0][3].style.fg, Some(Color(0x89, 0xc6, 0xc6)));
+        assert_eq!(terminal.cells[0][2].style, Style::default());
+        // An edit highlights the line again.
+        feed(&mut editor, "I// \x1b");
+        let comment = &editor.terminal().cells[0][3].style;
+        assert_eq!(comment.fg, Some(Color(0x80, 0x80, 0x80)));
+        assert!(comment.italic);
+    }
+
+    #[test]
+    fn test_colorscheme() {
+        let mut editor = editor("colorscheme.rs", "fn main() {}");
+        feed(&mut editor, ":colorscheme light\r");
+        let keyword = &editor.terminal().cells[0][0].style;
+        assert_eq!(keyword.fg, Some(Color(0x2f, 0x5e, 0x98)));
+        feed(&mut editor, ":hi Keyword fg=#ff0000 style=NONE\r");
+        let keyword = &editor.terminal().cells[0][0].style;
+        assert_eq!(keyword.fg, Some(Color(0xff, 0, 0)));
+        assert!(!keyword.bold);
+        assert_eq!(
+            editor.terminal().cells[0][3].style.fg,
+            Some(Color(0x1f, 0x7a, 0x7a))
+        );
+    }
+
+    #[test]
+    fn test_set_and_source() {
+        let mut editor = editor("set", "one\ntwo");
+        let rimrc = TempFile::new("rimrc", "\" comment\nset nu ts=4\n:set bogus\n");
+        editor.source(&rimrc.0);
+        editor.editor_loop();
+        assert_eq!(editor.terminal().row_text(1), "  2 two");
+        assert_eq!(
+            editor.terminal().row_text(4),
+            format!("{}:3: Unknown option: bogus", rimrc.0.display())
+        );
+        feed(&mut editor, ":set ts? nu! rnu\r");
+        assert_eq!(editor.terminal().row_text(0), "  0 one");
+        assert_eq!(editor.terminal().row_text(4), "tabstop=4");
+        feed(&mut editor, ":set rnu&\r");
+        assert_eq!(editor.terminal().row_text(0), "one");
+        // Tabs reach the next multiple of `tabstop`.
+        feed(&mut editor, "Ia\tb\x1b");
+        assert_eq!(editor.terminal().row_text(0), "a   bone");
+        assert_eq!(editor.terminal().cursor, (5, 0));
+        feed(&mut editor, ":set ts=2\r");
+        assert_eq!(editor.terminal().row_text(0), "a bone");
+        feed(&mut editor, ":set et\rA\tx\x1b");
+        assert_eq!(editor.text()[0].iter().collect::<String>(), "a\tbone  x");
+        // Searches ignore case with `ignorecase`, unless `smartcase` sees a capital.
+        feed(&mut editor, ":/TWO\r");
+        assert_eq!(editor.terminal().row_text(4), "Pattern not found: TWO");
+        feed(&mut editor, ":set ic scs\r:/TWO\r");
+        assert_eq!(editor.terminal().row_text(4), "Pattern not found: TWO");
+        feed(&mut editor, ":/two\r");
+        assert_eq!(editor.cursor(), (1, 0));
+        feed(&mut editor, "gg:set noscs\r:/TWO\r");
+        assert_eq!(editor.cursor(), (1, 0));
+    }
+
+    #[test]
+    fn test_rimrc_sets_options_of_all_buffers() {
+        let first = editor("rimrc-1", "a\tb");
+        let second = editor("rimrc-2", "c\td");
+        let config = Config {
+            files: vec![
+                first.filepath().unwrap().to_string(),
+                second.filepath().unwrap().to_string(),
+            ],
+            ..Default::default()
+        };
+        let mut editor = Editor::new(VirtualTerminal::new(80, 5), config);
+        // The files are read before the rimrc, whose options the other buffer takes
+        // when it is shown.
+        let rimrc = TempFile::new("rimrc-options", "set ts=4 nu et\n");
+        editor.source(&rimrc.0);
+        feed(&mut editor, ":bn\r");
+        assert_eq!(editor.terminal().row_text(0), "  1 c   d");
+        feed(&mut editor, "A\t\x1b");
+        assert_eq!(lines(&editor), ["c\td   "]);
+        // Once shown, a buffer keeps its options.
+        feed(&mut editor, ":set ts=2\r:bp!\r");
+        assert_eq!(editor.terminal().row_text(0), "  1 a   b");
+    }
+
+    #[test]
+    fn test_mappings() {
+        let mut editor = editor("map", "1\n2\n3\n4");
+        editor
+            .terminal_mut()
+            .feed(":set mapleader=,\r:nnoremap <Leader>n :set nu<CR>\r,n");
+        editor.editor_loop();
+        assert_eq!(editor.terminal().row_text(0), "  1 1");
+        // A recursive mapping is mapped again, a non-recursive one is not.
+        editor
+            .terminal_mut()
+            .feed(":nmap Q jj\r:nmap X Q\r:nnoremap Y Q\rX");
+        editor.editor_loop();
+        assert_eq!(editor.cursor(), (2, 0));
+        feed(&mut editor, "Y");
+        assert_eq!(editor.cursor(), (2, 0));
+        // A buffer-local mapping takes precedence.
+        feed(&mut editor, ":nmap <buffer> X k\rX");
+        assert_eq!(editor.cursor(), (1, 0));
+        feed(&mut editor, ":nmap\r");
+        let terminal = editor.terminal();
+        assert_eq!(terminal.row_text(0), "n  X             @k");
+        assert_eq!(terminal.row_text(1), "n  ,n           * :set nu<CR>");
+        assert_eq!(
+            terminal.row_text(4),
+            "Press ENTER or type command to continue"
+        );
+        // The key dismissing the listing is not handled otherwise.
+        feed(&mut editor, "\r");
+        assert_eq!(editor.cursor(), (1, 0));
+        assert_eq!(editor.terminal().row_text(0), "  1 1");
+    }
+
+    #[test]
+    fn test_mapping_timeout_and_silent() {
+        let mut editor = editor("timeout", "");
+        editor
+            .terminal_mut()
+            .feed(":inoremap jk <Esc>\r:nnoremap <silent> S :set ts?<CR>\rijkS");
+        editor.editor_loop();
+        assert!(editor.text()[0].is_empty());
+        assert_eq!(editor.terminal().row_text(4), "NORMAL, 1, 1");
+        editor.terminal_mut().feed("ij");
+        editor.terminal_mut().push_event(EditorEvent::Timeout);
+        feed(&mut editor, "k\x1b");
+        assert_eq!(editor.text()[0], ['j', 'k']);
+        feed(&mut editor, ":nmap R R\rR");
+        assert_eq!(editor.terminal().row_text(4), "REPLACE, 1, 3");
+        feed(&mut editor, "\x1b:nunmap S\r:nunmap S\r");
+        assert_eq!(editor.terminal().row_text(4), "No such mapping");
+    }
+
+    #[test]
+    fn test_command_line_files() {
+        let first = editor(
+            "args-1",
+            "one
+  two
+three two",
+        );
+        let second = editor("args-2", "other");
+        let (first_path, second_path) = (first.filepath().unwrap(), second.filepath().unwrap());
+        let config = Config {
+            files: vec![first_path.to_string(), second_path.to_string()],
+            readonly: true,
+            ..Default::default()
+        };
+        let mut editor = Editor::new(VirtualTerminal::new(80, 5), config);
+        editor.run_commands(&["2".to_string(), "/two".to_string(), "bogus".to_string()]);
+        assert_eq!(editor.cursor(), (2, 6));
+        editor.editor_loop();
+        assert_eq!(
+            editor.terminal().row_text(4),
+            "Not an editor command: bogus"
+        );
+        feed(&mut editor, "x:w\r");
+        assert_eq!(
+            editor.terminal().row_text(4),
+            "'readonly' option is set (add ! to override)"
+        );
+        feed(&mut editor, ":next\r");
+        assert_eq!(
+            editor.terminal().row_text(4),
+            "No write since last change (add ! to override)"
+        );
+        feed(&mut editor, ":w!\r:next\r:args\r");
+        assert_eq!(editor.terminal().row_text(0), "other");
+        assert_eq!(
+            editor.terminal().row_text(4),
+            format!("{} [{}]", first_path, second_path)
+        );
+        assert_eq!(
+            std::fs::read_to_string(first_path).unwrap(),
+            "one\n  two\nthree wo"
+        );
+    }
+
+    #[test]
+    fn test_diff_mode() {
+        let first = editor("diff-1", "same\nchanged\nadded\nsame");
+        let second = editor("diff-2", "same\nother\nsame");
+        let config = Config {
+            files: vec![
+                first.filepath().unwrap().to_string(),
+                second.filepath().unwrap().to_string(),
+            ],
+            diff: true,
+            ..Default::default()
+        };
+        let mut editor = Editor::new(VirtualTerminal::new(81, 8), config);
+        editor.editor_loop();
+        let terminal = editor.terminal();
+        assert_eq!(terminal.row_text(0), format!("{:<40}|same", "same"));
+        let background = |x: usize, y: usize| terminal.cells[y][x].style.bg;
+        assert_eq!(background(0, 0), None);
+        assert!(background(0, 1).is_some());
+        assert!(background(0, 2).is_some());
+        assert_ne!(background(0, 1), background(0, 2));
+        assert_eq!(background(0, 3), None);
+        assert_eq!(background(41, 1), background(0, 1));
+        assert_eq!(background(41, 2), None);
+    }
+
+    #[test]
+    fn test_buffers() {
+        let mut editor = editor("buffers-1", "one");
+        let first = editor.filepath().unwrap().to_string();
+        let other = self::editor("buffers-2", "two");
+        let second = other.filepath().unwrap().to_string();
+        feed(&mut editor, &format!(":e {}\r:ls\r", second));
+        assert_eq!(
+            editor.terminal().row_text(2),
+            format!("  1 #h   {:<30} line 1", format!("\"{}\"", first))
+        );
+        assert_eq!(
+            editor.terminal().row_text(3),
+            format!("  2 %a   {:<30} line 1", format!("\"{}\"", second))
+        );
+        feed(&mut editor, "\r\x1e");
+        assert_eq!(editor.terminal().row_text(0), "one");
+        feed(&mut editor, "x:bn\r");
+        assert_eq!(
+            editor.terminal().row_text(4),
+            "No write since last change (add ! to override)"
+        );
+        feed(&mut editor, ":bd\r");
+        assert_eq!(
+            editor.terminal().row_text(4),
+            "No write since last change for buffer 1 (add ! to override)"
+        );
+        feed(&mut editor, ":qa\r");
+        assert_eq!(
+            editor.terminal().row_text(4),
+            format!("No write since last change for buffer \"{}\"", first)
+        );
+        feed(&mut editor, ":wa\r:bd\r");
+        assert_eq!(std::fs::read_to_string(&first).unwrap(), "ne");
+        assert_eq!(editor.terminal().row_text(0), "two");
+        feed(&mut editor, ":b 1\r");
+        assert_eq!(editor.terminal().row_text(4), "Buffer 1 does not exist");
+        feed(&mut editor, ":qa\rj");
+        assert_eq!(editor.terminal().events.len(), 1);
+    }
+
+    #[test]
+    fn test_quit_modified() {
+        let mut editor = editor("quit", "text");
+        feed(&mut editor, "x:q\r");
+        assert!(editor
+            .terminal()
+            .row_text(4)
+            .starts_with("No write since last change"));
+        feed(&mut editor, "0");
+        assert_eq!(editor.terminal().row_text(4), "NORMAL, 1, 1");
+        feed(&mut editor, "q");
+        assert!(editor
+            .terminal()
+            .row_text(4)
+            .starts_with("No write since last change"));
+        // The editor is still running, and quits with `:q!`.
+        feed(&mut editor, ":q!\rj");
+        assert_eq!(editor.terminal().events.len(), 1);
+    }
+
+    #[test]
+    fn test_windows() {
+        let mut editor = editor("windows", "1\n2\n3\n4\n5\n6");
+        let name = editor.filepath().unwrap().to_string();
+        editor.terminal_mut().resize(40, 12);
+        feed(&mut editor, "\x17sjx");
+        let terminal = editor.terminal();
+        assert_eq!(terminal.row_text(0), "1");
+        assert_eq!(terminal.row_text(1), "");
+        assert_eq!(terminal.row_text(5), format!("{} [+]", name));
+        assert_eq!(terminal.row_text(6), "1");
+        assert_eq!(terminal.row_text(7), "");
+        assert_eq!(terminal.row_text(10), format!("{} [+]", name));
+        assert_eq!(terminal.row_text(11), "NORMAL, 2, 1");
+        assert_eq!(terminal.cursor, (0, 1));
+        feed(&mut editor, "\x17jjj");
+        assert_eq!(editor.cursor(), (2, 0));
+        assert_eq!(editor.terminal().cursor, (0, 8));
+        feed(&mut editor, "\x17-");
+        // The window above takes the row.
+        assert_eq!(editor.terminal().row_text(6), format!("{} [+]", name));
+        assert_eq!(editor.terminal().row_text(7), "1");
+        feed(&mut editor, ":q\r\x17v");
+        let terminal = editor.terminal();
+        assert_eq!(terminal.row_text(0), format!("{:<20}|1", "1"));
+        assert_eq!(terminal.cells[10][20].ch, '|');
+        assert_eq!(terminal.cursor, (0, 1));
+        feed(&mut editor, "\x17l\x17o:close\r");
+        assert_eq!(editor.terminal().row_text(0), "1");
+        assert_eq!(editor.terminal().row_text(11), "Cannot close last window");
+    }
+
+    #[test]
+    fn test_tab_pages() {
+        let mut editor = editor("tabs", "1\n2\n3");
+        let name = editor.filepath().unwrap().to_string();
+        let base = name.rsplit('/').next().unwrap().to_string();
+        feed(&mut editor, "x:tabnew\r");
+        let terminal = editor.terminal();
+        assert_eq!(terminal.row_text(0), format!(" + {}  [No Name]", base));
+        assert_eq!(terminal.row_text(2), "");
+        feed(&mut editor, "gtj");
+        assert_eq!(editor.terminal().row_text(2), "2");
+        assert_eq!(editor.terminal().cursor, (0, 2));
+        feed(&mut editor, &format!("gT:tabnew {}\r", name));
+        // The new tab page comes after the current one, with the cursor where the
+        // buffer was left.
+        assert_eq!(
+            editor.terminal().row_text(0),
+            format!(" + {}  [No Name]  + {}", base, base)
+        );
+        assert_eq!(editor.cursor(), (1, 0));
+        // A click on the label of the second tab page.
+        let click = MouseEvent::Press(MouseButton::Left, base.len() as u16 + 6, 1);
+        let terminal = editor.terminal_mut();
+        terminal.push_event(EditorEvent::Input(Event::Mouse(click)));
+        terminal.feed(":tabclose\r");
+        editor.editor_loop();
+        assert_eq!(
+            editor.terminal().row_text(0),
+            format!(" + {}  + {}", base, base)
+        );
+        feed(&mut editor, ":tabc\r:tabclose\r");
+        assert_eq!(editor.terminal().row_text(1), "2");
+        assert_eq!(editor.terminal().row_text(4), "Cannot close last tab page");
+    }
+
+    #[test]
+    fn test_split_without_room() {
+        // The tab line leaves no row for the text, and shares its row with the message.
+        let mut editor = Editor::new(VirtualTerminal::new(20, 1), Config::default());
+        feed(&mut editor, ":tabnew\r:split\r");
+        assert!(editor.terminal().row_text(0).starts_with("Not enough room"));
+    }
+
+    #[test]
+    fn test_window_without_text_rows() {
+        let contents: Vec<String> = (1..=20).map(|i| format!("line {}", i)).collect();
+        let mut editor = editor("no-rows", &contents.join("\n"));
+        // The lower window keeps only its status line.
+        feed(&mut editor, ":split\r");
+        editor.terminal_mut().resize(80, 4);
+        feed(&mut editor, "");
+        assert_eq!(editor.terminal().row_text(0), "line 1");
+        assert!(editor.terminal().row_text(2).ends_with("-no-rows"));
+        // Clicking on it, dragging and moving around in it leave the other window alone.
+        editor.terminal_mut().push_event(press(1, 3));
+        editor
+            .terminal_mut()
+            .push_event(EditorEvent::Input(Event::Mouse(MouseEvent::Hold(1, 3))));
+        feed(&mut editor, "jj\x05zz\x04");
+        assert_eq!(editor.cursor(), (4, 0));
+        assert_eq!(editor.terminal().row_text(0), "line 1");
+        assert!(editor.terminal().row_text(2).ends_with("-no-rows"));
+    }
+
+    fn press(x: u16, y: u16) -> EditorEvent {
+        EditorEvent::Input(Event::Mouse(MouseEvent::Press(MouseButton::Left, x, y)))
+    }
+
+    #[test]
+    fn test_mouse() {
+        let contents: Vec<String> = (1..=20).map(|i| format!("line {}", i)).collect();
+        let mut editor = editor("mouse", &format!("foo bar\nbaz\n{}", contents.join("\n")));
+        // Clicks past the end of a line or on the status line stay on the text.
+        editor.terminal_mut().push_event(press(30, 2));
+        editor.editor_loop();
+        assert_eq!(editor.cursor(), (1, 2));
+        editor.terminal_mut().push_event(press(3, 5));
+        editor.editor_loop();
+        assert_eq!(editor.cursor(), (3, 2));
+        // The wheel scrolls the view, and the cursor along when its line leaves it.
+        let wheel = MouseEvent::Press(MouseButton::WheelDown, 1, 1);
+        editor
+            .terminal_mut()
+            .push_event(EditorEvent::Input(Event::Mouse(wheel)));
+        editor.editor_loop();
+        assert_eq!(editor.terminal().row_text(0), "line 2");
+        assert_eq!(editor.cursor(), (3, 2));
+        editor
+            .terminal_mut()
+            .push_event(EditorEvent::Input(Event::Mouse(wheel)));
+        editor.terminal_mut().push_event(press(1, 1));
+        editor.editor_loop();
+        assert_eq!(editor.terminal().row_text(0), "line 5");
+        assert_eq!(editor.cursor(), (6, 0));
+        let wheel = MouseEvent::Press(MouseButton::WheelUp, 1, 1);
+        for _ in 0..2 {
+            editor
+                .terminal_mut()
+                .push_event(EditorEvent::Input(Event::Mouse(wheel)));
+        }
+        // Dragging selects text in Visual mode.
+        let terminal = editor.terminal_mut();
+        terminal.push_event(press(2, 1));
+        terminal.push_event(EditorEvent::Input(Event::Mouse(MouseEvent::Hold(5, 2))));
+        terminal.push_event(EditorEvent::Input(Event::Mouse(MouseEvent::Release(5, 2))));
+        editor.editor_loop();
+        assert_eq!(editor.terminal().row_text(4), "VISUAL, 2, 3");
+        assert!(editor.terminal().cells[0][1].style.reverse);
+        assert!(!editor.terminal().cells[0][0].style.reverse);
+        feed(&mut editor, "dp");
+        // The selection went to the register and came back after the remaining "f".
+        assert_eq!(lines(&editor)[..2], ["foo bar", "baz"]);
+        assert_eq!(editor.cursor(), (0, 1));
+        // A double click selects a word and a triple click the line.
+        editor.terminal_mut().push_event(press(2, 1));
+        editor.terminal_mut().push_event(press(2, 1));
+        editor.editor_loop();
+        assert_eq!(editor.terminal().row_text(4), "VISUAL, 1, 3");
+        editor.terminal_mut().push_event(press(2, 1));
+        editor.editor_loop();
+        assert_eq!(editor.terminal().row_text(4), "VISUAL LINE, 1, 2");
+        editor.terminal_mut().feed("\x1b:set mouse=\r");
+        editor.terminal_mut().push_event(press(2, 2));
+        editor.editor_loop();
+        assert!(!editor.terminal().mouse);
+        assert_eq!(editor.cursor(), (0, 1));
+        // termion may report zero for the first column and row.
+        editor.terminal_mut().feed(":set mouse=a\r");
+        let terminal = editor.terminal_mut();
+        terminal.push_event(press(0, 0));
+        terminal.push_event(EditorEvent::Input(Event::Mouse(MouseEvent::Hold(0, 0))));
+        terminal.push_event(EditorEvent::Input(Event::Mouse(MouseEvent::Release(0, 0))));
+        editor.editor_loop();
+        assert_eq!(editor.cursor(), (0, 0));
+    }
+
+    #[test]
+    fn test_paste() {
+        let mut editor = editor("paste", "ab\ncd");
+        let paste = |text: &str| EditorEvent::Paste(text.to_string());
+        // An escape character in pasted text does not leave Insert mode.
+        editor.terminal_mut().feed("li");
+        editor.terminal_mut().push_event(paste("x\n\x1by"));
+        editor.editor_loop();
+        assert_eq!(editor.terminal().row_text(0), "ax");
+        assert_eq!(editor.terminal().row_text(4), "INSERT, 2, 3");
+        assert_eq!(editor.text()[1], ['\x1b', 'y', 'b']);
+        editor.terminal_mut().feed("\x1b");
+        editor.terminal_mut().push_event(paste("12"));
+        editor.editor_loop();
+        assert_eq!(editor.text()[1], ['\x1b', 'y', '1', '2', 'b']);
+        assert_eq!(editor.cursor(), (1, 3));
+        editor.terminal_mut().feed("jV");
+        editor.terminal_mut().push_event(paste("line"));
+        editor.terminal_mut().feed(":");
+        editor.terminal_mut().push_event(paste("set\nnu"));
+        feed(&mut editor, "\r");
+        assert_eq!(editor.terminal().row_text(2), "  3 line");
+        assert_eq!(editor.text().len(), 3);
+    }
+
+    #[test]
+    fn test_completion() {
+        let mut editor = editor("completion", "foobar fooqux\nfo");
+        // Ctrl-n puts the first candidate in the text and shows the others below it.
+        feed(&mut editor, "jA\x0e");
+        assert_eq!(lines(&editor)[1], "foobar");
+        assert_eq!(editor.terminal().row_text(2), " foobar");
+        assert_eq!(editor.terminal().row_text(3), " fooqux");
+        feed(&mut editor, "\x0e");
+        assert_eq!(lines(&editor)[1], "fooqux");
+        // Ctrl-e goes back to the text typed and closes the menu.
+        feed(&mut editor, "\x05");
+        assert_eq!(lines(&editor)[1], "fo");
+        assert_eq!(editor.terminal().row_text(2), "");
+        // Typing filters the candidates; Ctrl-y accepts the one selected.
+        feed(&mut editor, "\x0e\x10q");
+        assert_eq!(lines(&editor)[1], "foq");
+        assert_eq!(editor.terminal().row_text(2), " fooqux");
+        assert_eq!(editor.terminal().row_text(3), "");
+        feed(&mut editor, "\x0e\x19 ");
+        assert_eq!(lines(&editor)[1], "fooqux ");
+        assert_eq!(editor.terminal().row_text(2), "");
+        // Ctrl-x Ctrl-l completes whole lines.
+        feed(&mut editor, "\n  foob\x18\x0c\x1b");
+        assert_eq!(lines(&editor)[2], "  foobar fooqux");
+        feed(&mut editor, "ozz\x0e");
+        assert_eq!(editor.terminal().row_text(4), "Pattern not found");
+    }
+
+    #[test]
+    fn test_word_motions_and_operators() {
+        let mut editor = editor(
+            "words",
+            "foo.bar baz
+
+  qux-1 end",
+        );
+        feed(&mut editor, "wwww");
+        assert_eq!(editor.cursor(), (1, 0));
+        feed(&mut editor, "wgegeB");
+        assert_eq!(editor.cursor(), (0, 8));
+        // The last word of a line is deleted without its line break.
+        feed(&mut editor, "dw");
+        assert_eq!(lines(&editor), ["foo.bar ", "", "  qux-1 end"]);
+        feed(&mut editor, ":set iskeyword=@,-\n0cwx\x1b");
+        assert_eq!(lines(&editor)[0], "x.bar ");
+        feed(&mut editor, "jjwcwy\x1bdd");
+        assert_eq!(lines(&editor), ["x.bar ", ""]);
+        feed(&mut editor, "kyyPwd$jwwdb");
+        assert_eq!(lines(&editor), ["x", "xbar ", ""]);
+        assert_eq!(editor.cursor(), (1, 1));
+    }
+
+    #[test]
+    fn test_text_objects() {
+        let mut editor = editor(
+            "objects",
+            "fn f(a, (b)) {\n    x = \"s t\";\n    y;\n}\n\nend",
+        );
+        assert_eq!(lines(feed(&mut editor, "fbdib"))[0], "fn f(a, ()) {");
+        assert_eq!(lines(feed(&mut editor, "da("))[0], "fn f(a, ) {");
+        assert_eq!(lines(feed(&mut editor, "jci\"q\x1b"))[1], "    x = \"q\";");
+        // The lines inside braces on lines of their own are taken whole.
+        assert_eq!(
+            lines(feed(&mut editor, "di{")),
+            ["fn f(a, ) {", "}", "", "end"]
+        );
+        assert_eq!(lines(feed(&mut editor, "ggf(va(d"))[0], "fn f {");
+        assert_eq!(lines(feed(&mut editor, "vipd")), ["", "end"]);
+        assert_eq!(lines(feed(&mut editor, "jyiwP")), ["", "endend"]);
+    }
+
+    #[test]
+    fn test_marks() {
+        let contents: Vec<String> = (0..10).map(|i| i.to_string()).collect();
+        let mut editor = editor("marks", &contents.join("\n"));
+        assert_eq!(feed(&mut editor, "3Gmajjmb").cursor(), (4, 0));
+        // The marks move down with the line put above them.
+        assert_eq!(feed(&mut editor, "ggyyP'a").cursor(), (3, 0));
+        assert_eq!(feed(&mut editor, "`b").cursor(), (5, 0));
+        assert_eq!(feed(&mut editor, "''").cursor(), (3, 0));
+        assert_eq!(feed(&mut editor, "jjjdd'b").cursor(), (5, 0));
+        // The mark goes with its line.
+        assert_eq!(feed(&mut editor, "dd'a").cursor(), (3, 0));
+        assert_eq!(feed(&mut editor, "'b").cursor(), (3, 0));
+        assert_eq!(editor.terminal().row_text(4), "Mark not set");
+        feed(&mut editor, "d'.");
+        assert_eq!(editor.text().len(), 6);
+        // Deleting every line leaves the empty line for the marks of the first one.
+        feed(&mut editor, "ggmaGmbggdG'a");
+        assert_eq!(editor.terminal().row_text(4), "NORMAL, 1, 1");
+        feed(&mut editor, "'b");
+        assert_eq!(editor.terminal().row_text(4), "Mark not set");
+    }
+
+    #[test]
+    fn test_replace_and_single_key_edits() {
+        let mut editor = editor("replace", "abcd\nx = 0x0f;\n  foo\nbar)\nbaz");
+        let line = |editor: &Editor<VirtualTerminal>| {
+            let (line, column) = editor.cursor();
+            ((line, column), lines(editor)[line].clone())
+        };
+        let at = |line, column, text: &str| ((line, column), text.to_string());
+        // Backspace puts back the characters typed over and removes those added.
+        assert_eq!(
+            line(feed(&mut editor, "lRXYZW\x7f\x7f\x1b")),
+            at(0, 3, "aXYd")
+        );
+        assert_eq!(line(feed(&mut editor, "0~~")), at(0, 2, "AxYd"));
+        assert_eq!(line(feed(&mut editor, "03rq5rz")), at(0, 2, "qqqd"));
+        assert_eq!(line(feed(&mut editor, "j0\x01")), at(1, 7, "x = 0x10;"));
+        assert_eq!(line(feed(&mut editor, "3\x18")), at(1, 7, "x = 0x0d;"));
+        assert_eq!(line(feed(&mut editor, "jJ")), at(2, 5, "  foo bar)"));
+        assert_eq!(line(feed(&mut editor, "gJ")), at(2, 10, "  foo bar)baz"));
+        assert_eq!(line(feed(&mut editor, "0r\n")), at(3, 0, " foo bar)baz"));
+        assert_eq!(editor.text().len(), 4);
+    }
+
+    #[test]
+    fn test_jumps_and_changes() {
+        let contents: Vec<String> = (0..20).map(|i| i.to_string()).collect();
+        let mut editor = editor("jumps", &contents.join("\n"));
+        assert_eq!(feed(&mut editor, "Ggg5G").cursor(), (4, 0));
+        assert_eq!(feed(&mut editor, "\x0f").cursor(), (0, 0));
+        assert_eq!(feed(&mut editor, "\x0f").cursor(), (19, 0));
+        assert_eq!(feed(&mut editor, "\t").cursor(), (0, 0));
+        assert_eq!(feed(&mut editor, "\t").cursor(), (4, 0));
+        assert_eq!(feed(&mut editor, "3Gx8GxGg;").cursor(), (7, 0));
+        assert_eq!(feed(&mut editor, "g;").cursor(), (2, 0));
+        assert_eq!(feed(&mut editor, "g,").cursor(), (7, 0));
+        assert_eq!(feed(&mut editor, "G`.").cursor(), (7, 0));
+        // Where Insert mode was left, which is after the last character typed.
+        assert_eq!(feed(&mut editor, "ggjAx\x1bG`^").cursor(), (1, 1));
+    }
+
+    #[test]
+    fn test_find_char() {
+        let mut editor = editor("find", "f(a, b, c), d\nx, y");
+        feed(&mut editor, "2f,");
+        assert_eq!(editor.cursor(), (0, 6));
+        feed(&mut editor, ";,,");
+        assert_eq!(editor.cursor(), (0, 3));
+        feed(&mut editor, "2;T(");
+        assert_eq!(editor.cursor(), (0, 2));
+        feed(&mut editor, "dt)");
+        assert_eq!(lines(&editor)[0], "f(), d");
+        feed(&mut editor, "0cf,g\x1b");
+        assert_eq!(lines(&editor)[0], "g d");
+        // With `m` and `h` in `charsearch`, the search goes on to the next lines and the
+        // characters it goes to next are highlighted until another key is typed.
+        feed(&mut editor, ":set charsearch=mh\n0f ");
+        let background = |editor: &Editor<VirtualTerminal>, x: usize, y: usize| {
+            editor.terminal().cells[y][x].style.bg
+        };
+        assert_eq!(editor.cursor(), (0, 1));
+        assert!(background(&editor, 2, 1).is_some());
+        assert!(background(&editor, 1, 1).is_none());
+        feed(&mut editor, ";");
+        assert_eq!(editor.cursor(), (1, 2));
+        feed(&mut editor, "j");
+        assert!(background(&editor, 2, 1).is_none());
+        // A count before the operator multiplies the one after it.
+        feed(&mut editor, "02d2l");
+        assert_eq!(lines(&editor)[1], "");
+    }
+
+    #[test]
+    fn test_line_and_bracket_motions() {
+        let mut editor = editor("lines", "a {\n  b\n}\n\nc\nd\ne\nf\ng\nh");
+        assert_eq!(feed(&mut editor, "G").cursor(), (9, 0));
+        // Lines 7 to 10 are shown.
+        assert_eq!(feed(&mut editor, "H").cursor(), (6, 0));
+        assert_eq!(feed(&mut editor, "2H").cursor(), (7, 0));
+        assert_eq!(feed(&mut editor, "L").cursor(), (9, 0));
+        assert_eq!(feed(&mut editor, "M").cursor(), (7, 0));
+        assert_eq!(feed(&mut editor, "gg").cursor(), (0, 0));
+        assert_eq!(feed(&mut editor, "%").cursor(), (2, 0));
+        assert_eq!(feed(&mut editor, "%").cursor(), (0, 2));
+        assert_eq!(feed(&mut editor, "}").cursor(), (3, 0));
+        assert_eq!(feed(&mut editor, "3G").cursor(), (2, 0));
+        assert_eq!(feed(&mut editor, "50%").cursor(), (4, 0));
+        assert_eq!(feed(&mut editor, "2gg").cursor(), (1, 2));
+        // `d}` from the start of a line takes whole lines.
+        feed(&mut editor, "ggd}");
+        assert_eq!(editor.text().len(), 7);
+        assert!(editor.text()[0].is_empty());
+        feed(&mut editor, "jdG");
+        assert_eq!(editor.text().len(), 1);
+        // `d}` on the last character of the text takes it.
+        feed(&mut editor, "ia\x1bd}");
+        assert!(editor.text()[0].is_empty());
+    }
+
+    #[test]
+    fn test_scroll_commands() {
+        let contents: Vec<String> = (0..20).map(|i| i.to_string()).collect();
+        let mut editor = editor("scroll-commands", &contents.join("\n"));
+        let top =
+            |editor: &Editor<VirtualTerminal>| (editor.terminal().row_text(0), editor.cursor().0);
+        assert_eq!(top(feed(&mut editor, "\x04")), ("2".to_string(), 2));
+        assert_eq!(top(feed(&mut editor, "\x15")), ("0".to_string(), 0));
+        assert_eq!(top(feed(&mut editor, "\x06")), ("2".to_string(), 2));
+        assert_eq!(top(feed(&mut editor, "\x02")), ("0".to_string(), 2));
+        assert_eq!(top(feed(&mut editor, "3\x05")), ("3".to_string(), 3));
+        assert_eq!(top(feed(&mut editor, "\x19")), ("2".to_string(), 3));
+        assert_eq!(top(feed(&mut editor, "zt")), ("3".to_string(), 3));
+        assert_eq!(top(feed(&mut editor, "zb")), ("0".to_string(), 3));
+        assert_eq!(top(feed(&mut editor, "zz")), ("2".to_string(), 3));
+        assert_eq!(
+            top(feed(&mut editor, ":set so=1\rG")),
+            ("16".to_string(), 19)
+        );
+        assert_eq!(top(feed(&mut editor, "H")), ("16".to_string(), 17));
+        assert_eq!(top(feed(&mut editor, "L")), ("16".to_string(), 19));
+        assert_eq!(top(feed(&mut editor, "ggL")), ("0".to_string(), 2));
+        assert_eq!(top(feed(&mut editor, "\x04")), ("2".to_string(), 4));
+        // The screen is scrolled by terminal scrolling and drawn correctly.
+        assert_eq!(editor.terminal().row_text(3), "5");
+        editor.terminal_mut().resize(80, 8);
+        // Only the text rows of the window in a split are scrolled.
+        feed(&mut editor, ":split\r\x05\x05\x19");
+        let rows: Vec<String> = (0..7).map(|row| editor.terminal().row_text(row)).collect();
+        assert_eq!(rows[..3], ["4", "5", "6"]);
+        assert!(rows[3].ends_with("-scroll-commands"));
+        assert_eq!(rows[4..6], ["3", "4"]);
+        assert!(rows[6].ends_with("-scroll-commands"));
+    }
 }
@@ -0,0 +1,838 @@
+use crate::complete::*;
+use crate::event::*;
+use crate::json::*;
+use crate::text::*;
+use std::cmp;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Longest time to wait for the response to a request.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest time to wait for a server to shut down before it is killed.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Write a message with the `Content-Length` header the protocol frames messages with.
+pub fn write_message(writer: &mut impl Write, message: &Json) -> std::io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// Read the next message. `None` at the end of the input or when it is not a message.
+pub fn read_message(reader: &mut impl BufRead) -> Option<Json> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let mut body = vec![0; length?];
+    reader.read_exact(&mut body).ok()?;
+    Json::parse(&String::from_utf8_lossy(&body)).ok()
+}
+
+/// A language server running as a child process, talked to over its stdin and stdout.
+pub struct Server {
+    /// The filetype the server was started for, e.g. `rust`.
+    pub filetype: String,
+    child: Child,
+    stdin: ChildStdin,
+    /// Messages read from the server by a thread of its own.
+    messages: Receiver<Json>,
+    next_id: u64,
+    /// How documents are synchronized, from the capabilities of the server: 0 not at
+    /// all, 1 by sending the whole text, 2 by sending the changed part.
+    pub sync: usize,
+    /// The id of the `initialize` request until its response comes.
+    initializing: Option<u64>,
+}
+
+impl Server {
+    /// Start the server and ask it to initialize for the project at `root`, without
+    /// waiting for it to be ready. Each message it sends is announced through `waker` if
+    /// given.
+    pub fn start(
+        filetype: &str,
+        command: &[String],
+        root: &Path,
+        waker: Option<Sender<EditorEvent>>,
+    ) -> Result<Server, String> {
+        let mut child = Command::new(&command[0])
+            .args(&command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|why| format!("Cannot start language server \"{}\": {}", command[0], why))?;
+        let stdin = child.stdin.take().unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let (sender, messages) = channel();
+        thread::spawn(move || {
+            while let Some(message) = read_message(&mut stdout) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+                if let Some(waker) = &waker {
+                    let _ = waker.send(EditorEvent::Lsp);
+                }
+            }
+        });
+        let mut server = Server {
+            filetype: filetype.to_string(),
+            child,
+            stdin,
+            messages,
+            next_id: 1,
+            sync: 0,
+            initializing: None,
+        };
+        let params = Json::object(vec![
+            ("processId", (std::process::id() as usize).into()),
+            ("rootUri", path_to_uri(root).into()),
+            ("capabilities", capabilities()),
+        ]);
+        server.initializing = Some(server.send_request("initialize", params));
+        Ok(server)
+    }
+
+    /// The server answered `initialize`, so documents can be opened on it.
+    pub fn ready(&self) -> bool {
+        self.initializing.is_none()
+    }
+
+    /// Wait for the response to `initialize` if it has not come yet. Notifications which
+    /// come in meanwhile are passed to `on_notification`.
+    pub fn wait_ready(&mut self, on_notification: &mut dyn FnMut(Json)) -> Result<(), String> {
+        match self.initializing {
+            Some(id) => {
+                let result = self.wait(id, TIMEOUT, on_notification).ok_or_else(|| {
+                    "No response from the language server to initialize".to_string()
+                })?;
+                self.initialized(result)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Take in the capabilities the server answered `initialize` with.
+    fn initialized(&mut self, result: Result<Json, String>) -> Result<(), String> {
+        self.initializing = None;
+        let result = result?;
+        let sync = result.at(&["capabilities", "textDocumentSync"]);
+        self.sync = sync
+            .and_then(|sync| sync.as_usize().or_else(|| sync.get("change")?.as_usize()))
+            .unwrap_or(0);
+        self.notify("initialized", Json::object(vec![]));
+        Ok(())
+    }
+
+    fn send(&mut self, message: Json) {
+        // A server which went away shows as requests timing out.
+        let _ = write_message(&mut self.stdin, &message);
+    }
+
+    pub fn notify(&mut self, method: &str, params: Json) {
+        self.send(Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("method", method.into()),
+            ("params", params),
+        ]));
+    }
+
+    /// Send a request without waiting for its result. Returns its id.
+    fn send_request(&mut self, method: &str, params: Json) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("id", id.into()),
+            ("method", method.into()),
+            ("params", params),
+        ]));
+        id
+    }
+
+    /// Send a request and wait for its result. Notifications which come in meanwhile
+    /// are passed to `on_notification`.
+    pub fn request(
+        &mut self,
+        method: &str,
+        params: Json,
+        on_notification: &mut dyn FnMut(Json),
+    ) -> Result<Json, String> {
+        let id = self.send_request(method, params);
+        self.wait(id, TIMEOUT, on_notification)
+            .ok_or_else(|| format!("No response from the language server to {}", method))?
+    }
+
+    /// Wait for the response with `id`, for at most `timeout`.
+    fn wait(
+        &mut self,
+        id: u64,
+        timeout: Duration,
+        on_notification: &mut dyn FnMut(Json),
+    ) -> Option<Result<Json, String>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let message = match self.messages.recv_timeout(left) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => {
+                    return None
+                }
+            };
+            if message.get("method").is_some() {
+                if let Some(notification) = self.answer(message) {
+                    on_notification(notification);
+                }
+                continue;
+            }
+            if message.get("id").and_then(Json::as_usize) == Some(id as usize) {
+                return Some(response_result(&message));
+            }
+        }
+    }
+
+    /// The notifications which came in since the last time, without waiting. The
+    /// response to `initialize` is taken in too, failing if it is an error.
+    pub fn poll(&mut self) -> Result<Vec<Json>, String> {
+        let mut notifications = Vec::new();
+        while let Ok(message) = self.messages.try_recv() {
+            if message.get("method").is_some() {
+                notifications.extend(self.answer(message));
+            } else if let Some(id) = self.initializing {
+                if message.get("id").and_then(Json::as_usize) == Some(id as usize) {
+                    self.initialized(response_result(&message))?;
+                }
+            }
+        }
+        Ok(notifications)
+    }
+
+    /// Reply to a request from the server, none of which is supported, with an empty
+    /// result. Returns the message if it is a notification instead.
+    fn answer(&mut self, message: Json) -> Option<Json> {
+        match message.get("id") {
+            Some(id) => {
+                let reply = Json::object(vec![
+                    ("jsonrpc", "2.0".into()),
+                    ("id", id.clone()),
+                    ("result", Json::Null),
+                ]);
+                self.send(reply);
+                None
+            }
+            None => Some(message),
+        }
+    }
+}
+
+/// Ask the server to shut down and exit, killing it if it does not.
+impl Drop for Server {
+    fn drop(&mut self) {
+        let id = self.next_id;
+        self.send(Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("id", id.into()),
+            ("method", "shutdown".into()),
+            ("params", Json::Null),
+        ]));
+        self.wait(id, SHUTDOWN_TIMEOUT, &mut |_| ());
+        self.notify("exit", Json::Null);
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// The result of a response, or the message of its error.
+fn response_result(response: &Json) -> Result<Json, String> {
+    if let Some(error) = response.get("error") {
+        let text = error.get("message").and_then(Json::as_str).unwrap_or("");
+        return Err(format!("Language server error: {}", text));
+    }
+    Ok(response.get("result").cloned().unwrap_or(Json::Null))
+}
+
+/// What the editor can do with the results of a server.
+fn capabilities() -> Json {
+    let empty = || Json::object(vec![]);
+    let text_document = Json::object(vec![
+        (
+            "synchronization",
+            Json::object(vec![("didSave", true.into())]),
+        ),
+        (
+            "hover",
+            Json::object(vec![(
+                "contentFormat",
+                vec!["plaintext".into(), "markdown".into()].into(),
+            )]),
+        ),
+        ("completion", empty()),
+        ("definition", empty()),
+        ("references", empty()),
+        ("rename", empty()),
+        ("publishDiagnostics", empty()),
+    ]);
+    Json::object(vec![("textDocument", text_document)])
+}
+
+/// The language identifier of a document of the filetype.
+pub fn language_id(filetype: &str) -> &str {
+    match filetype {
+        "sh" => "shellscript",
+        filetype => filetype,
+    }
+}
+
+/// Characters left as they are in the path of a `file:` URI.
+fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte)
+}
+
+/// The `file:` URI of the path, made absolute from the current directory.
+pub fn path_to_uri(path: &Path) -> String {
+    let path = std::env::current_dir()
+        .map(|dir| dir.join(path))
+        .unwrap_or_else(|_| path.to_path_buf());
+    let mut uri = String::from("file://");
+    for &byte in path.to_string_lossy().as_bytes() {
+        if is_unreserved(byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
+
+/// The path of a `file:` URI.
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::new();
+    let mut i = 0;
+    while i < encoded.len() {
+        let decoded = match (encoded[i], encoded.get(i + 1..i + 3)) {
+            (b'%', Some(hex)) => u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok(),
+            _ => None,
+        };
+        match decoded {
+            Some(byte) => {
+                bytes.push(byte);
+                i += 3;
+            }
+            None => {
+                bytes.push(encoded[i]);
+                i += 1;
+            }
+        }
+    }
+    Some(PathBuf::from(String::from_utf8(bytes).ok()?))
+}
+
+/// The whole text of a document, as it is written to its file.
+pub fn document_text(text: &TextState) -> String {
+    text.iter()
+        .map(|line| line.iter().collect::<String>())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// The position of a line and column in the protocol, where columns count UTF-16
+/// code units.
+pub fn position(text: &TextState, line: usize, column: usize) -> Json {
+    let character: usize = text.get(line).map_or(0, |chars| {
+        chars[..cmp::min(column, chars.len())]
+            .iter()
+            .map(|c| c.len_utf16())
+            .sum()
+    });
+    Json::object(vec![("line", line.into()), ("character", character.into())])
+}
+
+/// The position of the end of the text.
+pub fn end_position(text: &TextState) -> Json {
+    let last = text.len() - 1;
+    position(text, last, text[last].len())
+}
+
+/// Line and column of a position in the protocol, kept within the text.
+pub fn from_position(text: &TextState, position: &Json) -> (usize, usize) {
+    let line = position.get("line").and_then(Json::as_usize).unwrap_or(0);
+    let character = position
+        .get("character")
+        .and_then(Json::as_usize)
+        .unwrap_or(0);
+    if line >= text.len() {
+        let last = text.len() - 1;
+        return (last, text[last].len());
+    }
+    let mut units = 0;
+    let column = text[line]
+        .iter()
+        .take_while(|c| {
+            units += c.len_utf16();
+            units <= character
+        })
+        .count();
+    (line, column)
+}
+
+/// The lines `start..old_end` of the text a language server has, which are now the lines
+/// `start..new_end`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LineChange {
+    pub start: usize,
+    pub old_end: usize,
+    pub new_end: usize,
+}
+
+impl LineChange {
+    /// The `removed` lines from `line` replaced with `added` lines.
+    pub fn new(line: usize, removed: usize, added: usize) -> Self {
+        LineChange {
+            start: line,
+            old_end: line + removed,
+            new_end: line + added,
+        }
+    }
+
+    /// This change followed by `later`, whose lines are those after this change.
+    pub fn then(self, later: LineChange) -> Self {
+        let end = cmp::max(self.new_end, later.old_end);
+        LineChange {
+            start: cmp::min(self.start, later.start),
+            old_end: self.old_end + (end - self.new_end),
+            new_end: end - later.old_end + later.new_end,
+        }
+    }
+}
+
+/// The change to send in `textDocument/didChange` for the lines of `change` in `new`.
+/// `end` is the position of the end of the text the server has.
+pub fn content_change(new: &TextState, change: LineChange, end: &Json) -> Json {
+    let lines = new[change.start..change.new_end]
+        .iter()
+        .map(|line| line.iter().collect::<String>());
+    let (start, end, text) = if change.new_end < new.len() {
+        // Whole lines are replaced, each with its line break.
+        let text: String = lines.map(|line| line + "\n").collect();
+        (
+            position(new, change.start, 0),
+            position(new, change.old_end, 0),
+            text,
+        )
+    } else if change.start > 0 {
+        // The lines up to the end are replaced, from the line break before them.
+        let text: String = lines.map(|line| "\n".to_string() + &line).collect();
+        let before = change.start - 1;
+        (position(new, before, new[before].len()), end.clone(), text)
+    } else {
+        (position(new, 0, 0), end.clone(), document_text(new))
+    };
+    Json::object(vec![
+        ("range", Json::object(vec![("start", start), ("end", end)])),
+        ("text", text.into()),
+    ])
+}
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum Severity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+impl Severity {
+    /// The highlight group the diagnostic is drawn with.
+    pub fn group(self) -> &'static str {
+        match self {
+            Severity::Error => "DiagnosticError",
+            Severity::Warning => "DiagnosticWarning",
+            Severity::Information => "DiagnosticInfo",
+            Severity::Hint => "DiagnosticHint",
+        }
+    }
+
+    /// The letter drawn in the sign column.
+    pub fn sign(self) -> char {
+        match self {
+            Severity::Error => 'E',
+            Severity::Warning => 'W',
+            Severity::Information => 'I',
+            Severity::Hint => 'H',
+        }
+    }
+}
+
+/// An error or warning a language server found in the text.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// The diagnostics of `textDocument/publishDiagnostics`, by line.
+pub fn diagnostics(params: &Json, text: &TextState) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = params
+        .get("diagnostics")
+        .and_then(Json::as_array)
+        .unwrap_or(&[])
+        .iter()
+        .map(|diagnostic| {
+            let start = diagnostic.at(&["range", "start"]).unwrap_or(&Json::Null);
+            let (line, column) = from_position(text, start);
+            let severity = match diagnostic.get("severity").and_then(Json::as_usize) {
+                Some(2) => Severity::Warning,
+                Some(3) => Severity::Information,
+                Some(4) => Severity::Hint,
+                _ => Severity::Error,
+            };
+            let message = diagnostic.get("message").and_then(Json::as_str);
+            Diagnostic {
+                line,
+                column,
+                severity,
+                message: message.unwrap_or("").to_string(),
+            }
+        })
+        .collect();
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+    diagnostics
+}
+
+/// The text of a hover result, which comes as markup, as a string or as a list of them.
+pub fn hover_text(result: &Json) -> Option<String> {
+    fn text(contents: &Json) -> String {
+        match contents {
+            Json::String(text) => text.clone(),
+            Json::Array(contents) => contents
+                .iter()
+                .map(text)
+                .collect::<Vec<String>>()
+                .join("\n"),
+            contents => contents
+                .get("value")
+                .and_then(Json::as_str)
+                .unwrap_or("")
+                .to_string(),
+        }
+    }
+    let text = text(result.get("contents")?);
+    let text = text.trim();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.to_string())
+    }
+}
+
+/// The URI and start position of each location in a result of `textDocument/definition`
+/// or `textDocument/references`: one location, a list of them, or location links.
+pub fn locations(result: &Json) -> Vec<(String, Json)> {
+    let one = |location: &Json| {
+        let (uri, range) = match location.get("targetUri") {
+            Some(uri) => (uri, location.get("targetSelectionRange")?),
+            None => (location.get("uri")?, location.get("range")?),
+        };
+        Some((uri.as_str()?.to_string(), range.get("start")?.clone()))
+    };
+    match result {
+        Json::Array(locations) => locations.iter().filter_map(one).collect(),
+        location => one(location).into_iter().collect(),
+    }
+}
+
+/// Names of the kinds of completion items, by their number less one.
+const COMPLETION_KINDS: [&str; 25] = [
+    "Text",
+    "Method",
+    "Function",
+    "Constructor",
+    "Field",
+    "Variable",
+    "Class",
+    "Interface",
+    "Module",
+    "Property",
+    "Unit",
+    "Value",
+    "Enum",
+    "Keyword",
+    "Snippet",
+    "Color",
+    "File",
+    "Reference",
+    "Folder",
+    "EnumMember",
+    "Constant",
+    "Struct",
+    "Event",
+    "Operator",
+    "TypeParameter",
+];
+
+/// The candidates of a `textDocument/completion` result, a list of items or a
+/// `CompletionList`, in the order of their `sortText`. Snippets are completed with
+/// their label.
+pub fn completion_items(result: &Json) -> Vec<Candidate> {
+    let items = result
+        .as_array()
+        .or_else(|| result.get("items")?.as_array())
+        .unwrap_or(&[]);
+    let mut items: Vec<&Json> = items.iter().collect();
+    let sort_text = |item: &Json| {
+        item.get("sortText")
+            .or_else(|| item.get("label"))
+            .and_then(Json::as_str)
+            .unwrap_or("")
+            .to_string()
+    };
+    items.sort_by_key(|item| sort_text(item));
+    items
+        .iter()
+        .filter_map(|item| {
+            let label = item.get("label")?.as_str()?;
+            let snippet = item.get("insertTextFormat").and_then(Json::as_usize) == Some(2);
+            let word = match (
+                snippet,
+                item.at(&["textEdit", "newText"]),
+                item.get("insertText"),
+            ) {
+                (false, Some(text), _) | (false, None, Some(text)) => text.as_str()?,
+                _ => label,
+            };
+            let kind = item
+                .get("kind")
+                .and_then(Json::as_usize)
+                .and_then(|kind| COMPLETION_KINDS.get(kind.wrapping_sub(1)))
+                .unwrap_or(&"");
+            Some(Candidate::new(word, kind))
+        })
+        .collect()
+}
+
+/// A replacement of the text in `range`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TextEdit {
+    pub range: Json,
+    pub new_text: String,
+}
+
+/// The edits of a `WorkspaceEdit` by document URI, from `changes` or `documentChanges`.
+pub fn workspace_edits(edit: &Json) -> Vec<(String, Vec<TextEdit>)> {
+    let text_edits = |edits: &Json| -> Vec<TextEdit> {
+        edits
+            .as_array()
+            .unwrap_or(&[])
+            .iter()
+            .filter_map(|edit| {
+                Some(TextEdit {
+                    range: edit.get("range")?.clone(),
+                    new_text: edit.get("newText")?.as_str()?.to_string(),
+                })
+            })
+            .collect()
+    };
+    if let Some(changes) = edit.get("documentChanges").and_then(Json::as_array) {
+        return changes
+            .iter()
+            .filter_map(|change| {
+                let uri = change.at(&["textDocument", "uri"])?.as_str()?;
+                Some((uri.to_string(), text_edits(change.get("edits")?)))
+            })
+            .collect();
+    }
+    match edit.get("changes") {
+        Some(Json::Object(changes)) => changes
+            .iter()
+            .map(|(uri, edits)| (uri.clone(), text_edits(edits)))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Make the edits, whose ranges refer to the text before any of them. Returns the
+/// first line changed.
+pub fn apply_edits(text: &mut TextState, edits: &[TextEdit]) -> Option<usize> {
+    let mut edits: Vec<_> = edits
+        .iter()
+        .map(|edit| {
+            let null = Json::Null;
+            let start = from_position(text, edit.range.get("start").unwrap_or(&null));
+            let end = from_position(text, edit.range.get("end").unwrap_or(&null));
+            (start, cmp::max(start, end), edit.new_text.as_str())
+        })
+        .collect();
+    // From the end, so that the positions of the edits still to be made stay valid.
+    edits.sort_by_key(|&(start, _, _)| cmp::Reverse(start));
+    for (start, end, new_text) in &edits {
+        delete_range(text, *start, *end);
+        let inserted: TextState = new_text
+            .split('\n')
+            .map(|line| line.chars().collect())
+            .collect();
+        insert_text(text, *start, &inserted);
+    }
+    edits.last().map(|(start, _, _)| start.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(lines: &[&str]) -> TextState {
+        lines.iter().map(|line| line.chars().collect()).collect()
+    }
+
+    #[test]
+    fn test_messages() {
+        let message = Json::object(vec![("id", 1usize.into()), ("method", "é".into())]);
+        let mut bytes = Vec::new();
+        write_message(&mut bytes, &message).unwrap();
+        write_message(&mut bytes, &Json::Null).unwrap();
+        assert!(bytes.starts_with(b"Content-Length: 22\r\n\r\n{"));
+        let mut reader = &bytes[..];
+        assert_eq!(read_message(&mut reader), Some(message));
+        assert_eq!(read_message(&mut reader), Some(Json::Null));
+        assert_eq!(read_message(&mut reader), None);
+    }
+
+    #[test]
+    fn test_positions_and_uris() {
+        let lines = text(&["a😀b", "c"]);
+        let position = position(&lines, 0, 2);
+        assert_eq!(position.to_string(), "{\"line\":0,\"character\":3}");
+        assert_eq!(from_position(&lines, &position), (0, 2));
+        assert_eq!(
+            from_position(
+                &lines,
+                &Json::parse("{\"line\":5,\"character\":0}").unwrap()
+            ),
+            (1, 1)
+        );
+        let uri = path_to_uri(Path::new("/tmp/a b/c.rs"));
+        assert_eq!(uri, "file:///tmp/a%20b/c.rs");
+        assert_eq!(uri_to_path(&uri), Some(PathBuf::from("/tmp/a b/c.rs")));
+    }
+
+    #[test]
+    fn test_content_change() {
+        let old = text(&["one", "two", "three"]);
+        let end = position(&old, 2, 5);
+        let cases = [
+            (LineChange::new(1, 1, 1), text(&["one", "2", "three"])),
+            (LineChange::new(2, 1, 0), text(&["one", "two"])),
+            (
+                LineChange::new(3, 0, 1),
+                text(&["one", "two", "three", "four"]),
+            ),
+            (
+                LineChange::new(0, 0, 1),
+                text(&["zero", "one", "two", "three"]),
+            ),
+            (LineChange::new(0, 3, 1), text(&["1"])),
+            (LineChange::new(1, 1, 0), text(&["one", "three"])),
+            // A line inserted above the line changed before.
+            (
+                LineChange::new(1, 1, 1).then(LineChange::new(0, 0, 1)),
+                text(&["zero", "one", "2", "three"]),
+            ),
+            (
+                LineChange::new(2, 1, 2).then(LineChange::new(0, 2, 1)),
+                text(&["1", "four", "five"]),
+            ),
+        ];
+        for (change, new) in &cases {
+            let change = content_change(new, *change, &end);
+            let edit = TextEdit {
+                range: change.get("range").unwrap().clone(),
+                new_text: change.get("text").unwrap().as_str().unwrap().to_string(),
+            };
+            let mut edited = old.clone();
+            apply_edits(&mut edited, &[edit]);
+            assert_eq!(edited, *new);
+        }
+        assert_eq!(
+            content_change(&cases[0].1, cases[0].0, &end).to_string(),
+            "{\"range\":{\"start\":{\"line\":1,\"character\":0},\
+             \"end\":{\"line\":2,\"character\":0}},\"text\":\"2\\n\"}"
+        );
+    }
+
+    #[test]
+    fn test_results() {
+        let lines = text(&["fn foo() {}", "foo();"]);
+        let params = Json::parse(
+            r#"{"uri": "file:///a.rs", "diagnostics": [
+                {"range": {"start": {"line": 1, "character": 0}}, "severity": 2, "message": "w"},
+                {"range": {"start": {"line": 0, "character": 3}}, "message": "e"}]}"#,
+        )
+        .unwrap();
+        let found = diagnostics(&params, &lines);
+        assert_eq!(found.len(), 2);
+        assert_eq!((found[0].line, found[0].column), (0, 3));
+        assert_eq!(found[0].severity, Severity::Error);
+        assert_eq!(found[1].severity, Severity::Warning);
+        let hover =
+            Json::parse(r#"{"contents": [{"language": "rust", "value": "fn foo()"}, "Docs"]}"#);
+        assert_eq!(
+            hover_text(&hover.unwrap()).as_deref(),
+            Some("fn foo()\nDocs")
+        );
+        let link = Json::parse(
+            r#"[{"targetUri": "file:///b.rs", "targetSelectionRange": {"start": {"line": 2, "character": 1}}}]"#,
+        );
+        let found = locations(&link.unwrap());
+        assert_eq!(found[0].0, "file:///b.rs");
+        assert_eq!(found[0].1.get("line").and_then(Json::as_usize), Some(2));
+        let edit = Json::parse(
+            r#"{"changes": {"file:///a.rs": [
+                {"range": {"start": {"line": 0, "character": 3}, "end": {"line": 0, "character": 6}}, "newText": "bar"},
+                {"range": {"start": {"line": 1, "character": 0}, "end": {"line": 1, "character": 3}}, "newText": "bar"}]}}"#,
+        );
+        let items = Json::parse(
+            r#"{"isIncomplete": false, "items": [
+                {"label": "foo", "kind": 3, "sortText": "b"},
+                {"label": "bar()", "insertText": "bar", "kind": 2, "sortText": "a"},
+                {"label": "baz", "insertText": "baz($1)", "insertTextFormat": 2}]}"#,
+        );
+        assert_eq!(
+            completion_items(&items.unwrap()),
+            [
+                Candidate::new("bar", "Method"),
+                Candidate::new("foo", "Function"),
+                Candidate::new("baz", "")
+            ]
+        );
+        let edits = workspace_edits(&edit.unwrap());
+        let mut edited = lines.clone();
+        assert_eq!(apply_edits(&mut edited, &edits[0].1), Some(0));
+        assert_eq!(edited, text(&["fn bar() {}", "bar();"]));
+    }
+}
@@ -1,19 +1,57 @@
 extern crate termion;
 
 use rim::editor::Editor;
+use rim::options::rimrc_path;
+use rim::terminal::TermionTerminal;
 use rim::util::Config;
 use std::env;
-use std::io::{stdin, stdout};
+use std::io::{stdin, stdout, Read};
+use std::path::PathBuf;
+use std::process::exit;
 use termion::input::MouseTerminal;
 use termion::raw::IntoRawMode;
 use termion::screen::AlternateScreen;
 
 fn main() {
-    let config = Config::new(env::args()).unwrap();
-    let stdin = stdin();
-    let stdin = stdin.lock();
-    let stdout = AlternateScreen::from(MouseTerminal::from(stdout().into_raw_mode().unwrap()));
-    // let mut stdout = Box::from(stdout);
-    let editor = Editor::new(stdin, stdout, config);
+    let mut config = Config::new(env::args()).unwrap_or_else(|e| {
+        eprintln!("rim: {}", e);
+        eprintln!("More info with: \"rim -h\"");
+        exit(1);
+    });
+    if let Some(print) = &config.print {
+        print!("{}", print);
+        return;
+    }
+    let rimrc = match config.rimrc.as_deref() {
+        Some("NONE") => None,
+        Some(path) if PathBuf::from(path).is_file() => Some(PathBuf::from(path)),
+        Some(path) => {
+            eprintln!("rim: Cannot read startup file: \"{}\"", path);
+            exit(1);
+        }
+        None => rimrc_path(),
+    };
+    let commands = std::mem::take(&mut config.commands);
+    let stdout = || AlternateScreen::from(MouseTerminal::from(stdout().into_raw_mode().unwrap()));
+    let mut editor = if config.read_stdin {
+        // The text comes through stdin, so keys are read from the terminal itself.
+        let mut text = String::new();
+        stdin().read_to_string(&mut text).unwrap_or_else(|e| {
+            eprintln!("rim: Cannot read stdin: {}", e);
+            exit(1);
+        });
+        config.stdin = Some(text);
+        let tty = termion::get_tty().unwrap_or_else(|e| {
+            eprintln!("rim: Cannot open the terminal: {}", e);
+            exit(1);
+        });
+        Editor::new(TermionTerminal::new(tty, stdout()), config)
+    } else {
+        Editor::new(TermionTerminal::new(stdin(), stdout()), config)
+    };
+    if let Some(path) = rimrc {
+        editor.source(&path);
+    }
+    editor.run_commands(&commands);
     editor.editor_loop();
 }
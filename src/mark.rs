@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+/// Most entries kept in a change list or the jump list.
+const MAX_ENTRIES: usize = 100;
+
+/// Where `position` goes when the `removed` lines from `line` on are replaced with
+/// `added` lines. It stays in the first of them as long as there are added lines in
+/// their place, and is gone with the others.
+pub fn shift_position(
+    (number, column): (usize, usize),
+    line: usize,
+    removed: usize,
+    added: usize,
+) -> Option<(usize, usize)> {
+    if number < line {
+        Some((number, column))
+    } else if number < line + removed {
+        (number - line < added).then_some((number, column))
+    } else {
+        Some((number + added - removed, column))
+    }
+}
+
+/// The marks of a buffer and its change list.
+#[derive(Default)]
+pub struct Marks {
+    /// Marks by name: `a` to `z` and `A` to `Z` set with `m`, and those set on the way:
+    /// `'` before the last jump, `^` where Insert mode was left, `[` and `]` around the
+    /// text last changed or yanked, and `<` and `>` around the last Visual selection.
+    marks: HashMap<char, (usize, usize)>,
+    /// Where the text was changed, oldest first.
+    changes: Vec<(usize, usize)>,
+    /// The entry of the change list `g;` or `g,` went to last, or its length.
+    change_index: usize,
+}
+
+impl Marks {
+    /// The position of a mark. `.` is where the text was last changed.
+    pub fn get(&self, name: char) -> Option<(usize, usize)> {
+        match name {
+            '.' => self.changes.last().copied(),
+            _ => self.marks.get(&name).copied(),
+        }
+    }
+
+    pub fn set(&mut self, name: char, position: (usize, usize)) {
+        self.marks.insert(name, position);
+    }
+
+    pub fn remove(&mut self, name: char) {
+        self.marks.remove(&name);
+    }
+
+    /// Add a change at `position` to the change list, in place of the newest one when it
+    /// is in the same line.
+    pub fn record_change(&mut self, position: (usize, usize)) {
+        if self.changes.last().is_some_and(|last| last.0 == position.0) {
+            self.changes.pop();
+        }
+        self.changes.push(position);
+        if self.changes.len() > MAX_ENTRIES {
+            self.changes.remove(0);
+        }
+        self.change_index = self.changes.len();
+    }
+
+    /// Go `count` changes back in the change list, as `g;` does, or forth if negative
+    /// as `g,` does.
+    pub fn go_to_change(&mut self, count: i32) -> Result<(usize, usize), String> {
+        if self.changes.is_empty() {
+            return Err("changelist is empty".to_string());
+        }
+        let index = self.change_index as i32 - count;
+        if index < 0 {
+            return Err("At start of changelist".to_string());
+        }
+        if index as usize >= self.changes.len() {
+            return Err("At end of changelist".to_string());
+        }
+        self.change_index = index as usize;
+        Ok(self.changes[self.change_index])
+    }
+
+    /// Move the marks and the changes along with the `removed` lines from `line` on
+    /// replaced with `added` lines. Those in lines which are gone are dropped.
+    pub fn shift(&mut self, line: usize, removed: usize, added: usize) {
+        self.marks = self
+            .marks
+            .drain()
+            .filter_map(|(name, position)| {
+                shift_position(position, line, removed, added).map(|position| (name, position))
+            })
+            .collect();
+        let mut index = self.change_index;
+        let mut kept = Vec::new();
+        for (i, &position) in self.changes.iter().enumerate() {
+            match shift_position(position, line, removed, added) {
+                Some(position) => kept.push(position),
+                None if i <= self.change_index => index = index.saturating_sub(1),
+                None => (),
+            }
+        }
+        self.changes = kept;
+        self.change_index = index;
+    }
+}
+
+/// The positions jumped from in any buffer, oldest first, for `Ctrl-o` and `Ctrl-i`.
+#[derive(Default)]
+pub struct JumpList {
+    /// Buffer numbers and positions.
+    jumps: Vec<(usize, (usize, usize))>,
+    /// The entry `Ctrl-o` or `Ctrl-i` went to last, or the length of the list.
+    index: usize,
+}
+
+impl JumpList {
+    /// Add `position` in buffer `number` as the newest jump, dropping an older one in
+    /// the same line.
+    pub fn push(&mut self, number: usize, position: (usize, usize)) {
+        self.jumps
+            .retain(|&(buffer, (line, _))| buffer != number || line != position.0);
+        self.jumps.push((number, position));
+        if self.jumps.len() > MAX_ENTRIES {
+            self.jumps.remove(0);
+        }
+        self.index = self.jumps.len();
+    }
+
+    /// Go `count` jumps back, or forth if negative, from `current`, the buffer number and
+    /// position of the cursor. Going back from the newest jump first adds `current`, so
+    /// that going forth comes back to it.
+    pub fn go(
+        &mut self,
+        count: i32,
+        current: (usize, (usize, usize)),
+    ) -> Option<(usize, (usize, usize))> {
+        if count > 0 && self.index == self.jumps.len() {
+            self.push(current.0, current.1);
+            self.index = self.jumps.len() - 1;
+        }
+        let index = self.index as i32 - count;
+        if index < 0 || index as usize >= self.jumps.len() {
+            return None;
+        }
+        self.index = index as usize;
+        Some(self.jumps[self.index])
+    }
+
+    /// Move the jumps in buffer `number` along with the `removed` lines from `line` on
+    /// replaced with `added` lines. Those in lines which are gone are dropped.
+    pub fn shift(&mut self, number: usize, line: usize, removed: usize, added: usize) {
+        let mut index = self.index;
+        let mut kept = Vec::new();
+        for (i, &(buffer, position)) in self.jumps.iter().enumerate() {
+            if buffer != number {
+                kept.push((buffer, position));
+                continue;
+            }
+            match shift_position(position, line, removed, added) {
+                Some(position) => kept.push((buffer, position)),
+                None if i <= self.index => index = index.saturating_sub(1),
+                None => (),
+            }
+        }
+        self.jumps = kept;
+        self.index = index;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shift_marks() {
+        let mut marks = Marks::default();
+        marks.set('a', (1, 2));
+        marks.set('b', (3, 0));
+        marks.set('c', (5, 1));
+        // Two lines put after line 1.
+        marks.shift(2, 0, 2);
+        assert_eq!(marks.get('a'), Some((1, 2)));
+        assert_eq!(marks.get('b'), Some((5, 0)));
+        // Lines 5 and 6 joined.
+        marks.shift(5, 2, 1);
+        assert_eq!(marks.get('b'), Some((5, 0)));
+        assert_eq!(marks.get('c'), Some((6, 1)));
+        // Lines 1 to 5 deleted.
+        marks.shift(1, 5, 0);
+        assert_eq!((marks.get('a'), marks.get('b')), (None, None));
+        assert_eq!(marks.get('c'), Some((1, 1)));
+    }
+
+    #[test]
+    fn test_change_list() {
+        let mut marks = Marks::default();
+        assert!(marks.go_to_change(1).is_err());
+        marks.record_change((0, 1));
+        marks.record_change((0, 4));
+        marks.record_change((3, 0));
+        marks.record_change((6, 2));
+        assert_eq!(marks.get('.'), Some((6, 2)));
+        assert_eq!(marks.go_to_change(1), Ok((6, 2)));
+        assert_eq!(marks.go_to_change(2), Ok((0, 4)));
+        assert!(marks.go_to_change(1).is_err());
+        assert_eq!(marks.go_to_change(-1), Ok((3, 0)));
+        marks.shift(3, 1, 0);
+        assert_eq!(marks.go_to_change(-1), Ok((5, 2)));
+        assert_eq!(
+            marks.go_to_change(-1),
+            Err("At end of changelist".to_string())
+        );
+    }
+
+    #[test]
+    fn test_jump_list() {
+        let mut jumps = JumpList::default();
+        jumps.push(1, (0, 0));
+        jumps.push(1, (10, 3));
+        jumps.push(2, (4, 0));
+        jumps.push(1, (10, 5));
+        assert_eq!(jumps.go(1, (1, (20, 0))), Some((1, (10, 5))));
+        assert_eq!(jumps.go(1, (1, (10, 5))), Some((2, (4, 0))));
+        assert_eq!(jumps.go(1, (2, (4, 0))), Some((1, (0, 0))));
+        assert_eq!(jumps.go(1, (1, (0, 0))), None);
+        assert_eq!(jumps.go(-3, (1, (0, 0))), Some((1, (20, 0))));
+        assert_eq!(jumps.go(-1, (1, (20, 0))), None);
+        jumps.shift(1, 0, 1, 0);
+        assert_eq!(jumps.go(1, (1, (19, 0))), Some((1, (9, 5))));
+        assert_eq!(jumps.go(1, (1, (9, 5))), Some((2, (4, 0))));
+        assert_eq!(jumps.go(1, (2, (4, 0))), None);
+    }
+}
@@ -0,0 +1,956 @@
+use crate::syntax::*;
+use crate::text::*;
+use std::cmp;
+
+/// The characters `iskeyword` puts in words, e.g. `@,48-57,_,192-255`. Each part is `@`
+/// for letters, a character code, a single character or a range of either. A part
+/// starting with `^` takes the characters out again.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Keyword {
+    /// Whether the part adds or removes characters, and the part.
+    parts: Vec<(bool, Part)>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Part {
+    Letters,
+    Codes(u32, u32),
+}
+
+impl Keyword {
+    pub fn parse(value: &str) -> Result<Keyword, String> {
+        let invalid = || format!("Invalid argument: iskeyword={}", value);
+        let code = |part: &str| -> Option<u32> {
+            let mut chars = part.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if !c.is_ascii_digit() => Some(c as u32),
+                _ => part.parse().ok(),
+            }
+        };
+        let mut parts = Vec::new();
+        for part in value.split(',').filter(|part| !part.is_empty()) {
+            let (include, part) = match part.strip_prefix('^') {
+                Some(rest) if !rest.is_empty() => (false, rest),
+                _ => (true, part),
+            };
+            let range = match part.split_once('-') {
+                _ if part == "@" => Part::Letters,
+                Some((first, last)) if !first.is_empty() && !last.is_empty() => {
+                    let first = code(first).ok_or_else(invalid)?;
+                    let last = code(last).ok_or_else(invalid)?;
+                    Part::Codes(first, last)
+                }
+                _ => {
+                    let code = code(part).ok_or_else(invalid)?;
+                    Part::Codes(code, code)
+                }
+            };
+            parts.push((include, range));
+        }
+        Ok(Keyword { parts })
+    }
+
+    /// Whether `c` belongs to words. Characters past Latin-1 do when they are letters or
+    /// digits.
+    pub fn contains(&self, c: char) -> bool {
+        if c as u32 >= 256 {
+            return c.is_alphanumeric();
+        }
+        let mut result = false;
+        for &(include, part) in &self.parts {
+            let matches = match part {
+                Part::Letters => c.is_alphabetic(),
+                Part::Codes(first, last) => (first..=last).contains(&(c as u32)),
+            };
+            if matches {
+                result = include;
+            }
+        }
+        result
+    }
+}
+
+impl Default for Keyword {
+    fn default() -> Self {
+        Keyword::parse("@,48-57,_,192-255").unwrap()
+    }
+}
+
+/// What motions look at besides the cursor.
+pub struct Context<'a> {
+    pub text: &'a TextState,
+    /// Characters of words, from `iskeyword`.
+    pub keyword: Keyword,
+    /// Tokens of the lines when the buffer has a grammar, with which `%` tells brackets
+    /// in strings and comments from the others. They must be up to date to the end.
+    pub highlighter: Option<&'a Highlighter>,
+}
+
+impl<'a> Context<'a> {
+    /// Whether the character is in a string or a comment.
+    fn is_quoted(&self, (line, column): (usize, usize)) -> bool {
+        self.highlighter.is_some_and(|highlighter| {
+            highlighter.tokens(line).iter().any(|token| {
+                token.range.contains(&column)
+                    && matches!(token.scope, Scope::String | Scope::Comment)
+            })
+        })
+    }
+}
+
+/// A motion in Normal mode, which an operator can also act on.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    /// `0`
+    LineStart,
+    /// `$`
+    LineEnd,
+    /// The line of the cursor and those below it, for an operator typed twice as in `dd`.
+    Lines,
+    /// `w`, or `W` for WORDs.
+    WordForward(bool),
+    /// `b` or `B`
+    WordBackward(bool),
+    /// `e` or `E`
+    WordEnd(bool),
+    /// `ge` or `gE`
+    WordEndBackward(bool),
+    /// `f`, `F`, `t` or `T`, and whether it is repeated with `;` or `,`.
+    Find(Find, bool),
+    /// The first non-blank of a line, counting from 0, for `gg`, `G`, `H`, `M`, `L` and
+    /// `{count}%`.
+    GoToLine(usize),
+    /// `}`, or `{` backward.
+    Paragraph(bool),
+    /// `)`, or `(` backward.
+    Sentence(bool),
+    /// `%`: the bracket matching the one under or after the cursor.
+    MatchPair,
+    /// A position, as `` `{mark} `` goes to.
+    GoTo((usize, usize)),
+}
+
+/// The positions after `from`, or before it when not `forward`, nearest first.
+pub fn walk<'a>(
+    text: &'a TextState,
+    from: (usize, usize),
+    forward: bool,
+) -> Box<dyn Iterator<Item = (usize, usize)> + 'a> {
+    let (line, column) = from;
+    if forward {
+        Box::new((line..text.len()).flat_map(move |number| {
+            let start = if number == line { column + 1 } else { 0 };
+            (start..text[number].len()).map(move |column| (number, column))
+        }))
+    } else {
+        Box::new((0..=line).rev().flat_map(move |number| {
+            let end = if number == line {
+                column
+            } else {
+                text[number].len()
+            };
+            (0..end).rev().map(move |column| (number, column))
+        }))
+    }
+}
+
+/// A search for a character, `f`, `F`, `t` or `T`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Find {
+    pub c: char,
+    pub forward: bool,
+    /// `t` or `T`: stop next to the character.
+    pub till: bool,
+    /// Look past the line of the cursor.
+    pub multiline: bool,
+}
+
+impl Find {
+    /// The positions of the character from `from` on in the direction of the search,
+    /// nearest first.
+    pub fn positions<'a>(
+        &self,
+        text: &'a TextState,
+        from: (usize, usize),
+    ) -> Box<dyn Iterator<Item = (usize, usize)> + 'a> {
+        let c = self.c;
+        let multiline = self.multiline;
+        Box::new(
+            walk(text, from, self.forward)
+                .take_while(move |&(line, _)| multiline || line == from.0)
+                .filter(move |&(line, column)| text[line][column] == c),
+        )
+    }
+
+    /// Where the search stops at the `count`th character. `repeated` by `;` or `,`, a
+    /// `t` or `T` does not stay next to the character it stopped at before.
+    fn target(
+        &self,
+        text: &TextState,
+        from: (usize, usize),
+        count: usize,
+        repeated: bool,
+    ) -> Option<(usize, usize)> {
+        let mut targets =
+            self.positions(text, from)
+                .map(|(line, column)| match (self.till, self.forward) {
+                    (false, _) => (line, column),
+                    (true, true) if column > 0 => (line, column - 1),
+                    (true, true) => (line - 1, text[line - 1].len()),
+                    (true, false) => (line, column + 1),
+                });
+        let target = targets.nth(count - 1)?;
+        if repeated && self.till && target == from {
+            targets.next()
+        } else {
+            Some(target)
+        }
+    }
+}
+
+/// How much of the text between the cursor and where a motion goes an operator acts on.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MotionKind {
+    /// Up to the character before the end.
+    Exclusive,
+    /// Up to the character at the end.
+    Inclusive,
+    /// Whole lines.
+    Linewise,
+}
+
+impl Motion {
+    /// The motion typed with one key, such as `w`.
+    pub fn from_key(key: char) -> Option<Motion> {
+        Some(match key {
+            'h' => Motion::Left,
+            'l' => Motion::Right,
+            'k' => Motion::Up,
+            'j' => Motion::Down,
+            '0' => Motion::LineStart,
+            '$' => Motion::LineEnd,
+            'w' | 'W' => Motion::WordForward(key == 'W'),
+            'b' | 'B' => Motion::WordBackward(key == 'B'),
+            'e' | 'E' => Motion::WordEnd(key == 'E'),
+            '}' | '{' => Motion::Paragraph(key == '}'),
+            ')' | '(' => Motion::Sentence(key == ')'),
+            '%' => Motion::MatchPair,
+            _ => return None,
+        })
+    }
+
+    /// Whether the cursor position is remembered in the jump list before the motion.
+    pub fn is_jump(self) -> bool {
+        matches!(
+            self,
+            Motion::GoToLine(_)
+                | Motion::Paragraph(_)
+                | Motion::Sentence(_)
+                | Motion::MatchPair
+                | Motion::GoTo(_)
+        )
+    }
+
+    /// Where the motion goes from `from`, repeated `count` times, and how an operator
+    /// acts on the text. `operator` is the operator pending, which makes word motions
+    /// stop at the end of a line, and `cw` change only to the end of the word.
+    /// `None` when the motion cannot move.
+    pub fn target(
+        self,
+        context: &Context,
+        from: (usize, usize),
+        count: usize,
+        operator: Option<char>,
+    ) -> Option<((usize, usize), MotionKind)> {
+        let text = context.text;
+        let (line, column) = from;
+        let length = text[line].len();
+        let count = cmp::max(count, 1);
+        let words = Words {
+            text,
+            keyword: &context.keyword,
+        };
+        match self {
+            Motion::Left if column > 0 => {
+                Some(((line, column.saturating_sub(count)), MotionKind::Exclusive))
+            }
+            Motion::Right if column + 1 < length || (operator.is_some() && column < length) => {
+                let limit = if operator.is_some() {
+                    length
+                } else {
+                    length - 1
+                };
+                Some((
+                    (line, cmp::min(column + count, limit)),
+                    MotionKind::Exclusive,
+                ))
+            }
+            Motion::Up if line > 0 => {
+                Some(((line.saturating_sub(count), column), MotionKind::Linewise))
+            }
+            Motion::Down if line + 1 < text.len() => Some((
+                (cmp::min(line + count, text.len() - 1), column),
+                MotionKind::Linewise,
+            )),
+            Motion::Lines if line + count <= text.len() => {
+                Some(((line + count - 1, column), MotionKind::Linewise))
+            }
+            Motion::LineStart => Some(((line, 0), MotionKind::Exclusive)),
+            Motion::LineEnd => {
+                let last = cmp::min(line + count - 1, text.len() - 1);
+                let end = text[last].len().saturating_sub(1);
+                Some(((last, end), MotionKind::Inclusive))
+            }
+            Motion::WordForward(big) => {
+                // `cw` on a word changes only up to its end, like `ce`.
+                let on_word = column < length && !is_blank(text[line][column]);
+                if operator == Some('c') && on_word {
+                    let end = words.end(from, count, big, true);
+                    return Some((end?, MotionKind::Inclusive));
+                }
+                let to = words.forward(from, count, big, operator.is_some());
+                (to != from).then_some((to, MotionKind::Exclusive))
+            }
+            Motion::WordBackward(big) => words
+                .backward(from, count, big)
+                .map(|to| (to, MotionKind::Exclusive)),
+            Motion::WordEnd(big) => words
+                .end(from, count, big, false)
+                .map(|to| (to, MotionKind::Inclusive)),
+            Motion::WordEndBackward(big) => words
+                .end_backward(from, count, big)
+                .map(|to| (to, MotionKind::Inclusive)),
+            Motion::Find(find, repeated) => {
+                let kind = if find.forward {
+                    MotionKind::Inclusive
+                } else {
+                    MotionKind::Exclusive
+                };
+                find.target(text, from, count, repeated)
+                    .map(|to| (to, kind))
+            }
+            Motion::GoToLine(line) => {
+                let line = cmp::min(line, text.len() - 1);
+                let indent = text[line].iter().take_while(|c| c.is_whitespace()).count();
+                let column = cmp::min(indent, text[line].len().saturating_sub(1));
+                Some(((line, column), MotionKind::Linewise))
+            }
+            Motion::Paragraph(forward) => paragraph(text, from, count, forward),
+            Motion::Sentence(forward) => {
+                let starts = sentence_starts(text);
+                let to = if forward {
+                    let last = text.len() - 1;
+                    let mut after = starts.into_iter().filter(|&start| start > from);
+                    after.nth(count - 1).unwrap_or((last, text[last].len()))
+                } else {
+                    let mut before = starts.into_iter().rev().filter(|&start| start < from);
+                    before.nth(count - 1).unwrap_or((0, 0))
+                };
+                (to != from).then_some((to, MotionKind::Exclusive))
+            }
+            Motion::MatchPair => match_pair(context, from).map(|to| (to, MotionKind::Inclusive)),
+            Motion::GoTo((line, column)) => {
+                let line = cmp::min(line, text.len() - 1);
+                Some((
+                    (line, cmp::min(column, text[line].len())),
+                    MotionKind::Exclusive,
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// `}` or `{`: the `count`th empty line after or before the text of the paragraph,
+/// or the end or the start of the text.
+fn paragraph(
+    text: &TextState,
+    from: (usize, usize),
+    count: usize,
+    forward: bool,
+) -> Option<((usize, usize), MotionKind)> {
+    let mut line = from.0;
+    for remaining in (0..count).rev() {
+        let mut seen_text = false;
+        let mut first = true;
+        loop {
+            let empty = text[line].is_empty();
+            seen_text |= !empty;
+            if !first && seen_text && empty {
+                break;
+            }
+            first = false;
+            let next = if forward {
+                line + 1
+            } else {
+                line.wrapping_sub(1)
+            };
+            if next >= text.len() {
+                if remaining > 0 {
+                    return None;
+                }
+                break;
+            }
+            line = next;
+        }
+    }
+    // The end of the text is its last character.
+    let length = text[line].len();
+    let target = if forward && line + 1 == text.len() && length > 0 {
+        ((line, length - 1), MotionKind::Inclusive)
+    } else {
+        ((line, 0), MotionKind::Exclusive)
+    };
+    // The last character is taken by an operator even with the cursor on it.
+    let inclusive = matches!(target.1, MotionKind::Inclusive);
+    (target.0 != from || inclusive).then_some(target)
+}
+
+/// Where the sentences of the text start, in order. A sentence ends at `.`, `!` or `?`
+/// followed by the end of a line or a blank, with closing brackets and quotes in
+/// between. Empty lines count as sentences of their own.
+pub fn sentence_starts(text: &TextState) -> Vec<(usize, usize)> {
+    let mut starts = Vec::new();
+    let mut at_start = true;
+    for (number, line) in text.iter().enumerate() {
+        if line.is_empty() {
+            starts.push((number, 0));
+            at_start = true;
+            continue;
+        }
+        let mut column = 0;
+        while column < line.len() {
+            let c = line[column];
+            if at_start && !c.is_whitespace() {
+                starts.push((number, column));
+                at_start = false;
+            }
+            column += 1;
+            if ".!?".contains(c) {
+                while column < line.len() && ")]\"'".contains(line[column]) {
+                    column += 1;
+                }
+                at_start = column == line.len() || line[column].is_whitespace();
+            }
+        }
+    }
+    starts
+}
+
+/// `%`: the bracket matching the first bracket at or after `from` in its line. Brackets
+/// in strings and comments are matched among themselves.
+fn match_pair(context: &Context, from: (usize, usize)) -> Option<(usize, usize)> {
+    const PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+    let text = context.text;
+    let line = &text[from.0];
+    let pair_of = |c: char| PAIRS.iter().find(|&&(open, close)| c == open || c == close);
+    let column = (from.1..line.len()).find(|&column| pair_of(line[column]).is_some())?;
+    let start = (from.0, column);
+    let &(open, close) = pair_of(line[column]).unwrap();
+    let forward = line[column] == open;
+    let quoted = context.is_quoted(start);
+    let mut depth = 0;
+    for position in std::iter::once(start).chain(walk(text, start, forward)) {
+        let c = text[position.0][position.1];
+        if (c != open && c != close) || context.is_quoted(position) != quoted {
+            continue;
+        }
+        if (c == open) == forward {
+            depth += 1;
+        } else {
+            depth -= 1;
+            if depth == 0 {
+                return Some(position);
+            }
+        }
+    }
+    None
+}
+
+fn is_blank(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+/// Word motions over a text, in the way of Vim. Positions may be just past the end of a
+/// line, which counts as a blank between the lines.
+struct Words<'a> {
+    text: &'a TextState,
+    keyword: &'a Keyword,
+}
+
+/// Where a step through the text went.
+#[derive(PartialEq)]
+enum Step {
+    /// To the next or previous character of the line.
+    Char,
+    /// Onto the end of a line, or over to another line.
+    Line,
+}
+
+impl<'a> Words<'a> {
+    /// 0 for blanks and line ends, 1 for punctuation and 2 for keyword characters.
+    /// WORDs are made of anything but blanks, which are all 1.
+    fn class(&self, position: (usize, usize), big: bool) -> u8 {
+        match self.text[position.0].get(position.1) {
+            None => 0,
+            Some(&c) if is_blank(c) => 0,
+            Some(_) if big => 1,
+            Some(&c) if self.keyword.contains(c) => 2,
+            Some(_) => 1,
+        }
+    }
+
+    fn is_empty_line(&self, position: (usize, usize)) -> bool {
+        self.text[position.0].is_empty()
+    }
+
+    /// Step forward, onto the end of the line before the next one. `None` at the end of
+    /// the text.
+    fn next(&self, position: &mut (usize, usize)) -> Option<Step> {
+        let length = self.text[position.0].len();
+        if position.1 < length {
+            position.1 += 1;
+            Some(if position.1 < length {
+                Step::Char
+            } else {
+                Step::Line
+            })
+        } else if position.0 + 1 < self.text.len() {
+            *position = (position.0 + 1, 0);
+            Some(Step::Line)
+        } else {
+            None
+        }
+    }
+
+    /// Step backward, onto the end of the previous line. `None` at the start of the text.
+    fn previous(&self, position: &mut (usize, usize)) -> Option<Step> {
+        if position.1 > 0 {
+            position.1 -= 1;
+            Some(Step::Char)
+        } else if position.0 > 0 {
+            position.0 -= 1;
+            position.1 = self.text[position.0].len();
+            Some(Step::Line)
+        } else {
+            None
+        }
+    }
+
+    /// Step over the characters of class `class`. `false` when the start or the end of
+    /// the text was reached.
+    fn skip(&self, position: &mut (usize, usize), class: u8, big: bool, forward: bool) -> bool {
+        while self.class(*position, big) == class {
+            let step = if forward {
+                self.next(position)
+            } else {
+                self.previous(position)
+            };
+            if step.is_none() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// `w`: the start of the `count`th next word, an empty line counting as one. With
+    /// `stop_at_eol`, for operators, the last word stops at the end of its line rather
+    /// than going on to the next one. Past the last word, the end of the text.
+    fn forward(
+        &self,
+        from: (usize, usize),
+        count: usize,
+        big: bool,
+        stop_at_eol: bool,
+    ) -> (usize, usize) {
+        let mut position = from;
+        for remaining in (0..count).rev() {
+            let last = remaining == 0;
+            let class = self.class(position, big);
+            let on_last_line = position.0 + 1 == self.text.len();
+            match self.next(&mut position) {
+                None => return position,
+                Some(Step::Line) if on_last_line => return position,
+                Some(Step::Line) if stop_at_eol && last => return position,
+                _ => (),
+            }
+            if class != 0 {
+                while self.class(position, big) == class {
+                    match self.next(&mut position) {
+                        None => return position,
+                        Some(Step::Line) if stop_at_eol && last => return position,
+                        _ => (),
+                    }
+                }
+            }
+            while self.class(position, big) == 0 {
+                if position.1 == 0 && self.is_empty_line(position) {
+                    break;
+                }
+                match self.next(&mut position) {
+                    None => return position,
+                    Some(Step::Line) if stop_at_eol && last => return position,
+                    _ => (),
+                }
+            }
+        }
+        position
+    }
+
+    /// `b`: the start of the `count`th previous word, an empty line counting as one.
+    fn backward(&self, from: (usize, usize), count: usize, big: bool) -> Option<(usize, usize)> {
+        let mut position = from;
+        'words: for _ in 0..count {
+            self.previous(&mut position)?;
+            while self.class(position, big) == 0 {
+                if position.1 == 0 && self.is_empty_line(position) {
+                    continue 'words;
+                }
+                if self.previous(&mut position).is_none() {
+                    return Some(position);
+                }
+            }
+            let class = self.class(position, big);
+            if !self.skip(&mut position, class, big, false) {
+                return Some(position);
+            }
+            self.next(&mut position);
+        }
+        Some(position)
+    }
+
+    /// `e`: the end of the `count`th next word. With `stay`, for `cw`, the end of the word
+    /// under the cursor even when it is already there.
+    fn end(
+        &self,
+        from: (usize, usize),
+        count: usize,
+        big: bool,
+        stay: bool,
+    ) -> Option<(usize, usize)> {
+        let mut position = from;
+        for _ in 0..count {
+            let class = self.class(position, big);
+            self.next(&mut position)?;
+            if class != 0 && self.class(position, big) == class {
+                if !self.skip(&mut position, class, big, true) {
+                    return None;
+                }
+            } else if !stay || class == 0 {
+                while self.class(position, big) == 0 {
+                    self.next(&mut position)?;
+                }
+                let class = self.class(position, big);
+                if !self.skip(&mut position, class, big, true) {
+                    return None;
+                }
+            }
+            self.previous(&mut position);
+        }
+        Some(position)
+    }
+
+    /// `ge`: the end of the `count`th previous word, an empty line counting as one.
+    fn end_backward(
+        &self,
+        from: (usize, usize),
+        count: usize,
+        big: bool,
+    ) -> Option<(usize, usize)> {
+        let mut position = from;
+        'words: for _ in 0..count {
+            let class = self.class(position, big);
+            self.previous(&mut position)?;
+            if class != 0 {
+                while self.class(position, big) == class {
+                    if self.previous(&mut position).is_none() {
+                        return Some(position);
+                    }
+                }
+            }
+            while self.class(position, big) == 0 {
+                if position.1 == 0 && self.is_empty_line(position) {
+                    continue 'words;
+                }
+                if self.previous(&mut position).is_none() {
+                    return Some(position);
+                }
+            }
+        }
+        Some(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(lines: &[&str]) -> TextState {
+        lines.iter().map(|line| line.chars().collect()).collect()
+    }
+
+    fn context(text: &TextState) -> Context<'_> {
+        Context {
+            text,
+            keyword: Keyword::default(),
+            highlighter: None,
+        }
+    }
+
+    /// The positions `motion` goes through from `from`, one after the other.
+    fn visit(lines: &[&str], motion: Motion, from: (usize, usize)) -> Vec<(usize, usize)> {
+        let text = text(lines);
+        let context = context(&text);
+        let mut positions = Vec::new();
+        let mut position = from;
+        while let Some((to, _)) = motion.target(&context, position, 1, None) {
+            // Normal mode keeps the cursor on a character.
+            let to = (to.0, cmp::min(to.1, text[to.0].len().saturating_sub(1)));
+            if to == position {
+                break;
+            }
+            positions.push(to);
+            position = to;
+        }
+        positions
+    }
+
+    #[test]
+    fn test_keyword() {
+        let keyword = Keyword::default();
+        assert!(keyword.contains('a') && keyword.contains('_') && keyword.contains('9'));
+        assert!(keyword.contains('é') && keyword.contains('あ'));
+        assert!(!keyword.contains('-') && !keyword.contains(' '));
+        let keyword = Keyword::parse("@,48-57,_,-,^a,^,").unwrap();
+        assert!(keyword.contains('-') && keyword.contains('^') && keyword.contains('b'));
+        assert!(!keyword.contains('a'));
+        let keyword = Keyword::parse("a-c,35").unwrap();
+        assert!(keyword.contains('b') && keyword.contains('#') && !keyword.contains('d'));
+        assert!(Keyword::parse("1-x-").is_err());
+    }
+
+    #[test]
+    fn test_word_motions() {
+        let lines = ["foo.bar(baz)  qux", "", "  x-y", "end"];
+        assert_eq!(
+            visit(&lines, Motion::WordForward(false), (0, 0)),
+            [
+                (0, 3),
+                (0, 4),
+                (0, 7),
+                (0, 8),
+                (0, 11),
+                (0, 14),
+                (1, 0),
+                (2, 2),
+                (2, 3),
+                (2, 4),
+                (3, 0),
+                (3, 2)
+            ]
+        );
+        assert_eq!(
+            visit(&lines, Motion::WordForward(true), (0, 0)),
+            [(0, 14), (1, 0), (2, 2), (3, 0), (3, 2)]
+        );
+        assert_eq!(
+            visit(&lines, Motion::WordBackward(false), (3, 2)),
+            [
+                (3, 0),
+                (2, 4),
+                (2, 3),
+                (2, 2),
+                (1, 0),
+                (0, 14),
+                (0, 11),
+                (0, 8),
+                (0, 7),
+                (0, 4),
+                (0, 3),
+                (0, 0)
+            ]
+        );
+        assert_eq!(
+            visit(&lines, Motion::WordEnd(false), (0, 0)),
+            [
+                (0, 2),
+                (0, 3),
+                (0, 6),
+                (0, 7),
+                (0, 10),
+                (0, 11),
+                (0, 16),
+                (2, 2),
+                (2, 3),
+                (2, 4),
+                (3, 2)
+            ]
+        );
+        assert_eq!(
+            visit(&lines, Motion::WordEndBackward(true), (3, 2)),
+            [(2, 4), (1, 0), (0, 16), (0, 11), (0, 0)]
+        );
+    }
+
+    #[test]
+    fn test_operator_targets() {
+        let text = text(&["foo bar", "  baz"]);
+        let context = context(&text);
+        let target =
+            |motion: Motion, from, operator| motion.target(&context, from, 1, Some(operator));
+        // The last word of a line stops at its end.
+        assert_eq!(
+            target(Motion::WordForward(false), (0, 4), 'd'),
+            Some(((0, 7), MotionKind::Exclusive))
+        );
+        assert_eq!(
+            target(Motion::WordForward(false), (0, 0), 'c'),
+            Some(((0, 2), MotionKind::Inclusive))
+        );
+        assert_eq!(
+            target(Motion::WordForward(false), (0, 3), 'c'),
+            Some(((0, 4), MotionKind::Exclusive))
+        );
+        assert_eq!(
+            target(Motion::WordForward(false), (1, 2), 'd'),
+            Some(((1, 5), MotionKind::Exclusive))
+        );
+        assert_eq!(
+            target(Motion::Lines, (1, 0), 'd'),
+            Some(((1, 0), MotionKind::Linewise))
+        );
+        assert_eq!(target(Motion::Down, (1, 0), 'd'), None);
+        assert_eq!(target(Motion::WordBackward(false), (0, 0), 'd'), None);
+    }
+
+    #[test]
+    fn test_find() {
+        let text = text(&["a(b, c) d,", "e, f"]);
+        let context = context(&text);
+        let find = |c, forward, till, multiline| Find {
+            c,
+            forward,
+            till,
+            multiline,
+        };
+        let target = |motion: Motion, from, count| {
+            motion.target(&context, from, count, None).map(|(to, _)| to)
+        };
+        assert_eq!(
+            target(
+                Motion::Find(find(',', true, false, false), false),
+                (0, 0),
+                2
+            ),
+            Some((0, 9))
+        );
+        assert_eq!(
+            target(
+                Motion::Find(find(',', true, false, false), false),
+                (0, 9),
+                1
+            ),
+            None
+        );
+        assert_eq!(
+            target(Motion::Find(find(',', true, false, true), false), (0, 9), 1),
+            Some((1, 1))
+        );
+        assert_eq!(
+            target(Motion::Find(find(')', true, true, false), false), (0, 0), 1),
+            Some((0, 5))
+        );
+        // Repeating `t` goes past the character next to the cursor.
+        assert_eq!(
+            target(Motion::Find(find(',', true, true, false), false), (0, 2), 1),
+            Some((0, 2))
+        );
+        assert_eq!(
+            target(Motion::Find(find(',', true, true, false), true), (0, 2), 1),
+            Some((0, 8))
+        );
+        assert_eq!(
+            target(
+                Motion::Find(find('(', false, true, false), false),
+                (0, 5),
+                1
+            ),
+            Some((0, 2))
+        );
+        assert_eq!(
+            target(
+                Motion::Find(find('d', false, false, true), false),
+                (1, 3),
+                1
+            ),
+            Some((0, 8))
+        );
+        assert_eq!(
+            Motion::Find(find(')', false, false, false), false).target(
+                &context,
+                (0, 9),
+                1,
+                Some('d')
+            ),
+            Some(((0, 6), MotionKind::Exclusive))
+        );
+    }
+
+    #[test]
+    fn test_paragraphs_and_sentences() {
+        let lines = ["One. Two!) Three", "four?", "", "", "  Five.", "six"];
+        assert_eq!(
+            visit(&lines, Motion::Paragraph(true), (0, 3)),
+            [(2, 0), (5, 2)]
+        );
+        assert_eq!(
+            visit(&lines, Motion::Paragraph(false), (5, 1)),
+            [(3, 0), (0, 0)]
+        );
+        assert_eq!(
+            visit(&lines, Motion::Sentence(true), (0, 0)),
+            [(0, 5), (0, 11), (2, 0), (3, 0), (4, 2), (5, 0), (5, 2)]
+        );
+        assert_eq!(
+            visit(&lines, Motion::Sentence(false), (5, 1)),
+            [(5, 0), (4, 2), (3, 0), (2, 0), (0, 11), (0, 5), (0, 0)]
+        );
+        let text = text(&lines);
+        assert_eq!(
+            Motion::Paragraph(true).target(&context(&text), (0, 0), 3, None),
+            None
+        );
+        let text = self::text(&["a", "", "b"]);
+        assert_eq!(
+            Motion::Paragraph(true).target(&context(&text), (2, 0), 1, None),
+            Some(((2, 0), MotionKind::Inclusive))
+        );
+    }
+
+    #[test]
+    fn test_match_pair() {
+        let text = text(&["fn f(a: [u8; 2]) {", "    g(\")\", x); // (", "}"]);
+        let mut highlighter = Highlighter::new(std::rc::Rc::new(rust::Rust));
+        highlighter.update(&text, text.len());
+        let mut context = context(&text);
+        let target = |context: &Context, from| {
+            Motion::MatchPair
+                .target(context, from, 1, None)
+                .map(|(to, _)| to)
+        };
+        assert_eq!(target(&context, (0, 0)), Some((0, 15)));
+        assert_eq!(target(&context, (0, 8)), Some((0, 14)));
+        assert_eq!(target(&context, (0, 14)), Some((0, 8)));
+        assert_eq!(target(&context, (2, 0)), Some((0, 17)));
+        // Without a grammar the brackets in the string and the comment count.
+        assert_eq!(target(&context, (1, 5)), Some((1, 7)));
+        context.highlighter = Some(&highlighter);
+        assert_eq!(target(&context, (1, 5)), Some((1, 12)));
+        assert_eq!(target(&context, (0, 17)), Some((2, 0)));
+        assert_eq!(target(&context, (1, 18)), None);
+    }
+}
@@ -0,0 +1,381 @@
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OptionScope {
+    /// Shared by the whole editor.
+    Global,
+    /// Belongs to the window showing a buffer, e.g. `number`.
+    Window,
+    /// Belongs to the text, e.g. `tabstop`.
+    Buffer,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Kind {
+    Bool,
+    Number,
+    String,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+    Bool(bool),
+    Number(usize),
+    String(String),
+}
+
+pub struct OptionInfo {
+    pub name: &'static str,
+    pub short: &'static str,
+    pub scope: OptionScope,
+    pub kind: Kind,
+}
+
+const fn option(
+    name: &'static str,
+    short: &'static str,
+    scope: OptionScope,
+    kind: Kind,
+) -> OptionInfo {
+    OptionInfo {
+        name,
+        short,
+        scope,
+        kind,
+    }
+}
+
+pub const OPTIONS: &[OptionInfo] = &[
+    option("charsearch", "", OptionScope::Global, Kind::String),
+    option("expandtab", "et", OptionScope::Buffer, Kind::Bool),
+    option("ignorecase", "ic", OptionScope::Global, Kind::Bool),
+    option("iskeyword", "isk", OptionScope::Buffer, Kind::String),
+    option("linebreak", "lbr", OptionScope::Window, Kind::Bool),
+    option("mapleader", "", OptionScope::Global, Kind::String),
+    option("mouse", "", OptionScope::Global, Kind::String),
+    option("number", "nu", OptionScope::Window, Kind::Bool),
+    option("readonly", "ro", OptionScope::Buffer, Kind::Bool),
+    option("relativenumber", "rnu", OptionScope::Window, Kind::Bool),
+    option("showbreak", "sbr", OptionScope::Window, Kind::String),
+    option("sidescroll", "ss", OptionScope::Window, Kind::Number),
+    option("sidescrolloff", "siso", OptionScope::Window, Kind::Number),
+    option("scrolloff", "so", OptionScope::Window, Kind::Number),
+    option("smartcase", "scs", OptionScope::Global, Kind::Bool),
+    option("tabstop", "ts", OptionScope::Buffer, Kind::Number),
+    option("timeoutlen", "tm", OptionScope::Global, Kind::Number),
+    option("wrap", "", OptionScope::Window, Kind::Bool),
+];
+
+/// Find an option by its full or short name.
+pub fn find_option(name: &str) -> Option<&'static OptionInfo> {
+    OPTIONS
+        .iter()
+        .find(|info| info.name == name || (!info.short.is_empty() && info.short == name))
+}
+
+/// The options of one scope, read and written by their full names.
+/// The value given to `set` has the kind listed in `OPTIONS`.
+pub trait Options {
+    fn get(&self, name: &str) -> Option<Value>;
+    fn set(&mut self, name: &str, value: Value);
+}
+
+pub struct GlobalOptions {
+    pub ignorecase: bool,
+    pub smartcase: bool,
+    /// What `<Leader>` stands for in mappings defined afterwards.
+    pub mapleader: String,
+    /// Milliseconds to wait for the rest of a mapping.
+    pub timeoutlen: usize,
+    /// Modes in which the mouse is used: `n`, `v`, `i`, or `a` for all of them.
+    /// Empty leaves the mouse to the terminal.
+    pub mouse: String,
+    /// How `f`, `F`, `t` and `T` search: `m` to go on past the line of the cursor,
+    /// `h` to highlight the characters `;` goes to next.
+    pub charsearch: String,
+}
+
+impl Default for GlobalOptions {
+    fn default() -> Self {
+        GlobalOptions {
+            ignorecase: false,
+            smartcase: false,
+            mapleader: "\\".to_string(),
+            timeoutlen: 1000,
+            mouse: "a".to_string(),
+            charsearch: String::new(),
+        }
+    }
+}
+
+impl Options for GlobalOptions {
+    fn get(&self, name: &str) -> Option<Value> {
+        match name {
+            "ignorecase" => Some(Value::Bool(self.ignorecase)),
+            "smartcase" => Some(Value::Bool(self.smartcase)),
+            "mapleader" => Some(Value::String(self.mapleader.clone())),
+            "timeoutlen" => Some(Value::Number(self.timeoutlen)),
+            "mouse" => Some(Value::String(self.mouse.clone())),
+            "charsearch" => Some(Value::String(self.charsearch.clone())),
+            _ => None,
+        }
+    }
+    fn set(&mut self, name: &str, value: Value) {
+        match (name, value) {
+            ("ignorecase", Value::Bool(value)) => self.ignorecase = value,
+            ("smartcase", Value::Bool(value)) => self.smartcase = value,
+            ("mapleader", Value::String(value)) => self.mapleader = value,
+            ("timeoutlen", Value::Number(value)) => self.timeoutlen = value,
+            ("mouse", Value::String(value)) => self.mouse = value,
+            ("charsearch", Value::String(value)) => self.charsearch = value,
+            _ => (),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BufferOptions {
+    /// Columns between tab stops, which tabs are drawn up to.
+    pub tabstop: usize,
+    /// Tab inserts blanks up to the next tab stop in Insert mode.
+    pub expandtab: bool,
+    /// `:w` refuses to write without `!`.
+    pub readonly: bool,
+    /// Characters words are made of, for word motions. See `motion::Keyword`.
+    pub iskeyword: String,
+}
+
+impl Default for BufferOptions {
+    fn default() -> Self {
+        BufferOptions {
+            tabstop: 8,
+            expandtab: false,
+            readonly: false,
+            iskeyword: "@,48-57,_,192-255".to_string(),
+        }
+    }
+}
+
+impl Options for BufferOptions {
+    fn get(&self, name: &str) -> Option<Value> {
+        match name {
+            "tabstop" => Some(Value::Number(self.tabstop)),
+            "expandtab" => Some(Value::Bool(self.expandtab)),
+            "readonly" => Some(Value::Bool(self.readonly)),
+            "iskeyword" => Some(Value::String(self.iskeyword.clone())),
+            _ => None,
+        }
+    }
+    fn set(&mut self, name: &str, value: Value) {
+        match (name, value) {
+            ("tabstop", Value::Number(value)) => self.tabstop = value,
+            ("expandtab", Value::Bool(value)) => self.expandtab = value,
+            ("readonly", Value::Bool(value)) => self.readonly = value,
+            ("iskeyword", Value::String(value)) => self.iskeyword = value,
+            _ => (),
+        }
+    }
+}
+
+/// Copy the values of the options of `scope` from `from` to `to`.
+pub fn copy_options(to: &mut dyn Options, from: &dyn Options, scope: OptionScope) {
+    for info in OPTIONS.iter().filter(|info| info.scope == scope) {
+        if let Some(value) = from.get(info.name) {
+            to.set(info.name, value);
+        }
+    }
+}
+
+/// What one argument of `:set` asks for.
+#[derive(PartialEq, Debug)]
+pub enum Action {
+    /// `:set {option}?`, or `:set {option}` for options which are not booleans.
+    Show,
+    /// `:set {option}`, `:set no{option}` and `:set {option}={value}`.
+    Set(Value),
+    /// `:set {option}!` and `:set inv{option}`.
+    Toggle,
+    /// `:set {option}&`
+    Reset,
+}
+
+/// Parse one argument of `:set`.
+pub fn parse_setting(argument: &str) -> Result<(&'static OptionInfo, Action), String> {
+    let unknown = |name: &str| format!("Unknown option: {}", name);
+    if let Some((name, value)) = argument.split_once(['=', ':']) {
+        let info = find_option(name).ok_or_else(|| unknown(name))?;
+        let value = match info.kind {
+            Kind::Bool => return Err(format!("Invalid argument: {}", argument)),
+            Kind::Number => Value::Number(
+                value
+                    .parse()
+                    .map_err(|_| format!("Number required after =: {}", argument))?,
+            ),
+            Kind::String => Value::String(value.to_string()),
+        };
+        return Ok((info, Action::Set(value)));
+    }
+    for (suffix, action) in [
+        ("?", Action::Show),
+        ("&", Action::Reset),
+        ("!", Action::Toggle),
+    ] {
+        if let Some(name) = argument.strip_suffix(suffix) {
+            let info = find_option(name).ok_or_else(|| unknown(name))?;
+            if action == Action::Toggle && info.kind != Kind::Bool {
+                return Err(format!("Invalid argument: {}", argument));
+            }
+            return Ok((info, action));
+        }
+    }
+    if let Some(info) = find_option(argument) {
+        let action = match info.kind {
+            Kind::Bool => Action::Set(Value::Bool(true)),
+            _ => Action::Show,
+        };
+        return Ok((info, action));
+    }
+    for (prefix, action) in [
+        ("no", Action::Set(Value::Bool(false))),
+        ("inv", Action::Toggle),
+    ] {
+        if let Some(info) = argument.strip_prefix(prefix).and_then(find_option) {
+            if info.kind == Kind::Bool {
+                return Ok((info, action));
+            }
+        }
+    }
+    Err(unknown(argument))
+}
+
+/// Carry out a parsed `:set` argument on the options of its scope.
+/// `defaults` provides the values restored by `:set {option}&`.
+/// Returns the message which `:set {option}?` shows.
+pub fn apply_setting(
+    options: &mut dyn Options,
+    defaults: &dyn Options,
+    info: &OptionInfo,
+    action: Action,
+) -> Option<String> {
+    match action {
+        Action::Show => options.get(info.name).map(|value| match value {
+            Value::Bool(true) => info.name.to_string(),
+            Value::Bool(false) => format!("no{}", info.name),
+            Value::Number(value) => format!("{}={}", info.name, value),
+            Value::String(value) => format!("{}={}", info.name, value),
+        }),
+        Action::Set(value) => {
+            options.set(info.name, value);
+            None
+        }
+        Action::Toggle => {
+            if let Some(Value::Bool(value)) = options.get(info.name) {
+                options.set(info.name, Value::Bool(!value));
+            }
+            None
+        }
+        Action::Reset => {
+            if let Some(value) = defaults.get(info.name) {
+                options.set(info.name, value);
+            }
+            None
+        }
+    }
+}
+
+/// Split the arguments of `:set` at whitespace. A backslash keeps the next
+/// character, so that `:set showbreak=>\ ` keeps its space.
+pub fn split_arguments(arguments: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current = String::new();
+    let mut chars = arguments.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => current.extend(chars.next()),
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    result.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        result.push(current);
+    }
+    result
+}
+
+/// The startup file, `$XDG_CONFIG_HOME/rim/rimrc` or `~/.config/rim/rimrc`.
+pub fn rimrc_path() -> Option<PathBuf> {
+    config_directories()
+        .into_iter()
+        .map(|directory| directory.join("rimrc"))
+        .find(|path| path.is_file())
+}
+
+/// Directories holding the configuration of rim, in order of preference.
+pub fn config_directories() -> Vec<PathBuf> {
+    let mut directories = Vec::new();
+    if let Ok(config) = std::env::var("XDG_CONFIG_HOME") {
+        directories.push(PathBuf::from(config).join("rim"));
+    }
+    if let Ok(home) = std::env::var("HOME") {
+        directories.push(PathBuf::from(home).join(".config").join("rim"));
+    }
+    directories
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(options: &mut BufferOptions, argument: &str) -> Result<Option<String>, String> {
+        let (info, action) = parse_setting(argument)?;
+        Ok(apply_setting(
+            options,
+            &BufferOptions::default(),
+            info,
+            action,
+        ))
+    }
+
+    #[test]
+    fn test_set() {
+        let mut options = BufferOptions::default();
+        assert_eq!(set(&mut options, "ts=4"), Ok(None));
+        assert_eq!(options.tabstop, 4);
+        assert_eq!(
+            set(&mut options, "tabstop"),
+            Ok(Some("tabstop=4".to_string()))
+        );
+        assert_eq!(set(&mut options, "ts&"), Ok(None));
+        assert_eq!(options.tabstop, 8);
+        assert_eq!(set(&mut options, "et"), Ok(None));
+        assert!(options.expandtab);
+        assert_eq!(set(&mut options, "invet"), Ok(None));
+        assert_eq!(
+            set(&mut options, "expandtab?"),
+            Ok(Some("noexpandtab".to_string()))
+        );
+        assert_eq!(set(&mut options, "et!"), Ok(None));
+        assert_eq!(set(&mut options, "noet"), Ok(None));
+        assert!(!options.expandtab);
+        assert!(set(&mut options, "ts=x").is_err());
+        assert!(set(&mut options, "et=1").is_err());
+        assert!(set(&mut options, "nots").is_err());
+        assert!(set(&mut options, "ts!").is_err());
+        assert_eq!(
+            set(&mut options, "bogus"),
+            Err("Unknown option: bogus".to_string())
+        );
+    }
+
+    #[test]
+    fn test_split_arguments() {
+        assert_eq!(
+            split_arguments(" nu  sbr=>\\  ts=4"),
+            ["nu", "sbr=> ", "ts=4"]
+        );
+    }
+}
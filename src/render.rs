@@ -0,0 +1,505 @@
+use crate::complete::*;
+use crate::diff::*;
+use crate::layout::*;
+use crate::lsp::*;
+use crate::terminal::*;
+use crate::text::*;
+use crate::theme::*;
+use crate::util::*;
+use std::cmp;
+use std::ops::Range;
+
+/// Most candidates shown at once in the completion menu.
+const POPUP_HEIGHT: usize = 10;
+
+/// Unchanged cells between two changes which are rewritten anyway, as moving the
+/// cursor over them costs about as much.
+const MERGE_GAP: usize = 4;
+
+/// The contents of every cell of the terminal, and where the cursor is.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Grid {
+    pub width: usize,
+    pub height: usize,
+    pub cells: Vec<Vec<Cell>>,
+    pub cursor: (usize, usize),
+}
+
+impl Grid {
+    pub fn new(width: usize, height: usize) -> Self {
+        Grid {
+            width,
+            height,
+            cells: vec![vec![Cell::default(); width]; height],
+            cursor: (0, 0),
+        }
+    }
+
+    /// Draw cells from the position to the right, dropping those past the right edge.
+    pub fn put(&mut self, x: usize, y: usize, cells: &[Cell]) {
+        if let Some(row) = self.cells.get_mut(y) {
+            for (target, cell) in row.iter_mut().skip(x).zip(cells) {
+                *target = cell.clone();
+            }
+        }
+    }
+
+    /// Draw the cells of `other` with its top left corner at the position.
+    pub fn blit(&mut self, x: usize, y: usize, other: &Grid) {
+        for (i, row) in other.cells.iter().enumerate() {
+            self.put(x, y + i, row);
+        }
+    }
+
+    /// Draw `text` with `style`.
+    pub fn put_str(&mut self, x: usize, y: usize, text: &str, style: &Style) {
+        self.put(x, y, &cells(text, style));
+    }
+
+    /// Move `rows` up by `distance` rows (down if negative), as a terminal scrolls.
+    pub fn scroll(&mut self, rows: Range<usize>, distance: i32) {
+        let blank = vec![Cell::default(); self.width];
+        scroll_rows(&mut self.cells, rows, distance, &blank);
+    }
+
+    /// Spans of cells which differ from `previous`, as rows and column ranges.
+    pub fn changes(&self, previous: &Grid) -> Vec<(usize, Range<usize>)> {
+        let mut changes: Vec<(usize, Range<usize>)> = Vec::new();
+        for (y, (row, previous_row)) in self.cells.iter().zip(&previous.cells).enumerate() {
+            for x in 0..self.width {
+                if row[x] == previous_row[x] {
+                    continue;
+                }
+                match changes.last_mut() {
+                    Some((last_y, span)) if *last_y == y && x <= span.end + MERGE_GAP => {
+                        span.end = x + 1
+                    }
+                    _ => changes.push((y, x..x + 1)),
+                }
+            }
+        }
+        changes
+    }
+}
+
+/// Draws frames composed in a back buffer, sending only what differs from the
+/// front buffer, which mirrors the terminal.
+pub struct Renderer {
+    front: Grid,
+    back: Grid,
+}
+
+impl Renderer {
+    pub fn new(width: u16, height: u16) -> Self {
+        Renderer {
+            front: Grid::new(width as usize, height as usize),
+            back: Grid::new(width as usize, height as usize),
+        }
+    }
+
+    /// A blank frame of the size of the terminal to draw the next frame in.
+    pub fn frame(&mut self) -> &mut Grid {
+        self.back = Grid::new(self.front.width, self.front.height);
+        &mut self.back
+    }
+
+    /// Send the frame to the terminal.
+    pub fn present<T: Terminal>(&mut self, terminal: &mut T) {
+        for (y, span) in self.back.changes(&self.front) {
+            terminal.write_cells(span.start as u16, y as u16, &self.back.cells[y][span]);
+        }
+        let (x, y) = self.back.cursor;
+        terminal.set_cursor(x as u16, y as u16);
+        terminal.flush();
+        std::mem::swap(&mut self.front, &mut self.back);
+    }
+
+    /// Scroll `rows` of the terminal by `distance`, so that the rows which are still
+    /// visible need not be sent again.
+    pub fn scroll<T: Terminal>(&mut self, terminal: &mut T, rows: Range<usize>, distance: i32) {
+        let rows = rows.start..cmp::min(rows.end, self.front.height);
+        if distance != 0 && !rows.is_empty() {
+            terminal.scroll(rows.start as u16..rows.end as u16, distance);
+            self.front.scroll(rows, distance);
+        }
+    }
+
+    /// Forget what is on the terminal and clear it, so that the next frame is sent
+    /// entirely. Used for `Ctrl-l` and after the terminal was resized.
+    pub fn invalidate<T: Terminal>(&mut self, terminal: &mut T) {
+        let (width, height) = terminal.size();
+        terminal.clear();
+        self.front = Grid::new(width as usize, height as usize);
+    }
+}
+
+/// Draw the text rows of the layout, with the line number gutter.
+pub fn draw_layout(grid: &mut Grid, text: &TextState, layout: &Layout, theme: &Theme) {
+    let non_text = theme.style("NonText");
+    for (y, screen_line) in layout.rows.iter().enumerate() {
+        let screen_line = match screen_line {
+            Some(screen_line) => screen_line,
+            None => continue,
+        };
+        let mut row = cells(&screen_line.number, &theme.style("LineNr"));
+        if screen_line.continuation {
+            row.extend(cells(&layout.showbreak, &non_text));
+        }
+        let gutter = row.len();
+        let start = screen_line.range.start;
+        let chars = &text[screen_line.line][screen_line.range.clone()];
+        for (i, &c) in chars.iter().enumerate() {
+            // A tab is drawn as blanks up to the next tab stop.
+            let width = screen_line.cells[i + 1] - screen_line.cells[i];
+            let c = if c == '\t' { ' ' } else { c };
+            row.extend((0..width).map(|_| Cell::new(c, &Style::default())));
+        }
+        for token in &screen_line.tokens {
+            let style = theme.style(token.scope.group());
+            let cells = screen_line.cells[token.range.start - start]
+                ..screen_line.cells[token.range.end - start];
+            for cell in &mut row[gutter + cells.start..gutter + cells.end] {
+                cell.style = style.clone();
+            }
+        }
+        if row.len() > gutter {
+            if screen_line.precedes {
+                row[gutter] = Cell::new('<', &non_text);
+            }
+            if screen_line.extends {
+                *row.last_mut().unwrap() = Cell::new('>', &non_text);
+            }
+        }
+        grid.put(0, y, &row);
+    }
+}
+
+/// Highlight the rows of lines which differ from the other file in diff mode,
+/// right of the gutter.
+pub fn draw_diff(grid: &mut Grid, layout: &Layout, diff: &[LineDiff], theme: &Theme) {
+    for (y, screen_line) in layout.rows.iter().enumerate() {
+        let screen_line = match screen_line {
+            Some(screen_line) => screen_line,
+            None => continue,
+        };
+        let group = match diff.get(screen_line.line) {
+            Some(LineDiff::Added) => "DiffAdd",
+            Some(LineDiff::Changed) => "DiffChange",
+            _ => continue,
+        };
+        let background = theme.style(group).bg;
+        for cell in grid.cells[y]
+            .iter_mut()
+            .skip(screen_line.number.chars().count())
+        {
+            cell.style.bg = background;
+        }
+    }
+}
+
+/// Mark the lines with diagnostics: the sign of the most severe one in the sign
+/// column, and its message after the end of the line.
+pub fn draw_diagnostics(
+    grid: &mut Grid,
+    text: &TextState,
+    layout: &Layout,
+    diagnostics: &[Diagnostic],
+    theme: &Theme,
+) {
+    for (y, screen_line) in layout.rows.iter().enumerate() {
+        let screen_line = match screen_line {
+            Some(screen_line) => screen_line,
+            None => continue,
+        };
+        let line = screen_line.line;
+        let worst = diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.line == line)
+            .min_by(|a, b| a.severity.partial_cmp(&b.severity).unwrap());
+        let diagnostic = match worst {
+            Some(diagnostic) => diagnostic,
+            None => continue,
+        };
+        let style = theme.style(diagnostic.severity.group());
+        if !screen_line.continuation {
+            grid.put_str(0, y, &diagnostic.severity.sign().to_string(), &style);
+        }
+        if screen_line.range.end == text[line].len() {
+            let end = screen_line.cells.last().copied().unwrap_or(0);
+            let mut x = screen_line.number.chars().count() + end + 1;
+            if screen_line.continuation {
+                x += layout.showbreak.chars().count();
+            }
+            let message = diagnostic.message.lines().next().unwrap_or("");
+            grid.put_str(x, y, message, &style);
+        }
+    }
+}
+
+/// Highlight the selection of Visual mode, from `start` to `end` inclusive, given as
+/// line and column. A `linewise` selection takes whole lines. The end of a selected
+/// line break is shown as a highlighted cell after the line.
+pub fn draw_selection(
+    grid: &mut Grid,
+    text: &TextState,
+    layout: &Layout,
+    start: (usize, usize),
+    end: (usize, usize),
+    linewise: bool,
+    theme: &Theme,
+) {
+    let visual = theme.style("Visual");
+    for (y, screen_line) in layout.rows.iter().enumerate() {
+        let screen_line = match screen_line {
+            Some(screen_line) if (start.0..=end.0).contains(&screen_line.line) => screen_line,
+            _ => continue,
+        };
+        let line = screen_line.line;
+        let first = if line == start.0 && !linewise {
+            start.1
+        } else {
+            0
+        };
+        let last = if line == end.0 && !linewise {
+            end.1
+        } else {
+            usize::MAX
+        };
+        let range = &screen_line.range;
+        let mut gutter = screen_line.number.chars().count();
+        if screen_line.continuation {
+            gutter += layout.showbreak.chars().count();
+        }
+        // The cell after the end of the line stands for the line break.
+        let stop = if range.end == text[line].len() {
+            range.end + 1
+        } else {
+            range.end
+        };
+        for column in cmp::max(first, range.start)..cmp::min(last.saturating_add(1), stop) {
+            let x = gutter + screen_line.cells[column - range.start];
+            let width = match screen_line.cells.get(column - range.start + 1) {
+                Some(end) => end - screen_line.cells[column - range.start],
+                None => 1,
+            };
+            for cell in grid.cells[y].iter_mut().skip(x).take(width) {
+                cell.style.reverse |= visual.reverse;
+                cell.style.fg = visual.fg.or(cell.style.fg);
+                cell.style.bg = visual.bg.or(cell.style.bg);
+            }
+        }
+    }
+}
+
+/// Highlight the characters at the positions, given as line and column, e.g. those
+/// `;` goes to.
+pub fn draw_targets(grid: &mut Grid, layout: &Layout, targets: &[(usize, usize)], theme: &Theme) {
+    let style = theme.style("Search");
+    for (y, screen_line) in layout.rows.iter().enumerate() {
+        let screen_line = match screen_line {
+            Some(screen_line) => screen_line,
+            None => continue,
+        };
+        let range = &screen_line.range;
+        let mut gutter = screen_line.number.chars().count();
+        if screen_line.continuation {
+            gutter += layout.showbreak.chars().count();
+        }
+        for &(_, column) in targets
+            .iter()
+            .filter(|&&(line, column)| line == screen_line.line && range.contains(&column))
+        {
+            let cells = &screen_line.cells[column - range.start..=column - range.start + 1];
+            for cell in grid.cells[y]
+                .iter_mut()
+                .skip(gutter + cells[0])
+                .take(cells[1] - cells[0])
+            {
+                cell.style.fg = style.fg.or(cell.style.fg);
+                cell.style.bg = style.bg.or(cell.style.bg);
+            }
+        }
+    }
+}
+
+/// Draw the status line of one of several windows on its last row: the file name, and
+/// `[+]` when the buffer is modified.
+pub fn draw_window_status(
+    grid: &mut Grid,
+    name: &str,
+    modified: bool,
+    current: bool,
+    theme: &Theme,
+) {
+    let style = theme.style(if current {
+        "StatusLine"
+    } else {
+        "StatusLineNC"
+    });
+    let y = grid.height.saturating_sub(1);
+    let text = if modified {
+        format!("{} [+]", name)
+    } else {
+        name.to_string()
+    };
+    grid.put(0, y, &vec![Cell::new(' ', &style); grid.width]);
+    grid.put_str(0, y, &text, &style);
+}
+
+/// Draw the tab line on the first row: a label for each tab page, the one at `current`
+/// highlighted.
+pub fn draw_tab_line(grid: &mut Grid, labels: &[String], current: usize, theme: &Theme) {
+    grid.put(
+        0,
+        0,
+        &vec![Cell::new(' ', &theme.style("TabLineFill")); grid.width],
+    );
+    let mut x = 0;
+    for (i, label) in labels.iter().enumerate() {
+        let group = if i == current {
+            "TabLineSel"
+        } else {
+            "TabLine"
+        };
+        grid.put_str(x, 0, label, &theme.style(group));
+        x += label.chars().count();
+    }
+}
+
+/// Draw the completion menu below `anchor`, the position of the text being completed,
+/// or above it when there is no room below before row `bottom`. The list scrolls to
+/// show the selected candidate.
+pub fn draw_popup(
+    grid: &mut Grid,
+    anchor: (usize, usize),
+    bottom: usize,
+    items: &[&Candidate],
+    selected: Option<usize>,
+    theme: &Theme,
+) {
+    let (x, y) = anchor;
+    let below = bottom.saturating_sub(y + 1);
+    let mut height = cmp::min(items.len(), POPUP_HEIGHT);
+    let top = if height <= below || y < height {
+        height = cmp::min(height, below);
+        y + 1
+    } else {
+        y - height
+    };
+    let word_width = items
+        .iter()
+        .map(|item| item.word.chars().count())
+        .max()
+        .unwrap_or(0);
+    let kind_width = items
+        .iter()
+        .map(|item| item.kind.chars().count())
+        .max()
+        .unwrap_or(0);
+    let width = cmp::min(
+        word_width + if kind_width > 0 { kind_width + 1 } else { 0 } + 2,
+        grid.width,
+    );
+    let x = cmp::min(x, grid.width - width);
+    let first = selected.map_or(0, |i| (i + 1).saturating_sub(height));
+    for (row, (i, item)) in items
+        .iter()
+        .enumerate()
+        .skip(first)
+        .take(height)
+        .enumerate()
+    {
+        let group = if Some(i) == selected {
+            "PmenuSel"
+        } else {
+            "Pmenu"
+        };
+        let mut label = format!(" {:<1$}", item.word, word_width);
+        if kind_width > 0 {
+            label += &format!(" {:<1$}", item.kind, kind_width);
+        }
+        label.push(' ');
+        let label: String = label.chars().take(width).collect();
+        grid.put_str(x, top + row, &label, &theme.style(group));
+    }
+}
+
+/// Draw the border between windows side by side, a column of `height` rows.
+pub fn draw_border(grid: &mut Grid, x: usize, y: usize, height: usize, theme: &Theme) {
+    let style = theme.style("VertSplit");
+    for row in y..y + height {
+        grid.put(x, row, &[Cell::new('|', &style)]);
+    }
+}
+
+/// Draw the status line on the last row.
+pub fn draw_status(grid: &mut Grid, mode: &Mode, args: Vec<String>, theme: &Theme) {
+    let group = match mode {
+        Mode::Normal => "StatusLineNormal",
+        Mode::Insert => "StatusLineInsert",
+        Mode::Replace(_) => "StatusLineReplace",
+        Mode::Visual | Mode::VisualLine => "StatusLineVisual",
+        Mode::Command(_) => "StatusLineCommand",
+    };
+    let y = grid.height.saturating_sub(1);
+    grid.put_str(0, y, &args.join(", "), &theme.style(group));
+}
+
+/// Draw a message of several lines at the bottom, followed by a prompt to press a key.
+/// Returns the position of the cursor, after the prompt.
+pub fn draw_message(grid: &mut Grid, message: &str, theme: &Theme) -> (usize, usize) {
+    let lines: Vec<&str> = message.lines().collect();
+    let top = (grid.height + 1).saturating_sub(lines.len() + 2);
+    for y in top..grid.height {
+        grid.put(0, y, &vec![Cell::default(); grid.width]);
+    }
+    for (y, line) in (top..grid.height).zip(&lines) {
+        grid.put_str(0, y, line, &Style::default());
+    }
+    let prompt = "Press ENTER or type command to continue";
+    let y = grid.height.saturating_sub(1);
+    grid.put_str(0, y, prompt, &theme.style("MoreMsg"));
+    (cmp::min(prompt.len(), grid.width.saturating_sub(1)), y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes() {
+        let style = Style::default();
+        let previous = Grid::new(20, 2);
+        let mut grid = previous.clone();
+        grid.put_str(1, 0, "a", &style);
+        grid.put_str(4, 0, "b", &style);
+        grid.put_str(15, 0, "c", &style);
+        grid.put_str(0, 1, "d", &style);
+        assert_eq!(grid.changes(&previous), [(0, 1..5), (0, 15..16), (1, 0..1)]);
+        assert!(grid.changes(&grid).is_empty());
+    }
+
+    #[test]
+    fn test_present_sends_only_changes() {
+        let mut terminal = VirtualTerminal::new(10, 3);
+        let mut renderer = Renderer::new(10, 3);
+        renderer.frame().put_str(0, 0, "hello", &Style::default());
+        renderer.present(&mut terminal);
+        assert_eq!(terminal.row_text(0), "hello");
+        // A cell changed behind the renderer's back stays as it is, as nothing was redrawn.
+        terminal.write_cells(0, 0, &cells("j", &Style::default()));
+        let frame = renderer.frame();
+        frame.put_str(0, 0, "hello", &Style::default());
+        frame.put_str(0, 2, "x", &Style::default());
+        frame.cursor = (1, 2);
+        renderer.present(&mut terminal);
+        assert_eq!(terminal.row_text(0), "jello");
+        assert_eq!(terminal.row_text(2), "x");
+        assert_eq!(terminal.cursor, (1, 2));
+        renderer.scroll(&mut terminal, 0..5, 2);
+        assert_eq!(terminal.row_text(0), "x");
+        renderer.frame().put_str(0, 0, "x", &Style::default());
+        renderer.present(&mut terminal);
+        assert_eq!(terminal.row_text(0), "x");
+    }
+}
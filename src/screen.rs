@@ -1,47 +1,321 @@
+use crate::layout::*;
+use crate::options::*;
 use crate::text::*;
 use std::cmp;
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct Cursor {
     pub x: usize,
     pub y: usize,
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct ScreenState {
     pub cursor: Cursor,
     pub row_offset: usize,
-    pub terminal_size: (u16, u16),
+    /// First column shown on the screen when lines are not wrapped.
+    pub col_offset: usize,
+    /// Columns and rows of the window, including its status line.
+    pub size: (u16, u16),
+    pub wrap: WrapOptions,
+    pub number: bool,
+    pub relativenumber: bool,
+    /// Two columns for the signs of diagnostics are shown left of the line numbers.
+    pub signs: bool,
+    /// Minimal number of lines to keep above and below the cursor.
+    pub scrolloff: usize,
 }
 
 impl ScreenState {
+    /// Number of rows available for text. The last row is the status line, so a window
+    /// of one row has none.
+    pub fn text_rows(&self) -> usize {
+        (self.size.1 as usize).saturating_sub(1)
+    }
+    /// Number of columns available for text, right of the line number gutter.
+    pub fn text_width(&self, text: &TextState) -> usize {
+        cmp::max(
+            (self.size.0 as usize).saturating_sub(self.gutter_width(text)),
+            1,
+        )
+    }
+    /// Width of the gutter: the sign column and the line numbers including the
+    /// separating space. It grows with the number of lines in the buffer.
+    pub fn gutter_width(&self, text: &TextState) -> usize {
+        let signs = if self.signs { 2 } else { 0 };
+        signs + self.number_width(text)
+    }
+    fn number_width(&self, text: &TextState) -> usize {
+        if self.number || self.relativenumber {
+            cmp::max(text.len().to_string().len(), 3) + 1
+        } else {
+            0
+        }
+    }
+    /// The label drawn in the gutter for `line`, padded to the gutter width. The sign
+    /// column is left blank.
+    pub fn line_number(&self, text: &TextState, line: usize) -> String {
+        let signs = if self.signs { "  " } else { "" };
+        signs.to_string() + &self.number_label(text, line)
+    }
+    fn number_label(&self, text: &TextState, line: usize) -> String {
+        let width = self.number_width(text);
+        if width == 0 {
+            return String::new();
+        }
+        let current = self.cursor.y + self.row_offset;
+        if !self.relativenumber {
+            format!("{:>1$} ", line + 1, width - 1)
+        } else if line != current {
+            format!(
+                "{:>1$} ",
+                cmp::max(line, current) - cmp::min(line, current),
+                width - 1
+            )
+        } else if self.number {
+            format!("{:<1$} ", line + 1, width - 1)
+        } else {
+            format!("{:>1$} ", 0, width - 1)
+        }
+    }
     /// Move key vertically. After that, make sure key is in valid place.
-    /// Returns the line to rewrite.
-    pub fn move_vert(&mut self, text: &TextState, movement: i32) -> Option<usize> {
-        let mut line_to_rewrite = None;
-        if movement > 0 {
-            if self.cursor.y as i32 + movement > self.terminal_size.1 as i32 - 2 {
-                self.cursor.y -= movement as usize;
-                self.row_offset += movement as usize;
-                line_to_rewrite = Some(self.cursor.y + movement as usize);
-            }
-            self.cursor.y += movement as usize;
-        }
-        if movement < 0 {
-            let distance = -movement as usize;
-            if self.cursor.y as i32 + movement < 0 {
-                if self.row_offset > 0 {
-                    self.row_offset -= distance;
+    /// Returns the number of screen rows the view scrolled up (negative when down).
+    pub fn move_vert(&mut self, text: &TextState, movement: i32) -> i32 {
+        let line = (self.cursor.y + self.row_offset) as i32 + movement;
+        let line = cmp::min(cmp::max(line, 0) as usize, text.len() - 1);
+        let scrolled = self.scroll_to_line(text, line);
+        if self.cursor.x > text[line].len() {
+            self.cursor.x = cmp::max(text[line].len(), 1) - 1;
+        }
+        scrolled
+    }
+    /// Move key by screen rows rather than by lines, as `gj`/`gk` do.
+    /// Returns the number of screen rows the view scrolled up (negative when down).
+    pub fn move_screen_row(&mut self, text: &TextState, movement: i32) -> i32 {
+        let width = self.text_width(text);
+        let prefix = self.wrap.showbreak.chars().count();
+        let mut line = self.cursor.y + self.row_offset;
+        let segments = wrap_line(&text[line], width, &self.wrap);
+        let cells = self.line_columns(text);
+        let x = cmp::min(self.cursor.x, text[line].len());
+        let mut index = segment_of(&segments, x);
+        let column = cells[x] - cells[segments[index].start] + if index > 0 { prefix } else { 0 };
+        for _ in 0..movement.unsigned_abs() {
+            if movement > 0 {
+                if index + 1 < line_height(&text[line], width, &self.wrap) {
+                    index += 1;
+                } else if line + 1 < text.len() {
+                    line += 1;
+                    index = 0;
                 }
-                self.cursor.y += distance;
-                line_to_rewrite = Some(self.cursor.y - distance);
+            } else if index > 0 {
+                index -= 1;
+            } else if line > 0 {
+                line -= 1;
+                index = line_height(&text[line], width, &self.wrap) - 1;
             }
-            self.cursor.y -= -movement as usize;
         }
-
-        if self.cursor.x > text[self.cursor.y + self.row_offset].len() {
-            self.cursor.x = cmp::max(text[self.cursor.y + self.row_offset].len(), 1) - 1;
+        let segments = wrap_line(&text[line], width, &self.wrap);
+        let columns = columns(&text[line], self.wrap.tabstop);
+        let segment = &segments[index];
+        let column = column.saturating_sub(if index > 0 { prefix } else { 0 });
+        self.cursor.x = cmp::min(
+            char_at(&columns, segment, column),
+            cmp::max(segment.end, segment.start + 1) - 1,
+        );
+        let scrolled = self.scroll_to_line(text, line);
+        if self.cursor.x > text[line].len() {
+            self.cursor.x = cmp::max(text[line].len(), 1) - 1;
+        }
+        scrolled
+    }
+    /// Number of lines kept above and below the cursor: `scrolloff`, but no more than
+    /// half of the screen.
+    fn margin(&self) -> usize {
+        cmp::min(self.scrolloff, self.text_rows().saturating_sub(1) / 2)
+    }
+    /// Adjust `row_offset` so that every screen row of `line` is visible together with
+    /// `scrolloff` lines around it, and point the cursor at it.
+    /// Returns the number of screen rows the view scrolled up (negative when down).
+    fn scroll_to_line(&mut self, text: &TextState, line: usize) -> i32 {
+        let width = self.text_width(text);
+        let old_offset = self.row_offset;
+        let margin = self.margin();
+        if line.saturating_sub(margin) < self.row_offset {
+            self.row_offset = line.saturating_sub(margin);
+        } else {
+            let bottom = cmp::min(line + margin, text.len() - 1);
+            let mut rows: usize = (self.row_offset..=bottom)
+                .map(|l| line_height(&text[l], width, &self.wrap))
+                .sum();
+            while rows > self.text_rows() && self.row_offset < line {
+                rows -= line_height(&text[self.row_offset], width, &self.wrap);
+                self.row_offset += 1;
+            }
         }
-        return line_to_rewrite;
+        self.cursor.y = line - self.row_offset;
+        self.rows_scrolled(text, old_offset)
+    }
+    /// Number of screen rows the view scrolled up since `row_offset` was `old_offset`
+    /// (negative when down).
+    fn rows_scrolled(&self, text: &TextState, old_offset: usize) -> i32 {
+        let width = self.text_width(text);
+        let rows_between = |from: usize, to: usize| -> i32 {
+            (from..to)
+                .map(|l| line_height(&text[l], width, &self.wrap) as i32)
+                .sum()
+        };
+        if self.row_offset >= old_offset {
+            rows_between(old_offset, self.row_offset)
+        } else {
+            -rows_between(self.row_offset, old_offset)
+        }
+    }
+    /// Scroll the view by `lines` lines (up if negative), as the mouse wheel does.
+    /// The cursor stays on its line unless the line leaves the screen.
+    /// Returns the number of screen rows the view scrolled up (negative when down).
+    pub fn scroll_lines(&mut self, text: &TextState, lines: i32) -> i32 {
+        let old_offset = self.row_offset;
+        let line = self.cursor.y + self.row_offset;
+        let offset = cmp::max(self.row_offset as i32 + lines, 0) as usize;
+        self.row_offset = cmp::min(offset, text.len() - 1);
+        let (top, bottom) = self.cursor_lines(text);
+        let line = cmp::min(cmp::max(line, top), bottom);
+        self.cursor.y = line - self.row_offset;
+        self.cursor.x = cmp::min(self.cursor.x, cmp::max(text[line].len(), 1) - 1);
+        self.rows_scrolled(text, old_offset)
+    }
+    /// Scroll the view and the cursor by `lines` lines (up if negative), as `Ctrl-d` and
+    /// `Ctrl-u` do. The view stops at the start of the text or when the last line is at
+    /// the bottom of the screen, and the cursor goes on. The cursor is put on the first
+    /// non-blank character.
+    /// Returns the number of screen rows the view scrolled up (negative when down).
+    pub fn scroll_half_page(&mut self, text: &TextState, lines: i32) -> i32 {
+        let old_offset = self.row_offset;
+        let line = self.cursor.y + self.row_offset;
+        let distance = lines.unsigned_abs() as usize;
+        if lines > 0 {
+            let end = self.offset_ending_at(text, text.len() - 1);
+            self.row_offset = cmp::max(cmp::min(self.row_offset + distance, end), old_offset);
+        } else {
+            self.row_offset = self.row_offset.saturating_sub(distance);
+        }
+        let line = cmp::min(cmp::max(line as i32 + lines, 0) as usize, text.len() - 1);
+        self.put_cursor_in_view(text, line);
+        self.rows_scrolled(text, old_offset)
+    }
+    /// Scroll the view by `pages` screens (up if negative), as `Ctrl-f` and `Ctrl-b` do,
+    /// keeping two lines of the previous screen. Forwards the view can go on until the
+    /// last line is at the top of the screen. The cursor is moved into the view and put
+    /// on the first non-blank character.
+    /// Returns the number of screen rows the view scrolled up (negative when down).
+    pub fn scroll_pages(&mut self, text: &TextState, pages: i32) -> i32 {
+        let old_offset = self.row_offset;
+        let line = self.cursor.y + self.row_offset;
+        for _ in 0..pages.unsigned_abs() {
+            let (first, last) = self.shown_lines(text);
+            self.row_offset = if pages > 0 {
+                cmp::min(cmp::max(last.saturating_sub(1), first + 1), text.len() - 1)
+            } else {
+                let bottom = cmp::min(first + 1, text.len() - 1);
+                cmp::min(self.offset_ending_at(text, bottom), first.saturating_sub(1))
+            };
+        }
+        self.put_cursor_in_view(text, line);
+        self.rows_scrolled(text, old_offset)
+    }
+    /// Put the cursor on the first non-blank character of `line`, or of the nearest line
+    /// it can be on without scrolling the view.
+    fn put_cursor_in_view(&mut self, text: &TextState, line: usize) {
+        let (top, bottom) = self.cursor_lines(text);
+        let line = cmp::min(cmp::max(line, top), bottom);
+        self.cursor.y = line - self.row_offset;
+        let indent = text[line].iter().take_while(|c| c.is_whitespace()).count();
+        self.cursor.x = cmp::min(indent, cmp::max(text[line].len(), 1) - 1);
+    }
+    /// Scroll the view so that the cursor line is at the top (`zt`) of the screen,
+    /// below `scrolloff` lines.
+    /// Returns the number of screen rows the view scrolled up (negative when down).
+    pub fn scroll_cursor_to_top(&mut self, text: &TextState) -> i32 {
+        let line = self.cursor.y + self.row_offset;
+        self.set_row_offset(text, line.saturating_sub(self.margin()))
+    }
+    /// Scroll the view so that the cursor line is in the middle (`zz`) of the screen.
+    /// Returns the number of screen rows the view scrolled up (negative when down).
+    pub fn scroll_cursor_to_middle(&mut self, text: &TextState) -> i32 {
+        let width = self.text_width(text);
+        let line = self.cursor.y + self.row_offset;
+        let height = line_height(&text[line], width, &self.wrap);
+        let above = self.text_rows().saturating_sub(height) / 2;
+        let mut first = line;
+        let mut rows = 0;
+        while first > 0 {
+            rows += line_height(&text[first - 1], width, &self.wrap);
+            if rows > above {
+                break;
+            }
+            first -= 1;
+        }
+        self.set_row_offset(text, first)
+    }
+    /// Scroll the view so that the cursor line is at the bottom (`zb`) of the screen,
+    /// above `scrolloff` lines.
+    /// Returns the number of screen rows the view scrolled up (negative when down).
+    pub fn scroll_cursor_to_bottom(&mut self, text: &TextState) -> i32 {
+        let line = self.cursor.y + self.row_offset;
+        let bottom = cmp::min(line + self.margin(), text.len() - 1);
+        let offset = cmp::min(self.offset_ending_at(text, bottom), line);
+        self.set_row_offset(text, offset)
+    }
+    /// Show the text from line `offset`, keeping the cursor on its line.
+    fn set_row_offset(&mut self, text: &TextState, offset: usize) -> i32 {
+        let old_offset = self.row_offset;
+        let line = self.cursor.y + self.row_offset;
+        self.row_offset = offset;
+        self.cursor.y = line - offset;
+        self.rows_scrolled(text, old_offset)
+    }
+    /// The first line of a view which shows `line` whole at the bottom of the screen.
+    fn offset_ending_at(&self, text: &TextState, line: usize) -> usize {
+        let width = self.text_width(text);
+        let mut first = line;
+        let mut rows = line_height(&text[line], width, &self.wrap);
+        while first > 0 {
+            rows += line_height(&text[first - 1], width, &self.wrap);
+            if rows > self.text_rows() {
+                break;
+            }
+            first -= 1;
+        }
+        first
+    }
+    /// The first and the last of the lines the cursor can be on without scrolling the
+    /// view, keeping `scrolloff` lines around it unless the start or the end of the text
+    /// is shown.
+    pub fn cursor_lines(&self, text: &TextState) -> (usize, usize) {
+        let margin = self.margin();
+        let (first, last) = self.shown_lines(text);
+        let top = if first == 0 { 0 } else { first + margin };
+        let bottom = if last + 1 == text.len() {
+            last
+        } else {
+            last.saturating_sub(margin)
+        };
+        let top = cmp::min(top, last);
+        (top, cmp::max(bottom, top))
+    }
+    /// The first and the last of the lines shown whole on the screen.
+    pub fn shown_lines(&self, text: &TextState) -> (usize, usize) {
+        let width = self.text_width(text);
+        let mut last = self.row_offset;
+        let mut rows = line_height(&text[last], width, &self.wrap);
+        while last + 1 < text.len() {
+            rows += line_height(&text[last + 1], width, &self.wrap);
+            if rows > self.text_rows() {
+                break;
+            }
+            last += 1;
+        }
+        (self.row_offset, last)
     }
     /// Move key horizontally. After that, make sure key is in valid place.
     pub fn move_horiz(&mut self, text: &TextState, distance: i32) {
@@ -56,5 +330,362 @@ impl ScreenState {
             );
         }
     }
+    /// Adjust `col_offset` so that the cursor is visible with `nowrap`,
+    /// honoring `sidescroll` and `sidescrolloff`.
+    pub fn scroll_horizontally(&mut self, text: &TextState) {
+        if self.wrap.wrap {
+            self.col_offset = 0;
+            return;
+        }
+        let width = self.text_width(text);
+        let margin = cmp::min(self.wrap.sidescrolloff, (width - 1) / 2);
+        let columns = self.line_columns(text);
+        let x = column_of(&columns, self.cursor.x);
+        let left = column_of(&columns, self.col_offset);
+        let left = if x < left + margin {
+            let needed = left - x.saturating_sub(margin);
+            if self.wrap.sidescroll == 0 {
+                x.saturating_sub(width / 2)
+            } else {
+                left.saturating_sub(cmp::max(needed, self.wrap.sidescroll))
+            }
+        } else if x + margin >= left + width {
+            let needed = x + margin + 1 - width - left;
+            if self.wrap.sidescroll == 0 {
+                x.saturating_sub(width / 2)
+            } else {
+                left + cmp::max(needed, self.wrap.sidescroll)
+            }
+        } else {
+            return;
+        };
+        self.col_offset = index_at(&columns, left);
+    }
+    /// The columns of the characters of the line of the cursor. See `layout::columns`.
+    fn line_columns(&self, text: &TextState) -> Vec<usize> {
+        columns(&text[self.cursor.y + self.row_offset], self.wrap.tabstop)
+    }
+    /// Scroll the view `distance` columns to the right (left if negative), as `zl`/`zh` do,
+    /// and move the cursor along when it would leave the screen.
+    pub fn scroll_columns(&mut self, text: &TextState, distance: i32) {
+        if self.wrap.wrap {
+            return;
+        }
+        if distance < 0 {
+            self.col_offset -= cmp::min(self.col_offset, distance.unsigned_abs() as usize);
+        } else {
+            self.col_offset += distance as usize;
+        }
+        let width = self.text_width(text);
+        let margin = cmp::min(self.wrap.sidescrolloff, (width - 1) / 2);
+        let len = text[self.cursor.y + self.row_offset].len();
+        let columns = self.line_columns(text);
+        let left = column_of(&columns, self.col_offset);
+        let leftmost = cmp::min(index_at(&columns, left + margin), cmp::max(len, 1) - 1);
+        // The last character starting in the columns shown.
+        let right = (left + width).saturating_sub(margin + 1);
+        let rightmost = index_at(&columns, right + 1).saturating_sub(1);
+        self.cursor.x = cmp::min(cmp::max(self.cursor.x, leftmost), rightmost);
+    }
+    /// Scroll the view so that the cursor is at the left edge (`zs`) of the screen.
+    pub fn scroll_cursor_to_left(&mut self, text: &TextState) {
+        if !self.wrap.wrap {
+            let columns = self.line_columns(text);
+            let x = column_of(&columns, self.cursor.x);
+            self.col_offset = index_at(&columns, x.saturating_sub(self.wrap.sidescrolloff));
+        }
+    }
+    /// Scroll the view so that the cursor is at the right edge (`ze`) of the screen.
+    pub fn scroll_cursor_to_right(&mut self, text: &TextState) {
+        if !self.wrap.wrap {
+            let width = self.text_width(text);
+            let columns = self.line_columns(text);
+            let x = column_of(&columns, self.cursor.x);
+            let left = (x + self.wrap.sidescrolloff + 1).saturating_sub(width);
+            self.col_offset = index_at(&columns, left);
+        }
+    }
+    /// Line and column of the text drawn at column `x` and row `y` of the window,
+    /// following line wrapping. Past the end of a line gives its length, and below the
+    /// text the last line.
+    pub fn position_at(&self, text: &TextState, x: usize, y: usize) -> (usize, usize) {
+        let width = self.text_width(text);
+        let x = x.saturating_sub(self.gutter_width(text));
+        let mut line = self.row_offset;
+        let mut row = 0;
+        loop {
+            let last_line = line + 1 == text.len();
+            let columns = columns(&text[line], self.wrap.tabstop);
+            if !self.wrap.wrap {
+                if row == y || last_line {
+                    let start = cmp::min(self.col_offset, text[line].len());
+                    return (line, char_at(&columns, &(start..text[line].len()), x));
+                }
+                row += 1;
+            } else {
+                let segments = wrap_line(&text[line], width, &self.wrap);
+                if row + segments.len() > y || last_line {
+                    let index = cmp::min(y.saturating_sub(row), segments.len() - 1);
+                    let segment = &segments[index];
+                    let prefix = if index > 0 {
+                        self.wrap.showbreak.chars().count()
+                    } else {
+                        0
+                    };
+                    // Past the end of a continued row is its last character.
+                    let end = if index + 1 < segments.len() {
+                        segment.end - 1
+                    } else {
+                        segment.end
+                    };
+                    return (
+                        line,
+                        cmp::min(char_at(&columns, segment, x.saturating_sub(prefix)), end),
+                    );
+                }
+                row += segments.len();
+            }
+            line += 1;
+        }
+    }
+    /// Zero-based terminal position of the cursor, following line wrapping.
+    pub fn cursor_position(&self, text: &TextState) -> (usize, usize) {
+        let width = self.text_width(text);
+        let line = self.cursor.y + self.row_offset;
+        let row: usize = (self.row_offset..line)
+            .map(|l| line_height(&text[l], width, &self.wrap))
+            .sum();
+        let gutter = self.gutter_width(text);
+        let columns = columns(&text[line], self.wrap.tabstop);
+        if !self.wrap.wrap {
+            let x = column_of(&columns, self.cursor.x);
+            let left = column_of(&columns, self.col_offset);
+            return (gutter + cmp::min(x.saturating_sub(left), width - 1), row);
+        }
+        let segments = wrap_line(&text[line], width, &self.wrap);
+        let x = cmp::min(self.cursor.x, text[line].len());
+        let index = segment_of(&segments, x);
+        let prefix = if index > 0 {
+            self.wrap.showbreak.chars().count()
+        } else {
+            0
+        };
+        (
+            gutter
+                + cmp::min(
+                    columns[x] - columns[segments[index].start] + prefix,
+                    width - 1,
+                ),
+            row + index,
+        )
+    }
+}
+
+/// The column where character `index` of a line with the `columns` starts, going on one
+/// column per character past the end of the line.
+fn column_of(columns: &[usize], index: usize) -> usize {
+    let len = columns.len() - 1;
+    match index.checked_sub(len) {
+        Some(past) => columns[len] + past,
+        None => columns[index],
+    }
+}
+
+/// The first character starting at `column` or right of it, the other way round from
+/// `column_of`.
+fn index_at(columns: &[usize], column: usize) -> usize {
+    let len = columns.len() - 1;
+    match column.checked_sub(columns[len]) {
+        Some(past) if past > 0 => len + past,
+        _ => columns.partition_point(|&c| c < column),
+    }
+}
+
+/// The window-local options.
+impl Options for ScreenState {
+    fn get(&self, name: &str) -> Option<Value> {
+        match name {
+            "wrap" => Some(Value::Bool(self.wrap.wrap)),
+            "linebreak" => Some(Value::Bool(self.wrap.linebreak)),
+            "showbreak" => Some(Value::String(self.wrap.showbreak.clone())),
+            "sidescroll" => Some(Value::Number(self.wrap.sidescroll)),
+            "sidescrolloff" => Some(Value::Number(self.wrap.sidescrolloff)),
+            "number" => Some(Value::Bool(self.number)),
+            "relativenumber" => Some(Value::Bool(self.relativenumber)),
+            "scrolloff" => Some(Value::Number(self.scrolloff)),
+            _ => None,
+        }
+    }
+    fn set(&mut self, name: &str, value: Value) {
+        match (name, value) {
+            ("wrap", Value::Bool(value)) => self.wrap.wrap = value,
+            ("linebreak", Value::Bool(value)) => self.wrap.linebreak = value,
+            ("showbreak", Value::String(value)) => self.wrap.showbreak = value,
+            ("sidescroll", Value::Number(value)) => self.wrap.sidescroll = value,
+            ("sidescrolloff", Value::Number(value)) => self.wrap.sidescrolloff = value,
+            ("number", Value::Bool(value)) => self.number = value,
+            ("relativenumber", Value::Bool(value)) => self.relativenumber = value,
+            ("scrolloff", Value::Number(value)) => self.scrolloff = value,
+            _ => (),
+        }
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The text of `lines` and a screen of `size` showing it from the start.
+    fn screen<S: AsRef<str>>(lines: &[S], size: (u16, u16)) -> (TextState, ScreenState) {
+        let text = lines
+            .iter()
+            .map(|line| line.as_ref().chars().collect())
+            .collect();
+        let screen = ScreenState {
+            size,
+            ..Default::default()
+        };
+        (text, screen)
+    }
+
+    #[test]
+    fn test_scroll_counts_screen_rows() {
+        let (text, mut screen) = screen(&["a".repeat(25).as_str(), "b", "c", "d"], (10, 5));
+        assert_eq!(screen.move_vert(&text, 1), 0);
+        assert_eq!(screen.move_vert(&text, 1), 3);
+        assert_eq!(screen.row_offset, 1);
+        assert_eq!(screen.cursor_position(&text), (0, 1));
+        assert_eq!(screen.move_vert(&text, -2), -3);
+    }
+
+    #[test]
+    fn test_scroll_pages() {
+        let (text, mut screen) =
+            screen(&(0..10).map(|i| i.to_string()).collect::<Vec<_>>(), (10, 5));
+        assert_eq!(screen.scroll_half_page(&text, 2), 2);
+        assert_eq!(screen.scroll_half_page(&text, 2), 2);
+        assert_eq!(screen.scroll_half_page(&text, 2), 2);
+        assert_eq!((screen.row_offset, screen.cursor.y), (6, 0));
+        // The last line is at the bottom, so only the cursor moves.
+        assert_eq!(screen.scroll_half_page(&text, 2), 0);
+        assert_eq!(screen.cursor.y, 2);
+        assert_eq!(screen.scroll_pages(&text, 1), 2);
+        assert_eq!((screen.row_offset, screen.cursor.y), (8, 0));
+        assert_eq!(screen.scroll_pages(&text, -1), -2);
+        assert_eq!((screen.row_offset, screen.cursor.y), (6, 2));
+        screen.scrolloff = 1;
+        assert_eq!(screen.scroll_lines(&text, -3), -3);
+        // The cursor keeps a line below it.
+        assert_eq!(screen.cursor.y + screen.row_offset, 5);
+        assert_eq!(screen.move_vert(&text, -3), -2);
+        assert_eq!((screen.row_offset, screen.cursor.y), (1, 1));
+    }
+
+    #[test]
+    fn test_scroll_cursor_line() {
+        let (text, mut screen) = screen(&["b", "a".repeat(25).as_str(), "c", "d"], (10, 5));
+        screen.cursor.y = 1;
+        assert_eq!(screen.scroll_cursor_to_top(&text), 1);
+        assert_eq!((screen.row_offset, screen.cursor.y), (1, 0));
+        assert_eq!(screen.scroll_cursor_to_bottom(&text), -1);
+        assert_eq!((screen.row_offset, screen.cursor.y), (0, 1));
+        assert_eq!(screen.scroll_cursor_to_middle(&text), 1);
+        screen.move_vert(&text, 2);
+        assert_eq!(screen.scroll_cursor_to_top(&text), 1);
+        // The long line above does not fit in the upper half.
+        assert_eq!(screen.scroll_cursor_to_middle(&text), -1);
+        assert_eq!((screen.row_offset, screen.cursor.y), (2, 1));
+    }
+
+    #[test]
+    fn test_move_screen_row() {
+        let (text, mut screen) = screen(&["a".repeat(25).as_str(), "b"], (10, 5));
+        screen.cursor.x = 3;
+        screen.move_screen_row(&text, 1);
+        assert_eq!((screen.cursor.x, screen.cursor.y), (13, 0));
+        screen.move_screen_row(&text, 2);
+        assert_eq!((screen.cursor.x, screen.cursor.y), (0, 1));
+        screen.move_screen_row(&text, -1);
+        assert_eq!((screen.cursor.x, screen.cursor.y), (20, 0));
+    }
+
+    #[test]
+    fn test_position_at_and_scroll_lines() {
+        let (text, mut screen) = screen(&["a".repeat(25).as_str(), "b", "c", "d"], (10, 5));
+        screen.number = true;
+        assert_eq!(screen.text_width(&text), 6);
+        assert_eq!(screen.position_at(&text, 2, 0), (0, 0));
+        assert_eq!(screen.position_at(&text, 9, 1), (0, 11));
+        assert_eq!(screen.position_at(&text, 9, 4), (0, 25));
+        assert_eq!(screen.position_at(&text, 9, 5), (1, 1));
+        assert_eq!(screen.position_at(&text, 4, 9), (3, 0));
+        assert_eq!(screen.scroll_lines(&text, 2), 6);
+        assert_eq!((screen.row_offset, screen.cursor.y), (2, 0));
+        assert_eq!(screen.position_at(&text, 4, 1), (3, 0));
+        assert_eq!(screen.scroll_lines(&text, -5), -6);
+        assert_eq!(screen.row_offset, 0);
+        // The cursor was on line 2, which is below the screen now.
+        assert_eq!(screen.cursor.y, 0);
+    }
+
+    #[test]
+    fn test_horizontal_scroll() {
+        let (text, mut screen) = screen(&["a".repeat(30)], (10, 5));
+        screen.wrap.wrap = false;
+        screen.cursor.x = 12;
+        screen.scroll_horizontally(&text);
+        assert_eq!(screen.col_offset, 7);
+        screen.wrap.sidescroll = 1;
+        screen.wrap.sidescrolloff = 2;
+        screen.cursor.x = 15;
+        screen.scroll_horizontally(&text);
+        assert_eq!(screen.col_offset, 8);
+        assert_eq!(screen.cursor_position(&text), (7, 0));
+        screen.scroll_columns(&text, 5);
+        assert_eq!((screen.col_offset, screen.cursor.x), (13, 15));
+        screen.scroll_columns(&text, 2);
+        assert_eq!(screen.cursor.x, 17);
+        screen.scroll_cursor_to_left(&text);
+        assert_eq!(screen.col_offset, 15);
+    }
+
+    #[test]
+    fn test_tabs() {
+        let (text, mut screen) = screen(&["\t\tab\tc"], (10, 5));
+        screen.wrap.tabstop = 4;
+        screen.cursor.x = 2;
+        assert_eq!(screen.cursor_position(&text), (8, 0));
+        assert_eq!(screen.position_at(&text, 5, 0), (0, 1));
+        // The line is 13 columns wide and wraps after `b`, before a tab of two columns.
+        screen.cursor.x = 5;
+        assert_eq!(screen.cursor_position(&text), (2, 1));
+        screen.move_screen_row(&text, -1);
+        assert_eq!(screen.cursor.x, 0);
+        screen.wrap.wrap = false;
+        screen.cursor.x = 4;
+        // The view starts at the first character right of the middle column.
+        screen.scroll_horizontally(&text);
+        assert_eq!(screen.col_offset, 2);
+        assert_eq!(screen.cursor_position(&text), (2, 0));
+    }
+
+    #[test]
+    fn test_line_number_gutter() {
+        let (text, mut screen) = screen(&[""; 1200], (10, 5));
+        screen.number = true;
+        screen.cursor.y = 2;
+        assert_eq!(screen.gutter_width(&text), 5);
+        assert_eq!(screen.text_width(&text), 5);
+        assert_eq!(screen.line_number(&text, 0), "   1 ");
+        assert_eq!(screen.cursor_position(&text), (5, 2));
+        screen.relativenumber = true;
+        assert_eq!(screen.line_number(&text, 0), "   2 ");
+        assert_eq!(screen.line_number(&text, 2), "3    ");
+        screen.number = false;
+        assert_eq!(screen.line_number(&text, 2), "   0 ");
+        assert_eq!(screen.gutter_width(&text[..3].to_vec()), 4);
+        screen.signs = true;
+        assert_eq!(screen.gutter_width(&text), 7);
+        assert_eq!(screen.line_number(&text, 2), "     0 ");
+    }
+}
@@ -0,0 +1,292 @@
+pub mod markdown;
+pub mod rust;
+pub mod shell;
+pub mod toml;
+
+use crate::text::*;
+use std::cmp;
+use std::ops::Range;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Kind of a highlighted piece of text. Colors are chosen per scope by the theme.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Scope {
+    Comment,
+    String,
+    Number,
+    Constant,
+    Keyword,
+    Type,
+    Function,
+    PreProc,
+    Variable,
+    Special,
+    Heading,
+    Emphasis,
+    Strong,
+    Link,
+}
+
+impl Scope {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scope::Comment => "comment",
+            Scope::String => "string",
+            Scope::Number => "number",
+            Scope::Constant => "constant",
+            Scope::Keyword => "keyword",
+            Scope::Type => "type",
+            Scope::Function => "function",
+            Scope::PreProc => "preproc",
+            Scope::Variable => "variable",
+            Scope::Special => "special",
+            Scope::Heading => "heading",
+            Scope::Emphasis => "emphasis",
+            Scope::Strong => "strong",
+            Scope::Link => "link",
+        }
+    }
+    /// Highlight group of the theme used to draw the scope.
+    pub fn group(&self) -> &'static str {
+        match self {
+            Scope::Comment => "Comment",
+            Scope::String => "String",
+            Scope::Number => "Number",
+            Scope::Constant => "Constant",
+            Scope::Keyword => "Keyword",
+            Scope::Type => "Type",
+            Scope::Function => "Function",
+            Scope::PreProc => "PreProc",
+            Scope::Variable => "Identifier",
+            Scope::Special => "Special",
+            Scope::Heading => "Title",
+            Scope::Emphasis => "Emphasis",
+            Scope::Strong => "Strong",
+            Scope::Link => "Underlined",
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Token {
+    pub range: Range<usize>,
+    pub scope: Scope,
+}
+
+/// What a grammar needs to remember from the end of one line to start the next,
+/// e.g. being inside a block comment. Zero is the state at the start of a file.
+pub type LineState = u32;
+
+pub trait Grammar {
+    fn name(&self) -> &'static str;
+    /// Whether the grammar should be used for the file.
+    fn matches(&self, path: &Path, first_line: &[char]) -> bool;
+    /// Split a line into tokens. Text not covered by a token is drawn plainly.
+    fn tokenize(&self, line: &[char], state: LineState) -> (Vec<Token>, LineState);
+}
+
+/// Grammars which can be chosen for a file. More can be registered at runtime.
+pub struct Registry {
+    grammars: Vec<Rc<dyn Grammar>>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry {
+            grammars: vec![
+                Rc::new(rust::Rust),
+                Rc::new(toml::Toml),
+                Rc::new(markdown::Markdown),
+                Rc::new(shell::Shell),
+            ],
+        }
+    }
+}
+
+impl Registry {
+    /// Add a grammar, taking precedence over those already registered.
+    pub fn register(&mut self, grammar: Rc<dyn Grammar>) {
+        self.grammars.insert(0, grammar);
+    }
+    pub fn find(&self, path: &Path, first_line: &[char]) -> Option<Rc<dyn Grammar>> {
+        self.grammars
+            .iter()
+            .find(|grammar| grammar.matches(path, first_line))
+            .cloned()
+    }
+    pub fn by_name(&self, name: &str) -> Option<Rc<dyn Grammar>> {
+        self.grammars
+            .iter()
+            .find(|grammar| grammar.name() == name)
+            .cloned()
+    }
+}
+
+/// Whether the file has one of the extensions.
+pub fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(extension) => extensions.contains(&extension),
+        None => false,
+    }
+}
+
+/// Tokens of each line, computed lazily from the top of the file and kept
+/// until the line or a line above it is edited.
+pub struct Highlighter {
+    grammar: Rc<dyn Grammar>,
+    /// State at the start of each highlighted line, plus one for the line after the last.
+    states: Vec<LineState>,
+    tokens: Vec<Vec<Token>>,
+}
+
+impl Highlighter {
+    pub fn new(grammar: Rc<dyn Grammar>) -> Self {
+        Highlighter {
+            grammar,
+            states: vec![0],
+            tokens: Vec::new(),
+        }
+    }
+    pub fn grammar(&self) -> &dyn Grammar {
+        self.grammar.as_ref()
+    }
+    /// Forget what was computed for `line` and everything below it.
+    pub fn invalidate(&mut self, line: usize) {
+        self.tokens.truncate(line);
+        self.states.truncate(line + 1);
+    }
+    /// Make sure lines up to `last_line` are highlighted, starting from the first
+    /// line which is not cached.
+    pub fn update(&mut self, text: &TextState, last_line: usize) {
+        let last_line = cmp::min(last_line, text.len().saturating_sub(1));
+        while self.tokens.len() <= last_line && self.tokens.len() < text.len() {
+            let line = self.tokens.len();
+            let (tokens, state) = self.grammar.tokenize(&text[line], self.states[line]);
+            self.tokens.push(tokens);
+            self.states.push(state);
+        }
+    }
+    /// Highlighted tokens of a line, if it was highlighted by `update`.
+    pub fn tokens(&self, line: usize) -> &[Token] {
+        match self.tokens.get(line) {
+            Some(tokens) => tokens,
+            None => &[],
+        }
+    }
+    /// Number of lines currently cached.
+    pub fn cached_lines(&self) -> usize {
+        self.tokens.len()
+    }
+}
+
+/// Cursor over a line shared by the grammars' hand written tokenizers.
+pub struct Scanner<'a> {
+    pub line: &'a [char],
+    pub pos: usize,
+    pub tokens: Vec<Token>,
+}
+
+impl<'a> Scanner<'a> {
+    pub fn new(line: &'a [char]) -> Self {
+        Scanner {
+            line,
+            pos: 0,
+            tokens: Vec::new(),
+        }
+    }
+    pub fn done(&self) -> bool {
+        self.pos >= self.line.len()
+    }
+    pub fn peek(&self) -> Option<char> {
+        self.line.get(self.pos).copied()
+    }
+    pub fn peek_at(&self, offset: usize) -> Option<char> {
+        self.line.get(self.pos + offset).copied()
+    }
+    pub fn previous(&self) -> Option<char> {
+        if self.pos == 0 {
+            None
+        } else {
+            self.line.get(self.pos - 1).copied()
+        }
+    }
+    pub fn starts_with(&self, pattern: &str) -> bool {
+        pattern
+            .chars()
+            .enumerate()
+            .all(|(i, c)| self.line.get(self.pos + i) == Some(&c))
+    }
+    /// Advance while `predicate` holds and return the position where it started.
+    pub fn skip_while<F: Fn(char) -> bool>(&mut self, predicate: F) -> usize {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if !predicate(c) {
+                break;
+            }
+            self.pos += 1;
+        }
+        start
+    }
+    /// Advance past the first occurrence of `pattern`, or to the end of the line.
+    /// Returns whether it was found.
+    pub fn skip_past(&mut self, pattern: &str) -> bool {
+        while !self.done() {
+            if self.starts_with(pattern) {
+                self.pos += pattern.chars().count();
+                return true;
+            }
+            self.pos += 1;
+        }
+        false
+    }
+    pub fn text(&self, range: Range<usize>) -> String {
+        self.line[range].iter().collect()
+    }
+    /// Record a token from `start` to the current position.
+    pub fn push(&mut self, start: usize, scope: Scope) {
+        self.pos = cmp::min(self.pos, self.line.len());
+        if start < self.pos {
+            self.tokens.push(Token {
+                range: start..self.pos,
+                scope,
+            });
+        }
+    }
+    /// Record the rest of the line as a token.
+    pub fn push_rest(&mut self, scope: Scope) {
+        let start = self.pos;
+        self.pos = self.line.len();
+        self.push(start, scope);
+    }
+}
+
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_highlighter_is_incremental() {
+        let grammar = Registry::default()
+            .find(&PathBuf::from("main.rs"), &[])
+            .unwrap();
+        let mut highlighter = Highlighter::new(grammar);
+        let mut text: TextState = ["/* a", "b */", "let"]
+            .iter()
+            .map(|l| l.chars().collect())
+            .collect();
+        highlighter.update(&text, 1);
+        assert_eq!(highlighter.cached_lines(), 2);
+        assert_eq!(highlighter.tokens(1)[0].scope, Scope::Comment);
+        text[0] = "a".chars().collect();
+        highlighter.invalidate(0);
+        highlighter.update(&text, 2);
+        assert_eq!(highlighter.tokens(0), &[]);
+        assert_eq!(highlighter.tokens(2)[0].scope, Scope::Keyword);
+    }
+}
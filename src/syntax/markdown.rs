@@ -0,0 +1,109 @@
+use crate::syntax::*;
+
+/// Inside a fenced code block.
+const IN_CODE_BLOCK: LineState = 1;
+
+pub struct Markdown;
+
+impl Grammar for Markdown {
+    fn name(&self) -> &'static str {
+        "markdown"
+    }
+    fn matches(&self, path: &Path, _first_line: &[char]) -> bool {
+        has_extension(path, &["md", "markdown"])
+    }
+    fn tokenize(&self, line: &[char], state: LineState) -> (Vec<Token>, LineState) {
+        let mut s = Scanner::new(line);
+        s.skip_while(|c| c == ' ');
+        if s.starts_with("```") || s.starts_with("~~~") {
+            s.push_rest(Scope::PreProc);
+            let state = if state == IN_CODE_BLOCK {
+                0
+            } else {
+                IN_CODE_BLOCK
+            };
+            return (s.tokens, state);
+        }
+        if state == IN_CODE_BLOCK {
+            s.pos = 0;
+            s.push_rest(Scope::String);
+            return (s.tokens, state);
+        }
+        let start = s.pos;
+        if s.peek() == Some('#') {
+            s.push_rest(Scope::Heading);
+            return (s.tokens, state);
+        }
+        if s.peek() == Some('>') {
+            s.push_rest(Scope::Comment);
+            return (s.tokens, state);
+        }
+        if matches!(s.peek(), Some('-') | Some('*') | Some('+')) && s.peek_at(1) == Some(' ') {
+            s.pos += 1;
+            s.push(start, Scope::Special);
+        } else if s.peek().is_some_and(|c| c.is_ascii_digit()) {
+            s.skip_while(|c| c.is_ascii_digit());
+            if s.peek() == Some('.') && s.peek_at(1) == Some(' ') {
+                s.pos += 1;
+                s.push(start, Scope::Special);
+            }
+        }
+        while !s.done() {
+            let start = s.pos;
+            if s.peek() == Some('\\') {
+                s.pos += 2;
+            } else if s.peek() == Some('`') {
+                s.pos += 1;
+                s.skip_past("`");
+                s.push(start, Scope::String);
+            } else if s.starts_with("**") || s.starts_with("__") {
+                let delimiter = s.text(s.pos..s.pos + 2);
+                s.pos += 2;
+                s.skip_past(&delimiter);
+                s.push(start, Scope::Strong);
+            } else if (s.peek() == Some('*') || s.peek() == Some('_'))
+                && !s.previous().is_some_and(is_word_char)
+                && s.peek_at(1).is_some_and(|c| c != ' ')
+            {
+                let delimiter = s.text(s.pos..s.pos + 1);
+                s.pos += 1;
+                s.skip_past(&delimiter);
+                s.push(start, Scope::Emphasis);
+            } else if s.peek() == Some('[') || s.starts_with("![") {
+                s.skip_past("]");
+                if s.peek() == Some('(') {
+                    s.skip_past(")");
+                }
+                s.push(start, Scope::Link);
+            } else if s.starts_with("http://") || s.starts_with("https://") {
+                s.skip_while(|c| !c.is_whitespace());
+                s.push(start, Scope::Link);
+            } else {
+                s.pos += 1;
+            }
+        }
+        (s.tokens, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        let line: Vec<char> = "- `code` and **bold** [link](url)".chars().collect();
+        let (tokens, state) = Markdown.tokenize(&line, 0);
+        assert_eq!(state, 0);
+        let scopes: Vec<Scope> = tokens.iter().map(|t| t.scope).collect();
+        assert_eq!(
+            scopes,
+            vec![Scope::Special, Scope::String, Scope::Strong, Scope::Link]
+        );
+        let fence: Vec<char> = "```rust".chars().collect();
+        let (_, state) = Markdown.tokenize(&fence, 0);
+        assert_eq!(state, IN_CODE_BLOCK);
+        let line: Vec<char> = "# not a heading".chars().collect();
+        assert_eq!(Markdown.tokenize(&line, state).0[0].scope, Scope::String);
+    }
+}
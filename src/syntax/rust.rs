@@ -0,0 +1,226 @@
+use crate::syntax::*;
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref",
+    "return", "self", "Self", "static", "struct", "super", "trait", "type", "unsafe", "use",
+    "where", "while",
+];
+const TYPES: &[&str] = &[
+    "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64",
+    "i128", "isize", "f32", "f64",
+];
+const CONSTANTS: &[&str] = &["true", "false", "None", "Some", "Ok", "Err"];
+
+/// Block comments nest, so their depth is kept in the low bits of the state.
+const COMMENT_DEPTH: LineState = 0xffff;
+const IN_STRING: LineState = 1 << 16;
+/// Inside a raw string; the number of `#` is kept in the low bits.
+const IN_RAW_STRING: LineState = 1 << 17;
+
+pub struct Rust;
+
+impl Grammar for Rust {
+    fn name(&self) -> &'static str {
+        "rust"
+    }
+    fn matches(&self, path: &Path, _first_line: &[char]) -> bool {
+        has_extension(path, &["rs"])
+    }
+    fn tokenize(&self, line: &[char], mut state: LineState) -> (Vec<Token>, LineState) {
+        let mut s = Scanner::new(line);
+        let mut previous_word = String::new();
+        while !s.done() {
+            let start = s.pos;
+            if state & COMMENT_DEPTH > 0 && state & IN_RAW_STRING == 0 {
+                while !s.done() && state & COMMENT_DEPTH > 0 {
+                    if s.starts_with("/*") {
+                        state += 1;
+                        s.pos += 2;
+                    } else if s.starts_with("*/") {
+                        state -= 1;
+                        s.pos += 2;
+                    } else {
+                        s.pos += 1;
+                    }
+                }
+                s.push(start, Scope::Comment);
+            } else if state & IN_STRING > 0 {
+                if string_body(&mut s) {
+                    state = 0;
+                }
+                s.push(start, Scope::String);
+            } else if state & IN_RAW_STRING > 0 {
+                let closing = format!("\"{}", "#".repeat((state & COMMENT_DEPTH) as usize));
+                if s.skip_past(&closing) {
+                    state = 0;
+                }
+                s.push(start, Scope::String);
+            } else if s.starts_with("//") {
+                s.push_rest(Scope::Comment);
+            } else if s.starts_with("/*") {
+                state = 1;
+                s.pos += 2;
+                s.push(start, Scope::Comment);
+            } else if s.starts_with("#[") || s.starts_with("#![") {
+                s.skip_past("]");
+                s.push(start, Scope::PreProc);
+            } else if s.starts_with("r\"") || s.starts_with("r#") || s.starts_with("br\"") {
+                s.skip_while(|c| c == 'b' || c == 'r');
+                let hashes = s.pos;
+                s.skip_while(|c| c == '#');
+                let hashes = s.pos - hashes;
+                if s.peek() == Some('"') {
+                    s.pos += 1;
+                    let closing = format!("\"{}", "#".repeat(hashes));
+                    if !s.skip_past(&closing) {
+                        state = IN_RAW_STRING | hashes as LineState;
+                    }
+                    s.push(start, Scope::String);
+                } else {
+                    s.skip_while(is_word_char);
+                }
+            } else if s.peek() == Some('"') || s.starts_with("b\"") {
+                s.skip_past("\"");
+                if !string_body(&mut s) {
+                    state = IN_STRING;
+                }
+                s.push(start, Scope::String);
+            } else if s.peek() == Some('\'') {
+                // A character literal, unless it is a lifetime such as 'a.
+                if s.peek_at(1) == Some('\\') || s.peek_at(2) == Some('\'') {
+                    s.pos += 1;
+                    if s.peek() == Some('\\') {
+                        s.pos += 1;
+                    }
+                    s.pos += 1;
+                    s.skip_past("'");
+                    s.push(start, Scope::String);
+                } else {
+                    s.pos += 1;
+                    s.skip_while(is_word_char);
+                    s.push(start, Scope::Special);
+                }
+            } else if s.peek().is_some_and(|c| c.is_ascii_digit()) {
+                // A fraction, but neither a range such as `0..10` nor tuple fields such
+                // as `x.0.1`.
+                let field = s.previous() == Some('.');
+                s.skip_while(is_word_char);
+                if !field
+                    && s.peek() == Some('.')
+                    && s.peek_at(1).is_some_and(|c| c.is_ascii_digit())
+                {
+                    s.pos += 1;
+                    s.skip_while(is_word_char);
+                }
+                s.push(start, Scope::Number);
+            } else if s.peek().is_some_and(is_word_char) {
+                s.skip_while(is_word_char);
+                let word = s.text(start..s.pos);
+                let scope = if s.peek() == Some('!') {
+                    s.pos += 1;
+                    Some(Scope::PreProc)
+                } else if KEYWORDS.contains(&word.as_str()) {
+                    Some(Scope::Keyword)
+                } else if CONSTANTS.contains(&word.as_str()) {
+                    Some(Scope::Constant)
+                } else if TYPES.contains(&word.as_str())
+                    || word.chars().next().is_some_and(char::is_uppercase)
+                {
+                    if word.chars().all(|c| c.is_uppercase() || c == '_') && word.len() > 1 {
+                        Some(Scope::Constant)
+                    } else {
+                        Some(Scope::Type)
+                    }
+                } else if s.peek() == Some('(') || previous_word == "fn" {
+                    Some(Scope::Function)
+                } else {
+                    None
+                };
+                if let Some(scope) = scope {
+                    s.push(start, scope);
+                }
+                previous_word = word;
+            } else {
+                s.pos += 1;
+            }
+        }
+        (s.tokens, state)
+    }
+}
+
+/// Advance through a string body after its opening quote.
+/// Returns whether the closing quote was found on this line.
+fn string_body(s: &mut Scanner) -> bool {
+    while let Some(c) = s.peek() {
+        s.pos += 1;
+        match c {
+            '\\' => s.pos += 1,
+            '"' => return true,
+            _ => (),
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scopes(line: &str, state: LineState) -> (Vec<(String, Scope)>, LineState) {
+        let line: Vec<char> = line.chars().collect();
+        let (tokens, state) = Rust.tokenize(&line, state);
+        (
+            tokens
+                .into_iter()
+                .map(|t| (line[t.range].iter().collect(), t.scope))
+                .collect(),
+            state,
+        )
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let (tokens, state) = scopes("pub fn f<'a>(x: &'a str) -> Foo { \"a\\\"\" } // c", 0);
+        assert_eq!(state, 0);
+        assert_eq!(
+            tokens,
+            vec![
+                ("pub".to_string(), Scope::Keyword),
+                ("fn".to_string(), Scope::Keyword),
+                ("f".to_string(), Scope::Function),
+                ("'a".to_string(), Scope::Special),
+                ("'a".to_string(), Scope::Special),
+                ("str".to_string(), Scope::Type),
+                ("Foo".to_string(), Scope::Type),
+                ("\"a\\\"\"".to_string(), Scope::String),
+                ("// c".to_string(), Scope::Comment),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multiline_state() {
+        let (_, state) = scopes("/* /* */", 0);
+        assert_eq!(state, 1);
+        let (tokens, state) = scopes("*/ 'x'", state);
+        assert_eq!(state, 0);
+        assert_eq!(tokens[1], ("'x'".to_string(), Scope::String));
+        let (_, state) = scopes("let s = r#\"", 0);
+        assert_eq!(state, IN_RAW_STRING | 1);
+        let (tokens, state) = scopes("\"# 1", state);
+        assert_eq!(state, 0);
+        assert_eq!(tokens[1], ("1".to_string(), Scope::Number));
+    }
+
+    #[test]
+    fn test_numbers() {
+        let (tokens, _) = scopes("0..10 1.5e3 x.0.1 2u8", 0);
+        let numbers: Vec<String> = tokens
+            .into_iter()
+            .filter(|(_, scope)| *scope == Scope::Number)
+            .map(|(text, _)| text)
+            .collect();
+        assert_eq!(numbers, ["0", "10", "1.5e3", "0", "1", "2u8"]);
+    }
+}
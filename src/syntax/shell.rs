@@ -0,0 +1,157 @@
+use crate::syntax::*;
+
+const KEYWORDS: &[&str] = &[
+    "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case", "esac",
+    "in", "function", "select", "return", "break", "continue", "local", "export", "readonly",
+    "declare", "unset", "shift", "exit",
+];
+const BUILTINS: &[&str] = &[
+    "echo", "printf", "cd", "pwd", "read", "source", "eval", "exec", "set", "test", "trap",
+    "alias", "type", "wait", "true", "false",
+];
+
+const IN_DOUBLE_QUOTE: LineState = 1;
+const IN_SINGLE_QUOTE: LineState = 2;
+
+pub struct Shell;
+
+impl Grammar for Shell {
+    fn name(&self) -> &'static str {
+        "sh"
+    }
+    fn matches(&self, path: &Path, first_line: &[char]) -> bool {
+        let shebang: String = first_line.iter().collect();
+        has_extension(path, &["sh", "bash", "zsh"])
+            || path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| [".bashrc", ".zshrc", ".profile"].contains(&n))
+            || (shebang.starts_with("#!") && shebang.ends_with("sh"))
+    }
+    fn tokenize(&self, line: &[char], mut state: LineState) -> (Vec<Token>, LineState) {
+        let mut s = Scanner::new(line);
+        while !s.done() {
+            let start = s.pos;
+            if state == IN_SINGLE_QUOTE {
+                if s.skip_past("'") {
+                    state = 0;
+                }
+                s.push(start, Scope::String);
+            } else if state == IN_DOUBLE_QUOTE {
+                if double_quoted(&mut s) {
+                    state = 0;
+                }
+            } else if s.peek() == Some('#') && s.previous().is_none_or(char::is_whitespace) {
+                s.push_rest(Scope::Comment);
+            } else if s.peek() == Some('\'') {
+                s.pos += 1;
+                if !s.skip_past("'") {
+                    state = IN_SINGLE_QUOTE;
+                }
+                s.push(start, Scope::String);
+            } else if s.peek() == Some('"') {
+                s.pos += 1;
+                s.push(start, Scope::String);
+                if !double_quoted(&mut s) {
+                    state = IN_DOUBLE_QUOTE;
+                }
+            } else if s.peek() == Some('$') {
+                variable(&mut s);
+            } else if s.peek() == Some('\\') {
+                s.pos += 2;
+            } else if s.peek().is_some_and(|c| c.is_ascii_digit())
+                && !s.previous().is_some_and(is_word_char)
+            {
+                s.skip_while(|c| c.is_ascii_digit());
+                if s.peek().is_none_or(|c| !is_word_char(c)) {
+                    s.push(start, Scope::Number);
+                }
+            } else if s.peek().is_some_and(is_word_char) {
+                s.skip_while(|c| is_word_char(c) || c == '-');
+                let word = s.text(start..s.pos);
+                if s.peek() == Some('=') {
+                    s.push(start, Scope::Variable);
+                } else if KEYWORDS.contains(&word.as_str()) {
+                    s.push(start, Scope::Keyword);
+                } else if BUILTINS.contains(&word.as_str()) {
+                    s.push(start, Scope::Function);
+                }
+            } else {
+                s.pos += 1;
+            }
+        }
+        (s.tokens, state)
+    }
+}
+
+/// Highlight a `$name`, `${...}`, `$(` or special parameter at the cursor.
+fn variable(s: &mut Scanner) {
+    let start = s.pos;
+    s.pos += 1;
+    match s.peek() {
+        Some('{') => {
+            s.skip_past("}");
+        }
+        Some('(') => s.pos += 1,
+        Some(c) if is_word_char(c) => {
+            s.skip_while(is_word_char);
+        }
+        Some(_) => s.pos += 1,
+        None => (),
+    }
+    s.push(start, Scope::Variable);
+}
+
+/// Advance through a double quoted string, highlighting variables inside it.
+/// Returns whether the closing quote was found on this line.
+fn double_quoted(s: &mut Scanner) -> bool {
+    let mut start = s.pos;
+    while let Some(c) = s.peek() {
+        match c {
+            '\\' => s.pos += 2,
+            '"' => {
+                s.pos += 1;
+                s.push(start, Scope::String);
+                return true;
+            }
+            '$' => {
+                s.push(start, Scope::String);
+                variable(s);
+                start = s.pos;
+            }
+            _ => s.pos += 1,
+        }
+    }
+    s.push(start, Scope::String);
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        let line: Vec<char> = "if [ -n \"$HOME\" ]; then echo 1 # done".chars().collect();
+        let (tokens, state) = Shell.tokenize(&line, 0);
+        assert_eq!(state, 0);
+        let scopes: Vec<Scope> = tokens.iter().map(|t| t.scope).collect();
+        assert_eq!(
+            scopes,
+            vec![
+                Scope::Keyword,
+                Scope::String,
+                Scope::Variable,
+                Scope::String,
+                Scope::Keyword,
+                Scope::Function,
+                Scope::Number,
+                Scope::Comment
+            ]
+        );
+        let line: Vec<char> = "x='a".chars().collect();
+        assert_eq!(Shell.tokenize(&line, 0).1, IN_SINGLE_QUOTE);
+        let shebang: Vec<char> = "#!/bin/bash".chars().collect();
+        assert!(Shell.matches(Path::new("script"), &shebang));
+    }
+}
@@ -0,0 +1,134 @@
+use crate::syntax::*;
+
+const IN_BASIC_STRING: LineState = 1;
+const IN_LITERAL_STRING: LineState = 2;
+
+pub struct Toml;
+
+impl Grammar for Toml {
+    fn name(&self) -> &'static str {
+        "toml"
+    }
+    fn matches(&self, path: &Path, _first_line: &[char]) -> bool {
+        has_extension(path, &["toml"]) || path.file_name().is_some_and(|n| n == "Cargo.lock")
+    }
+    fn tokenize(&self, line: &[char], mut state: LineState) -> (Vec<Token>, LineState) {
+        let mut s = Scanner::new(line);
+        let mut after_equals = false;
+        // Open inline tables and arrays; keys follow `{` and `,` inside a table.
+        let mut nesting = Vec::new();
+        while !s.done() {
+            let start = s.pos;
+            if state == IN_BASIC_STRING {
+                if s.skip_past("\"\"\"") {
+                    state = 0;
+                }
+                s.push(start, Scope::String);
+            } else if state == IN_LITERAL_STRING {
+                if s.skip_past("'''") {
+                    state = 0;
+                }
+                s.push(start, Scope::String);
+            } else if s.peek() == Some('#') {
+                s.push_rest(Scope::Comment);
+            } else if s.starts_with("\"\"\"") || s.starts_with("'''") {
+                let quotes = s.text(s.pos..s.pos + 3);
+                s.pos += 3;
+                if !s.skip_past(&quotes) {
+                    state = if quotes == "\"\"\"" {
+                        IN_BASIC_STRING
+                    } else {
+                        IN_LITERAL_STRING
+                    };
+                }
+                s.push(start, Scope::String);
+            } else if s.peek() == Some('"') || s.peek() == Some('\'') {
+                let quote = s.peek().unwrap();
+                s.pos += 1;
+                while let Some(c) = s.peek() {
+                    s.pos += 1;
+                    if c == '\\' && quote == '"' {
+                        s.pos += 1;
+                    } else if c == quote {
+                        break;
+                    }
+                }
+                let scope = if after_equals {
+                    Scope::String
+                } else {
+                    Scope::Variable
+                };
+                s.push(start, scope);
+            } else if s.peek() == Some('[') && !after_equals {
+                s.skip_past("]");
+                s.skip_while(|c| c == ']');
+                s.push(start, Scope::Heading);
+            } else if s.peek() == Some('=') {
+                after_equals = true;
+                s.pos += 1;
+            } else if s.peek().is_some_and(|c| is_word_char(c) || c == '-') {
+                s.skip_while(|c| is_word_char(c) || c == '-' || c == '.' || c == ':');
+                let word = s.text(start..s.pos);
+                if !after_equals {
+                    s.push(start, Scope::Variable);
+                } else if word == "true" || word == "false" {
+                    s.push(start, Scope::Constant);
+                } else if word
+                    .chars()
+                    .next()
+                    .is_some_and(|c| c.is_ascii_digit() || c == '-' || c == '+')
+                    || word == "inf"
+                    || word == "nan"
+                {
+                    s.push(start, Scope::Number);
+                }
+            } else {
+                match s.peek().unwrap() {
+                    '{' => {
+                        nesting.push('{');
+                        after_equals = false;
+                    }
+                    '[' => nesting.push('['),
+                    '}' | ']' => {
+                        nesting.pop();
+                        after_equals = true;
+                    }
+                    ',' if nesting.last() == Some(&'{') => after_equals = false,
+                    _ => (),
+                }
+                s.pos += 1;
+            }
+        }
+        (s.tokens, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        let line: Vec<char> = "[dependencies] # deps".chars().collect();
+        let (tokens, _) = Toml.tokenize(&line, 0);
+        assert_eq!(tokens[0].scope, Scope::Heading);
+        assert_eq!(tokens[1].scope, Scope::Comment);
+        let line: Vec<char> = "termion = { version = \"1.5\", default = false }"
+            .chars()
+            .collect();
+        let (tokens, _) = Toml.tokenize(&line, 0);
+        let scopes: Vec<Scope> = tokens.iter().map(|t| t.scope).collect();
+        assert_eq!(
+            scopes,
+            vec![
+                Scope::Variable,
+                Scope::Variable,
+                Scope::String,
+                Scope::Variable,
+                Scope::Constant
+            ]
+        );
+        let line: Vec<char> = "a = \"\"\"x".chars().collect();
+        assert_eq!(Toml.tokenize(&line, 0).1, IN_BASIC_STRING);
+    }
+}
@@ -0,0 +1,395 @@
+use crate::event::*;
+use crate::theme::*;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+use termion::event::{Event, Key};
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Cell {
+    pub ch: char,
+    pub style: Style,
+}
+
+impl Cell {
+    pub fn new(ch: char, style: &Style) -> Self {
+        Cell {
+            ch,
+            style: style.clone(),
+        }
+    }
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell {
+            ch: ' ',
+            style: Style::default(),
+        }
+    }
+}
+
+/// Turn `text` into cells drawn with `style`.
+pub fn cells(text: &str, style: &Style) -> Vec<Cell> {
+    text.chars().map(|ch| Cell::new(ch, style)).collect()
+}
+
+/// Move `rows` of `cells` up by `distance` rows (down if negative), filling the rows
+/// scrolled into view with `blank`.
+pub fn scroll_rows<T: Clone>(cells: &mut [T], rows: Range<usize>, distance: i32, blank: &T) {
+    let rows = rows.start..std::cmp::min(rows.end, cells.len());
+    if rows.start >= rows.end {
+        return;
+    }
+    let region = &mut cells[rows];
+    let count = std::cmp::min(distance.unsigned_abs() as usize, region.len());
+    let length = region.len();
+    if distance > 0 {
+        region.rotate_left(count);
+        region[length - count..].fill(blank.clone());
+    } else {
+        region.rotate_right(count);
+        region[..count].fill(blank.clone());
+    }
+}
+
+/// Where the editor draws and where its input comes from.
+/// Coordinates are zero-based columns and rows.
+pub trait Terminal {
+    /// Number of columns and rows.
+    fn size(&self) -> (u16, u16);
+    fn color_support(&self) -> ColorSupport;
+    /// Blank the whole screen.
+    fn clear(&mut self);
+    /// Blank one row.
+    fn clear_row(&mut self, y: u16);
+    /// Move the contents of `rows` up by `distance` rows (down if negative), leaving the
+    /// other rows as they are.
+    fn scroll(&mut self, rows: Range<u16>, distance: i32);
+    /// Draw cells from the position to the right. Cells past the right edge are dropped.
+    fn write_cells(&mut self, x: u16, y: u16, cells: &[Cell]);
+    fn set_cursor(&mut self, x: u16, y: u16);
+    /// Turn the reporting of mouse events on or off.
+    fn set_mouse(&mut self, enabled: bool);
+    fn flush(&mut self);
+    /// Wait for the next event, for at most `timeout` if given.
+    /// `None` when there will be no more input.
+    fn next_event(&mut self, timeout: Option<Duration>) -> Option<EditorEvent>;
+    /// A sender through which other threads can deliver events, such as messages of
+    /// language servers. `None` when events cannot come from elsewhere.
+    fn waker(&self) -> Option<Sender<EditorEvent>>;
+}
+
+/// A real terminal driven through termion.
+pub struct TermionTerminal<W>
+where
+    W: Write,
+{
+    stdout: W,
+    events: Receiver<EditorEvent>,
+    waker: Waker,
+    support: ColorSupport,
+}
+
+impl<W> TermionTerminal<W>
+where
+    W: Write,
+{
+    /// Turns on bracketed paste, so that pasted text comes as a whole.
+    pub fn new<R>(reader: R, mut stdout: W) -> Self
+    where
+        R: Read + Send + 'static,
+    {
+        write!(stdout, "\x1b[?2004h").unwrap();
+        let (events, waker) = spawn_event_sources(reader);
+        TermionTerminal {
+            stdout,
+            events,
+            waker,
+            support: ColorSupport::detect(),
+        }
+    }
+
+    /// Escape sequence selecting the color, degraded to what the terminal supports.
+    fn color_escape(&self, color: Color, background: bool) -> String {
+        color_escape(self.support, color, background)
+    }
+}
+
+impl<W> Drop for TermionTerminal<W>
+where
+    W: Write,
+{
+    fn drop(&mut self) {
+        write!(self.stdout, "\x1b[?2004l").unwrap();
+        self.stdout.flush().unwrap();
+    }
+}
+
+/// Escape sequence selecting the color with the given color support.
+pub fn color_escape(support: ColorSupport, color: Color, background: bool) -> String {
+    let Color(r, g, b) = color;
+    match (support, background) {
+        (ColorSupport::TrueColor, false) => termion::color::Rgb(r, g, b).fg_string(),
+        (ColorSupport::TrueColor, true) => termion::color::Rgb(r, g, b).bg_string(),
+        (ColorSupport::Ansi256, false) => termion::color::AnsiValue(color.to_ansi256()).fg_string(),
+        (ColorSupport::Ansi256, true) => termion::color::AnsiValue(color.to_ansi256()).bg_string(),
+        (ColorSupport::Ansi16, background) => {
+            let index = color.to_ansi16();
+            let base = match (background, index >= 8) {
+                (false, false) => 30,
+                (false, true) => 90,
+                (true, false) => 40,
+                (true, true) => 100,
+            };
+            format!("\x1b[{}m", base + (index % 8) as u32)
+        }
+    }
+}
+
+impl<W> Terminal for TermionTerminal<W>
+where
+    W: Write,
+{
+    fn size(&self) -> (u16, u16) {
+        termion::terminal_size().unwrap_or((80, 24))
+    }
+    fn color_support(&self) -> ColorSupport {
+        self.support
+    }
+    fn clear(&mut self) {
+        write!(self.stdout, "{}", termion::clear::All).unwrap();
+    }
+    fn clear_row(&mut self, y: u16) {
+        write!(
+            self.stdout,
+            "{}{}",
+            termion::cursor::Goto(1, y + 1),
+            termion::clear::CurrentLine
+        )
+        .unwrap();
+    }
+    fn scroll(&mut self, rows: Range<u16>, distance: i32) {
+        // A scroll region (DECSTBM) keeps the other rows in place. Resetting it moves the
+        // cursor, which is put back when the frame is sent.
+        write!(self.stdout, "\x1b[{};{}r", rows.start + 1, rows.end).unwrap();
+        if distance > 0 {
+            write!(self.stdout, "{}", termion::scroll::Up(distance as u16)).unwrap();
+        } else if distance < 0 {
+            write!(self.stdout, "{}", termion::scroll::Down(-distance as u16)).unwrap();
+        }
+        write!(self.stdout, "\x1b[r").unwrap();
+    }
+    fn write_cells(&mut self, x: u16, y: u16, cells: &[Cell]) {
+        write!(self.stdout, "{}", termion::cursor::Goto(x + 1, y + 1)).unwrap();
+        let mut i = 0;
+        while i < cells.len() {
+            let style = &cells[i].style;
+            let run: String = cells[i..]
+                .iter()
+                .take_while(|cell| &cell.style == style)
+                .map(|cell| cell.ch)
+                .collect();
+            i += run.chars().count();
+            if *style == Style::default() {
+                write!(self.stdout, "{}", run).unwrap();
+                continue;
+            }
+            if let Some(color) = style.fg {
+                write!(self.stdout, "{}", self.color_escape(color, false)).unwrap();
+            }
+            if let Some(color) = style.bg {
+                write!(self.stdout, "{}", self.color_escape(color, true)).unwrap();
+            }
+            if style.bold {
+                write!(self.stdout, "{}", termion::style::Bold).unwrap();
+            }
+            if style.italic {
+                write!(self.stdout, "{}", termion::style::Italic).unwrap();
+            }
+            if style.underline {
+                write!(self.stdout, "{}", termion::style::Underline).unwrap();
+            }
+            if style.reverse {
+                write!(self.stdout, "{}", termion::style::Invert).unwrap();
+            }
+            write!(self.stdout, "{}{}", run, termion::style::Reset).unwrap();
+        }
+    }
+    fn set_cursor(&mut self, x: u16, y: u16) {
+        write!(self.stdout, "{}", termion::cursor::Goto(x + 1, y + 1)).unwrap();
+    }
+    fn set_mouse(&mut self, enabled: bool) {
+        // The modes termion's MouseTerminal turns on.
+        let mode = if enabled { 'h' } else { 'l' };
+        for number in [1000, 1002, 1015, 1006] {
+            write!(self.stdout, "\x1b[?{}{}", number, mode).unwrap();
+        }
+    }
+    fn flush(&mut self) {
+        self.stdout.flush().unwrap();
+    }
+    fn next_event(&mut self, timeout: Option<Duration>) -> Option<EditorEvent> {
+        match timeout {
+            None => self.events.recv().ok(),
+            Some(timeout) => match self.events.recv_timeout(timeout) {
+                Ok(event) => Some(event),
+                Err(RecvTimeoutError::Timeout) => Some(EditorEvent::Timeout),
+                Err(RecvTimeoutError::Disconnected) => None,
+            },
+        }
+    }
+    fn waker(&self) -> Option<Sender<EditorEvent>> {
+        self.waker.lock().unwrap().clone()
+    }
+}
+
+/// An in-memory screen fed with scripted events, to drive the editor in tests.
+pub struct VirtualTerminal {
+    pub size: (u16, u16),
+    pub cells: Vec<Vec<Cell>>,
+    pub cursor: (u16, u16),
+    pub events: VecDeque<EditorEvent>,
+    /// Mouse events are reported.
+    pub mouse: bool,
+}
+
+impl VirtualTerminal {
+    pub fn new(width: u16, height: u16) -> Self {
+        VirtualTerminal {
+            size: (width, height),
+            cells: vec![vec![Cell::default(); width as usize]; height as usize],
+            cursor: (0, 0),
+            events: VecDeque::new(),
+            mouse: true,
+        }
+    }
+    /// Queue key presses. `\x1b` is Escape, `\r` or `\n` is Enter, `\x7f` is Backspace
+    /// and `\x1e` is `Ctrl-^`.
+    pub fn feed(&mut self, keys: &str) {
+        for c in keys.chars() {
+            let key = match c {
+                '\x1b' => Key::Esc,
+                '\r' | '\n' => Key::Char('\n'),
+                '\t' => Key::Char('\t'),
+                '\x7f' => Key::Backspace,
+                // As termion reads them: Ctrl-\ to Ctrl-_ come as Ctrl-4 to Ctrl-7.
+                '\x1c'..='\x1f' => Key::Ctrl((c as u8 - 0x1c + b'4') as char),
+                c if (c as u32) < 0x20 => Key::Ctrl((c as u8 + b'a' - 1) as char),
+                c => Key::Char(c),
+            };
+            self.events.push_back(EditorEvent::Input(Event::Key(key)));
+        }
+    }
+    pub fn push_event(&mut self, event: EditorEvent) {
+        self.events.push_back(event);
+    }
+    /// The characters shown on a row, without trailing blanks.
+    pub fn row_text(&self, y: u16) -> String {
+        let text: String = self.cells[y as usize].iter().map(|cell| cell.ch).collect();
+        text.trim_end().to_string()
+    }
+    /// Change the size and queue the resize event a real terminal would send.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.size = (width, height);
+        self.cells = vec![vec![Cell::default(); width as usize]; height as usize];
+        self.events.push_back(EditorEvent::Resize(width, height));
+    }
+}
+
+impl Terminal for VirtualTerminal {
+    fn size(&self) -> (u16, u16) {
+        self.size
+    }
+    fn color_support(&self) -> ColorSupport {
+        ColorSupport::TrueColor
+    }
+    fn clear(&mut self) {
+        for row in self.cells.iter_mut() {
+            for cell in row.iter_mut() {
+                *cell = Cell::default();
+            }
+        }
+    }
+    fn clear_row(&mut self, y: u16) {
+        if let Some(row) = self.cells.get_mut(y as usize) {
+            for cell in row.iter_mut() {
+                *cell = Cell::default();
+            }
+        }
+    }
+    fn scroll(&mut self, rows: Range<u16>, distance: i32) {
+        let width = self.size.0 as usize;
+        scroll_rows(
+            &mut self.cells,
+            rows.start as usize..rows.end as usize,
+            distance,
+            &vec![Cell::default(); width],
+        );
+    }
+    fn write_cells(&mut self, x: u16, y: u16, cells: &[Cell]) {
+        if let Some(row) = self.cells.get_mut(y as usize) {
+            for (i, cell) in cells.iter().enumerate() {
+                if let Some(target) = row.get_mut(x as usize + i) {
+                    *target = cell.clone();
+                }
+            }
+        }
+    }
+    fn set_cursor(&mut self, x: u16, y: u16) {
+        self.cursor = (x, y);
+    }
+    fn set_mouse(&mut self, enabled: bool) {
+        self.mouse = enabled;
+    }
+    fn flush(&mut self) {}
+    /// Queued events come without delay. Once they run out, a wait with a timeout
+    /// times out, and a wait without one ends the input.
+    fn next_event(&mut self, timeout: Option<Duration>) -> Option<EditorEvent> {
+        match self.events.pop_front() {
+            Some(event) => Some(event),
+            None => timeout.map(|_| EditorEvent::Timeout),
+        }
+    }
+    /// Scripted events are all there is, so messages of language servers are only
+    /// picked up while waiting for a response or when redrawing.
+    fn waker(&self) -> Option<Sender<EditorEvent>> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtual_terminal() {
+        let mut terminal = VirtualTerminal::new(5, 3);
+        terminal.write_cells(3, 1, &cells("abc", &Style::default()));
+        assert_eq!(terminal.row_text(1), "   ab");
+        terminal.write_cells(0, 2, &cells("z", &Style::default()));
+        terminal.scroll(0..2, -1);
+        assert_eq!(terminal.row_text(0), "");
+        assert_eq!(terminal.row_text(1), "");
+        assert_eq!(terminal.row_text(2), "z");
+        terminal.scroll(0..3, 2);
+        assert_eq!(terminal.row_text(0), "z");
+        assert_eq!(terminal.row_text(2), "");
+        terminal.feed("i\x1b\r");
+        assert_eq!(terminal.events.len(), 3);
+    }
+
+    #[test]
+    fn test_color_escape() {
+        assert_eq!(
+            color_escape(ColorSupport::Ansi16, Color(0, 200, 0), true),
+            "\x1b[42m"
+        );
+        assert_eq!(
+            color_escape(ColorSupport::Ansi256, Color(255, 0, 0), false),
+            "\x1b[38;5;196m"
+        );
+    }
+}
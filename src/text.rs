@@ -1,50 +1,204 @@
-use std::cmp;
-use std::io::{BufRead, Error, Write};
 pub type TextState = Vec<Vec<char>>;
 
-pub trait UpdateScreen<W>
-where
-    W: Write,
-{
-    fn rewrite_entire_screen(&self, stdout: W, row_offset: usize) -> ();
-    fn rewrite_single_line(&self, stdout: W, line_to_rewrite: usize, row_offset: usize) -> ();
+/// The text from `start` up to `end`, both given as line and column. A range ending
+/// at column 0 of a line includes the line break before it.
+pub fn copy_range(text: &TextState, start: (usize, usize), end: (usize, usize)) -> TextState {
+    if start.0 == end.0 {
+        return vec![text[start.0][start.1..end.1].to_vec()];
+    }
+    let mut copied = vec![text[start.0][start.1..].to_vec()];
+    copied.extend(text[start.0 + 1..end.0].iter().cloned());
+    copied.push(text[end.0][..end.1].to_vec());
+    copied
+}
+
+/// Remove the text from `start` up to `end`, joining what is left of their lines.
+/// Returns the removed text.
+pub fn delete_range(text: &mut TextState, start: (usize, usize), end: (usize, usize)) -> TextState {
+    let removed = copy_range(text, start, end);
+    let mut joined = text[start.0][..start.1].to_vec();
+    joined.extend_from_slice(&text[end.0][end.1..]);
+    text.splice(start.0..=end.0, std::iter::once(joined));
+    removed
 }
 
-impl<W> UpdateScreen<W> for TextState
-where
-    W: Write,
-{
-    fn rewrite_entire_screen(&self, mut stdout: W, row_offset: usize) {
-        write!(stdout, "{}", termion::clear::All).unwrap();
-        for (i, column) in self[row_offset
-            ..cmp::min(
-                termion::terminal_size().unwrap().1 as usize + row_offset - 1,
-                self.len(),
-            )]
-            .iter()
-            .enumerate()
-        {
-            write!(
-                stdout,
-                "{}{}",
-                termion::cursor::Goto(0, i as u16 + 1),
-                column.iter().collect::<String>()
-            )
-            .unwrap();
+/// Put `inserted` at `position`, its first line joining the text before the position
+/// and its last line the text after it. Returns the position just after it.
+pub fn insert_text(
+    text: &mut TextState,
+    position: (usize, usize),
+    inserted: &TextState,
+) -> (usize, usize) {
+    let (line, column) = position;
+    let tail = text[line].split_off(column);
+    text[line].extend_from_slice(&inserted[0]);
+    text.splice(line + 1..line + 1, inserted[1..].iter().cloned());
+    let last = line + inserted.len() - 1;
+    let end = (last, text[last].len());
+    text[last].extend(tail);
+    end
+}
+
+/// Join `count` lines from `line` on, as `J` does when `spaces` is set: the indent of the
+/// lines joined is removed and a space put in between, but not after white space, to an
+/// empty line or before `)`. Without `spaces` the lines are joined as they are, as `gJ`
+/// does. Returns the column where the last line was joined.
+pub fn join_lines(text: &mut TextState, line: usize, count: usize, spaces: bool) -> usize {
+    let mut column = text[line].len();
+    for _ in 1..count {
+        if line + 1 >= text.len() {
+            break;
+        }
+        let next = text.remove(line + 1);
+        let mut next = &next[..];
+        let joined = &mut text[line];
+        column = joined.len();
+        if spaces {
+            next = &next[next.iter().take_while(|c| c.is_whitespace()).count()..];
+            let after_blank = joined.last().is_none_or(|c| c.is_whitespace());
+            if !after_blank && next.first().is_some_and(|&c| c != ')') {
+                joined.push(' ');
+            }
         }
-        write!(stdout, "{}", termion::cursor::Goto(1, 1)).unwrap();
-        stdout.flush().unwrap();
-    }
-    fn rewrite_single_line(&self, mut stdout: W, line_to_rewrite: usize, row_offset: usize) {
-        let column = self.iter().collect::<Vec<&Vec<char>>>()[line_to_rewrite + row_offset];
-        write!(
-            stdout,
-            "{}{}",
-            termion::cursor::Goto(0, line_to_rewrite as u16 + 1),
-            termion::clear::CurrentLine
-        )
-        .unwrap();
-        write!(stdout, "{}", column.iter().collect::<String>()).unwrap();
-        return;
+        joined.extend_from_slice(next);
+    }
+    column
+}
+
+/// Add `delta` to the number under or after `column` in `line`, as `Ctrl-a` and `Ctrl-x`
+/// do. Numbers starting with `0x` are hexadecimal and those with `0b` binary, which keep
+/// their width and the case of their letters. Decimal numbers may have a `-` sign, but
+/// not after a letter, a digit or `_`. Returns the column of the last character of the
+/// new number, or `None` when there is no number.
+pub fn add_to_number(line: &mut Vec<char>, column: usize, delta: i64) -> Option<usize> {
+    let mut i = 0;
+    while i < line.len() {
+        if !line[i].is_ascii_digit() {
+            i += 1;
+            continue;
+        }
+        let prefixed = |c: char, radix: u32| {
+            line[i] == '0'
+                && line.get(i + 1).map(|c| c.to_ascii_lowercase()) == Some(c)
+                && line.get(i + 2).is_some_and(|c| c.is_digit(radix))
+        };
+        let radix = if prefixed('x', 16) {
+            16
+        } else if prefixed('b', 2) {
+            2
+        } else {
+            10
+        };
+        let digits = if radix == 10 { i } else { i + 2 };
+        let mut end = digits;
+        while end < line.len() && line[end].is_digit(radix) {
+            end += 1;
+        }
+        if end <= column {
+            i = end;
+            continue;
+        }
+        let number: String = line[digits..end].iter().collect();
+        let width = end - digits;
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        let negative =
+            radix == 10 && i > 0 && line[i - 1] == '-' && !(i > 1 && is_word(line[i - 2]));
+        let (start, new) = if radix == 10 {
+            let value: i64 = number.parse().ok()?;
+            let value = if negative { -value } else { value };
+            let new = value.saturating_add(delta);
+            let digits = new.unsigned_abs().to_string();
+            // Leading zeros are kept.
+            let width = if number.starts_with('0') { width } else { 0 };
+            let sign = if new < 0 { "-" } else { "" };
+            let start = if negative { i - 1 } else { i };
+            (start, format!("{}{:0>2$}", sign, digits, width))
+        } else {
+            let value = u64::from_str_radix(&number, radix).ok()?;
+            let new = value.wrapping_add(delta as u64);
+            let upper = number.chars().rev().find(|c| c.is_alphabetic());
+            let new = match radix {
+                2 => format!("{:01$b}", new, width),
+                _ if upper.is_some_and(|c| c.is_uppercase()) => format!("{:01$X}", new, width),
+                _ => format!("{:01$x}", new, width),
+            };
+            (digits, new)
+        };
+        let new: Vec<char> = new.chars().collect();
+        let last = start + new.len() - 1;
+        line.splice(start..end, new);
+        return Some(last);
+    }
+    None
+}
+
+/// `c` in upper case if it is in lower case and the other way round, as `~` changes it.
+pub fn switch_case(c: char) -> char {
+    let switched: Vec<char> = if c.is_lowercase() {
+        c.to_uppercase().collect()
+    } else {
+        c.to_lowercase().collect()
+    };
+    match switched[..] {
+        [switched] => switched,
+        _ => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(lines: &[&str]) -> TextState {
+        lines.iter().map(|line| line.chars().collect()).collect()
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut lines = text(&["abc", "def", "ghi"]);
+        assert_eq!(copy_range(&lines, (0, 1), (0, 2)), text(&["b"]));
+        assert_eq!(copy_range(&lines, (0, 3), (1, 0)), text(&["", ""]));
+        let removed = delete_range(&mut lines, (0, 2), (2, 1));
+        assert_eq!(removed, text(&["c", "def", "g"]));
+        assert_eq!(lines, text(&["abhi"]));
+        assert_eq!(insert_text(&mut lines, (0, 2), &removed), (2, 1));
+        assert_eq!(lines, text(&["abc", "def", "ghi"]));
+    }
+
+    #[test]
+    fn test_join_lines() {
+        let mut lines = text(&["fn f(", "    a,", ")", "", "  b ", "c"]);
+        assert_eq!(join_lines(&mut lines, 0, 3, true), 8);
+        assert_eq!(lines[0].iter().collect::<String>(), "fn f( a,)");
+        assert_eq!(join_lines(&mut lines, 0, 2, true), 9);
+        assert_eq!(join_lines(&mut lines, 1, 3, true), 4);
+        assert_eq!(lines, text(&["fn f( a,)", "  b c"]));
+        let mut lines = text(&["a", "  b", "c"]);
+        assert_eq!(join_lines(&mut lines, 0, 5, false), 4);
+        assert_eq!(lines, text(&["a  bc"]));
+    }
+
+    #[test]
+    fn test_add_to_number() {
+        let add = |line: &str, column: usize, delta: i64| {
+            let mut line: Vec<char> = line.chars().collect();
+            let end = add_to_number(&mut line, column, delta);
+            (line.iter().collect::<String>(), end)
+        };
+        assert_eq!(add("x = 9;", 0, 1), ("x = 10;".to_string(), Some(5)));
+        assert_eq!(add("x = 1;", 5, 1), ("x = 1;".to_string(), None));
+        assert_eq!(add("-1", 1, 3), ("2".to_string(), Some(0)));
+        assert_eq!(add("a 0", 0, -2), ("a -2".to_string(), Some(3)));
+        assert_eq!(add("item-7", 0, 1), ("item-8".to_string(), Some(5)));
+        assert_eq!(add("007 8", 0, 1), ("008 8".to_string(), Some(2)));
+        assert_eq!(add("10 20", 2, 5), ("10 25".to_string(), Some(4)));
+        assert_eq!(add("0x0fF", 1, 1), ("0x100".to_string(), Some(4)));
+        assert_eq!(add("0x00fa", 0, 1), ("0x00fb".to_string(), Some(5)));
+        assert_eq!(
+            add("0XA", 2, -11),
+            ("0XFFFFFFFFFFFFFFFF".to_string(), Some(17))
+        );
+        assert_eq!(add("0b0111", 0, 1), ("0b1000".to_string(), Some(5)));
+        assert_eq!(add("0b2", 0, 1), ("1b2".to_string(), Some(0)));
     }
 }
@@ -0,0 +1,627 @@
+use crate::motion::*;
+use crate::text::*;
+use std::cmp;
+
+/// A text object, selected after `i` or `a` by an operator or in Visual mode.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TextObject {
+    /// `w`, or `W` for WORDs.
+    Word(bool),
+    /// `s`
+    Sentence,
+    /// `p`
+    Paragraph,
+    /// The brackets of `(`, `)` and `b`, `[` and `]`, `{`, `}` and `B`, or `<` and `>`.
+    Block(char, char),
+    /// `"`, `'` or `` ` ``: a quoted string in the line.
+    Quote(char),
+    /// `t`: an XML or HTML element.
+    Tag,
+}
+
+/// The text from `start` up to `end`, not included, or the lines from that of `start` to
+/// that of `end` when `linewise`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Selection {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+    pub linewise: bool,
+}
+
+impl Selection {
+    fn chars(start: (usize, usize), end: (usize, usize)) -> Selection {
+        Selection {
+            start,
+            end,
+            linewise: false,
+        }
+    }
+
+    fn lines(first: usize, last: usize) -> Selection {
+        Selection {
+            start: (first, 0),
+            end: (last, 0),
+            linewise: true,
+        }
+    }
+
+    /// Whether the selection takes more than the characters from `start` to `end`,
+    /// both included.
+    fn extends(&self, (start, end): ((usize, usize), (usize, usize))) -> bool {
+        let last = if self.linewise {
+            (self.end.0, usize::MAX)
+        } else {
+            self.end
+        };
+        let first = if self.linewise {
+            (self.start.0, 0)
+        } else {
+            self.start
+        };
+        first <= start && end < last && (first < start || (end.0, end.1 + 1) < last)
+    }
+}
+
+impl TextObject {
+    /// The text object of the key after `i` or `a`.
+    pub fn from_key(key: char) -> Option<TextObject> {
+        match key {
+            'w' => Some(TextObject::Word(false)),
+            'W' => Some(TextObject::Word(true)),
+            's' => Some(TextObject::Sentence),
+            'p' => Some(TextObject::Paragraph),
+            '(' | ')' | 'b' => Some(TextObject::Block('(', ')')),
+            '[' | ']' => Some(TextObject::Block('[', ']')),
+            '{' | '}' | 'B' => Some(TextObject::Block('{', '}')),
+            '<' | '>' => Some(TextObject::Block('<', '>')),
+            '"' | '\'' | '`' => Some(TextObject::Quote(key)),
+            't' => Some(TextObject::Tag),
+            _ => None,
+        }
+    }
+
+    /// Select `count` objects for `range`, the first and the last character of the
+    /// selection of Visual mode or the cursor twice. A selection of more than one
+    /// character grows: words, sentences and paragraphs are added after it, and blocks
+    /// and tags around it. `around` (`a`) takes the white space after the object, or
+    /// before it when there is none, or the brackets, quotes or tags of the object
+    /// rather than (`i`) only what is inside them.
+    pub fn select(
+        self,
+        context: &Context,
+        range: ((usize, usize), (usize, usize)),
+        count: usize,
+        around: bool,
+    ) -> Option<Selection> {
+        let text = context.text;
+        let (start, end) = range;
+        // Words, sentences and paragraphs after a selection start after its end.
+        let from = if start == end {
+            start
+        } else if self == TextObject::Paragraph {
+            (cmp::min(end.0 + 1, text.len() - 1), 0)
+        } else {
+            (
+                end.0,
+                cmp::min(end.1 + 1, text[end.0].len().saturating_sub(1)),
+            )
+        };
+        let selection = match self {
+            TextObject::Word(big) => word(context, from, count, big, around),
+            TextObject::Sentence => sentence(text, from, count, around),
+            TextObject::Paragraph => paragraph(text, from.0, count, around),
+            TextObject::Block(open, close) => {
+                let mut level = count;
+                loop {
+                    let selection = block(text, start, open, close, level, around)?;
+                    if start == end || selection.extends(range) {
+                        return Some(selection);
+                    }
+                    level += 1;
+                }
+            }
+            TextObject::Quote(quote) => return quoted(text, start, quote, count, around),
+            TextObject::Tag => {
+                let elements = elements(text);
+                let mut selections = elements
+                    .iter()
+                    .filter(|element| element.open.0 <= start && end < element.close.1)
+                    .map(|element| {
+                        if around {
+                            Selection::chars(element.open.0, element.close.1)
+                        } else {
+                            Selection::chars(element.open.1, element.close.0)
+                        }
+                    })
+                    .filter(|selection| start == end || selection.extends(range));
+                return selections.nth(count - 1);
+            }
+        }?;
+        Some(if start == end {
+            selection
+        } else if selection.linewise {
+            Selection::lines(start.0, selection.end.0)
+        } else {
+            Selection::chars(start, selection.end)
+        })
+    }
+}
+
+/// Whether `c` is a blank, and what kind of word it belongs to otherwise: 1 for
+/// punctuation and 2 for keyword characters. WORDs are made of anything but blanks.
+fn class(context: &Context, c: char, big: bool) -> u8 {
+    if c == ' ' || c == '\t' {
+        0
+    } else if big || !context.keyword.contains(c) {
+        1
+    } else {
+        2
+    }
+}
+
+/// `iw` and `aw`. Blanks between words count as words for `iw`. Within the line.
+fn word(
+    context: &Context,
+    (number, column): (usize, usize),
+    count: usize,
+    big: bool,
+    around: bool,
+) -> Option<Selection> {
+    let line = &context.text[number];
+    if line.is_empty() {
+        return None;
+    }
+    let class = |column: usize| class(context, line[column], big);
+    // The end of the run of characters of one class at `column`.
+    let run_end = |mut column: usize| {
+        let start = class(column);
+        while column < line.len() && class(column) == start {
+            column += 1;
+        }
+        column
+    };
+    let mut start = column;
+    while start > 0 && class(start - 1) == class(column) {
+        start -= 1;
+    }
+    let mut end = column;
+    if !around {
+        for _ in 0..count {
+            if end == line.len() {
+                break;
+            }
+            end = run_end(end);
+        }
+        return Some(Selection::chars((number, start), (number, end)));
+    }
+    let on_blank = class(column) == 0;
+    let mut trailing = false;
+    for _ in 0..count {
+        if end < line.len() && (on_blank || class(end) == 0) {
+            end = run_end(end);
+        }
+        if end < line.len() {
+            end = run_end(end);
+        }
+        if !on_blank && end < line.len() && class(end) == 0 {
+            end = run_end(end);
+            trailing = true;
+        }
+    }
+    // Without white space after the words that before them is taken, but not the indent.
+    if !on_blank && !trailing && start > 0 && class(start - 1) == 0 {
+        let mut blank = start;
+        while blank > 0 && class(blank - 1) == 0 {
+            blank -= 1;
+        }
+        if blank > 0 {
+            start = blank;
+        }
+    }
+    Some(Selection::chars((number, start), (number, end)))
+}
+
+/// `is` and `as`, the latter with the white space up to the next sentence.
+fn sentence(
+    text: &TextState,
+    from: (usize, usize),
+    count: usize,
+    around: bool,
+) -> Option<Selection> {
+    let starts = sentence_starts(text);
+    let index = starts.iter().rposition(|&start| start <= from)?;
+    let start = starts[index];
+    let last = text.len() - 1;
+    let mut end = starts
+        .get(index + count)
+        .copied()
+        .unwrap_or((last, text[last].len()));
+    if !around {
+        while end > start {
+            if end.1 == 0 {
+                end = (end.0 - 1, text[end.0 - 1].len());
+            } else if text[end.0][end.1 - 1].is_whitespace() {
+                end.1 -= 1;
+            } else {
+                break;
+            }
+        }
+    }
+    Some(Selection::chars(start, end))
+}
+
+/// `ip` and `ap`: lines with text, or blank lines between them, which `ip` counts as
+/// paragraphs of their own and `ap` takes after the lines with text.
+fn paragraph(text: &TextState, line: usize, count: usize, around: bool) -> Option<Selection> {
+    let blank = |line: usize| text[line].iter().all(|c| c.is_whitespace());
+    // The last line of the run of lines like `line`.
+    let run_end = |mut line: usize| {
+        let start = blank(line);
+        while line + 1 < text.len() && blank(line + 1) == start {
+            line += 1;
+        }
+        line
+    };
+    let mut first = line;
+    while first > 0 && blank(first - 1) == blank(line) {
+        first -= 1;
+    }
+    let mut last = line;
+    let mut trailing = false;
+    for number in 0..count {
+        let next = if number == 0 { last } else { last + 1 };
+        if next >= text.len() {
+            return None;
+        }
+        last = run_end(next);
+        if around && last + 1 < text.len() {
+            last = run_end(last + 1);
+            trailing = blank(last);
+        }
+    }
+    // Without blank lines after the paragraph those before it are taken.
+    if around && !trailing && !blank(line) {
+        while first > 0 && blank(first - 1) {
+            first -= 1;
+        }
+    }
+    Some(Selection::lines(first, last))
+}
+
+/// The brackets `level` blocks out from `from`, a bracket at `from` being of the first.
+fn brackets(
+    text: &TextState,
+    from: (usize, usize),
+    open: char,
+    close: char,
+    level: usize,
+) -> Option<((usize, usize), (usize, usize))> {
+    let unmatched_open = |from: (usize, usize)| {
+        let mut depth = 0;
+        walk(text, from, false).find(|&(line, column)| {
+            let c = text[line][column];
+            if c == close {
+                depth += 1;
+            } else if c == open {
+                if depth == 0 {
+                    return true;
+                }
+                depth -= 1;
+            }
+            false
+        })
+    };
+    let mut start = if text[from.0].get(from.1) == Some(&open) {
+        from
+    } else {
+        unmatched_open(from)?
+    };
+    for _ in 1..level {
+        start = unmatched_open(start)?;
+    }
+    let mut depth = 0;
+    let end = walk(text, start, true).find(|&(line, column)| {
+        let c = text[line][column];
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            if depth == 0 {
+                return true;
+            }
+            depth -= 1;
+        }
+        false
+    })?;
+    Some((start, end))
+}
+
+/// `i(`, `a(` and the other blocks. When the open bracket ends its line and the close
+/// one starts its own, the lines in between are what is inside.
+fn block(
+    text: &TextState,
+    from: (usize, usize),
+    open: char,
+    close: char,
+    level: usize,
+    around: bool,
+) -> Option<Selection> {
+    let (start, end) = brackets(text, from, open, close, level)?;
+    if around {
+        return Some(Selection::chars(start, (end.0, end.1 + 1)));
+    }
+    let open_ends_line = start.1 + 1 == text[start.0].len();
+    let close_starts_line = text[end.0][..end.1].iter().all(|c| c.is_whitespace());
+    if open_ends_line && close_starts_line && end.0 > start.0 + 1 {
+        return Some(Selection::lines(start.0 + 1, end.0 - 1));
+    }
+    let inner_start = if open_ends_line && end.0 > start.0 {
+        (start.0 + 1, 0)
+    } else {
+        (start.0, start.1 + 1)
+    };
+    Some(Selection::chars(cmp::min(inner_start, end), end))
+}
+
+/// `i"`, `a"` and the other quotes, in the line. Quotes pair up from the start of the
+/// line, and those after a backslash are not counted. The string after the cursor is
+/// taken when it is not in one. A count of 2 takes the quotes with `i`.
+fn quoted(
+    text: &TextState,
+    (number, column): (usize, usize),
+    quote: char,
+    count: usize,
+    around: bool,
+) -> Option<Selection> {
+    let line = &text[number];
+    let quotes: Vec<usize> = (0..line.len())
+        .filter(|&i| line[i] == quote && (i == 0 || line[i - 1] != '\\'))
+        .collect();
+    let (open, close) = quotes
+        .chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|&(_, close)| column <= close)?;
+    if !around {
+        return Some(if count > 1 {
+            Selection::chars((number, open), (number, close + 1))
+        } else {
+            Selection::chars((number, open + 1), (number, close))
+        });
+    }
+    let mut start = open;
+    let mut end = close + 1;
+    while end < line.len() && (line[end] == ' ' || line[end] == '\t') {
+        end += 1;
+    }
+    if end == close + 1 {
+        while start > 0 && (line[start - 1] == ' ' || line[start - 1] == '\t') {
+            start -= 1;
+        }
+    }
+    Some(Selection::chars((number, start), (number, end)))
+}
+
+/// Where a tag begins and where it ends, after its `>`.
+type Tag = ((usize, usize), (usize, usize));
+
+/// An element of XML or HTML: its start tag and its end tag.
+struct Element {
+    open: Tag,
+    close: Tag,
+}
+
+/// The elements of the text, innermost first. Tags without an end tag, as `<br>`, and
+/// ones closing themselves, as `<br/>`, are left out.
+fn elements(text: &TextState) -> Vec<Element> {
+    let chars: Vec<((usize, usize), char)> = text
+        .iter()
+        .enumerate()
+        .flat_map(|(number, line)| {
+            let line_break = std::iter::once(((number, line.len()), '\n'));
+            line.iter()
+                .enumerate()
+                .map(move |(column, &c)| ((number, column), c))
+                .chain(line_break)
+        })
+        .collect();
+    let mut open: Vec<(String, Tag)> = Vec::new();
+    let mut elements = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i].1 != '<' {
+            i += 1;
+            continue;
+        }
+        let end = match (i + 1..chars.len()).find(|&j| chars[j].1 == '>') {
+            Some(end) => end,
+            None => break,
+        };
+        let inside: String = chars[i + 1..end].iter().map(|&(_, c)| c).collect();
+        let start = chars[i].0;
+        let after = (chars[end].0 .0, chars[end].0 .1 + 1);
+        i = end + 1;
+        if inside.ends_with('/') || inside.starts_with('!') || inside.starts_with('?') {
+            continue;
+        }
+        let (closing, name) = match inside.strip_prefix('/') {
+            Some(name) => (true, name),
+            None => (false, inside.as_str()),
+        };
+        let name = name.split_whitespace().next().unwrap_or("").to_string();
+        if name.is_empty() {
+            continue;
+        }
+        if !closing {
+            open.push((name, (start, after)));
+        } else if let Some(index) = open.iter().rposition(|(open, _)| *open == name) {
+            let (_, tag) = open.remove(index);
+            open.truncate(index);
+            elements.push(Element {
+                open: tag,
+                close: (start, after),
+            });
+        }
+    }
+    elements.sort_by_key(|element| (cmp::Reverse(element.open.0), element.close.1));
+    elements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(lines: &[&str]) -> TextState {
+        lines.iter().map(|line| line.chars().collect()).collect()
+    }
+
+    /// The text `object` selects from `from`, with the line breaks in it.
+    fn selected(
+        lines: &[&str],
+        object: TextObject,
+        range: ((usize, usize), (usize, usize)),
+        count: usize,
+        around: bool,
+    ) -> Option<String> {
+        let text = text(lines);
+        let context = Context {
+            text: &text,
+            keyword: Keyword::default(),
+            highlighter: None,
+        };
+        let selection = object.select(&context, range, count, around)?;
+        let taken = if selection.linewise {
+            text[selection.start.0..=selection.end.0].to_vec()
+        } else {
+            copy_range(&text, selection.start, selection.end)
+        };
+        let lines: Vec<String> = taken.iter().map(|line| line.iter().collect()).collect();
+        Some(lines.join("\n"))
+    }
+
+    fn at(column: usize) -> ((usize, usize), (usize, usize)) {
+        ((0, column), (0, column))
+    }
+
+    #[test]
+    fn test_words() {
+        let line = ["  foo.bar baz  qux"];
+        let word = |column, count, around| {
+            selected(&line, TextObject::Word(false), at(column), count, around).unwrap()
+        };
+        assert_eq!(word(3, 1, false), "foo");
+        assert_eq!(word(3, 3, false), "foo.bar");
+        assert_eq!(word(3, 1, true), "foo");
+        assert_eq!(word(7, 1, true), "bar ");
+        assert_eq!(word(9, 1, true), " baz");
+        assert_eq!(word(16, 1, true), "  qux");
+        assert_eq!(word(10, 2, true), "baz  qux");
+        assert_eq!(word(0, 1, false), "  ");
+        let big = selected(&line, TextObject::Word(true), at(3), 1, true);
+        assert_eq!(big.as_deref(), Some("foo.bar "));
+        // A selection grows by the words after it.
+        let grown = selected(&line, TextObject::Word(false), ((0, 2), (0, 4)), 2, false);
+        assert_eq!(grown.as_deref(), Some("foo.bar"));
+    }
+
+    #[test]
+    fn test_sentences_and_paragraphs() {
+        let lines = ["One. Two", "three.  Four.", "", "", "Five"];
+        let sentence =
+            |from, around| selected(&lines, TextObject::Sentence, (from, from), 1, around);
+        assert_eq!(sentence((0, 6), false).as_deref(), Some("Two\nthree."));
+        assert_eq!(sentence((0, 6), true).as_deref(), Some("Two\nthree.  "));
+        assert_eq!(sentence((0, 0), true).as_deref(), Some("One. "));
+        let paragraph = |line, count, around| {
+            selected(
+                &lines,
+                TextObject::Paragraph,
+                ((line, 0), (line, 0)),
+                count,
+                around,
+            )
+        };
+        assert_eq!(
+            paragraph(1, 1, false).as_deref(),
+            Some("One. Two\nthree.  Four.")
+        );
+        assert_eq!(
+            paragraph(0, 1, true).as_deref(),
+            Some("One. Two\nthree.  Four.\n\n")
+        );
+        assert_eq!(paragraph(2, 1, true).as_deref(), Some("\n\nFive"));
+        assert_eq!(
+            paragraph(0, 2, false).as_deref(),
+            Some("One. Two\nthree.  Four.\n\n")
+        );
+        assert_eq!(paragraph(4, 1, true).as_deref(), Some("\n\nFive"));
+        assert_eq!(paragraph(4, 2, false), None);
+    }
+
+    #[test]
+    fn test_blocks() {
+        let line = ["f(a, (b), [c])"];
+        let block = |column, count, around| {
+            selected(
+                &line,
+                TextObject::Block('(', ')'),
+                at(column),
+                count,
+                around,
+            )
+        };
+        assert_eq!(block(6, 1, false).as_deref(), Some("b"));
+        assert_eq!(block(6, 1, true).as_deref(), Some("(b)"));
+        assert_eq!(block(6, 2, false).as_deref(), Some("a, (b), [c]"));
+        assert_eq!(block(1, 1, false).as_deref(), Some("a, (b), [c]"));
+        assert_eq!(block(13, 1, true).as_deref(), Some("(a, (b), [c])"));
+        assert_eq!(block(0, 1, false), None);
+        // The selection of the inside of a block grows to the outer block.
+        let grown = selected(
+            &line,
+            TextObject::Block('(', ')'),
+            ((0, 5), (0, 7)),
+            1,
+            false,
+        );
+        assert_eq!(grown.as_deref(), Some("a, (b), [c]"));
+        let lines = ["fn f() {", "    a;", "    b;", "}"];
+        let inside = selected(
+            &lines,
+            TextObject::Block('{', '}'),
+            ((1, 4), (1, 4)),
+            1,
+            false,
+        );
+        assert_eq!(inside.as_deref(), Some("    a;\n    b;"));
+        let empty = selected(&["{}"], TextObject::Block('{', '}'), at(0), 1, false);
+        assert_eq!(empty.as_deref(), Some(""));
+    }
+
+    #[test]
+    fn test_quotes_and_tags() {
+        let line = [r#"say "a \"b\"" and "c"  x"#];
+        let quote = |column, count, around| {
+            selected(&line, TextObject::Quote('"'), at(column), count, around)
+        };
+        assert_eq!(quote(6, 1, false).as_deref(), Some(r#"a \"b\""#));
+        assert_eq!(quote(0, 1, true).as_deref(), Some(r#""a \"b\"" "#));
+        assert_eq!(quote(14, 1, false).as_deref(), Some("c"));
+        assert_eq!(quote(19, 1, true).as_deref(), Some(r#""c"  "#));
+        assert_eq!(quote(19, 2, false).as_deref(), Some(r#""c""#));
+        assert_eq!(quote(23, 1, false), None);
+        let lines = ["<div id=\"x\"><p>a<br>b</p>", "<img/></div>"];
+        let tag =
+            |from, count, around| selected(&lines, TextObject::Tag, (from, from), count, around);
+        assert_eq!(tag((0, 16), 1, false).as_deref(), Some("a<br>b"));
+        assert_eq!(tag((0, 16), 1, true).as_deref(), Some("<p>a<br>b</p>"));
+        assert_eq!(
+            tag((0, 16), 2, false).as_deref(),
+            Some("<p>a<br>b</p>\n<img/>")
+        );
+        assert_eq!(
+            tag((0, 2), 1, true).as_deref(),
+            Some(lines.join("\n").as_str())
+        );
+        let grown = selected(&lines, TextObject::Tag, ((0, 15), (0, 20)), 1, false);
+        assert_eq!(grown.as_deref(), Some("<p>a<br>b</p>\n<img/>"));
+    }
+}
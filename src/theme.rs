@@ -0,0 +1,299 @@
+use crate::options::config_directories;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Built-in colorschemes, written in the same format as theme files.
+const BUILTIN_THEMES: &[(&str, &str)] = &[
+    ("default", include_str!("themes/default.theme")),
+    ("light", include_str!("themes/light.theme")),
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Color(pub u8, pub u8, pub u8);
+
+impl Color {
+    /// Parse `#rrggbb`.
+    pub fn parse(source: &str) -> Option<Color> {
+        let hex = source.strip_prefix('#')?;
+        if hex.len() != 6 {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        Some(Color((value >> 16) as u8, (value >> 8) as u8, value as u8))
+    }
+    /// Nearest entry of the xterm 256-color palette, from the color cube or the gray ramp.
+    pub fn to_ansi256(self) -> u8 {
+        let Color(r, g, b) = self;
+        let level = |c: u8| -> u8 {
+            if c < 48 {
+                0
+            } else if c < 115 {
+                1
+            } else {
+                (c - 35) / 40
+            }
+        };
+        let (lr, lg, lb) = (level(r), level(g), level(b));
+        let cube_value = |l: u8| if l == 0 { 0 } else { 55 + 40 * l as i32 };
+        let cube = 16 + 36 * lr + 6 * lg + lb;
+        let cube_distance = distance(self, (cube_value(lr), cube_value(lg), cube_value(lb)));
+        let average = (r as i32 + g as i32 + b as i32) / 3;
+        let gray_level = if average > 238 {
+            23
+        } else {
+            (std::cmp::max(average - 3, 0) / 10) as u8
+        };
+        let gray_value = 8 + 10 * gray_level as i32;
+        let gray_distance = distance(self, (gray_value, gray_value, gray_value));
+        if gray_distance < cube_distance {
+            232 + gray_level
+        } else {
+            cube
+        }
+    }
+    /// Nearest of the 16 basic ANSI colors, where 8 and above are the bright variants.
+    pub fn to_ansi16(self) -> u8 {
+        const PALETTE: [(i32, i32, i32); 16] = [
+            (0, 0, 0),
+            (205, 0, 0),
+            (0, 205, 0),
+            (205, 205, 0),
+            (0, 0, 238),
+            (205, 0, 205),
+            (0, 205, 205),
+            (229, 229, 229),
+            (127, 127, 127),
+            (255, 0, 0),
+            (0, 255, 0),
+            (255, 255, 0),
+            (92, 92, 255),
+            (255, 0, 255),
+            (0, 255, 255),
+            (255, 255, 255),
+        ];
+        (0..16).min_by_key(|&i| distance(self, PALETTE[i])).unwrap() as u8
+    }
+}
+
+fn distance(color: Color, other: (i32, i32, i32)) -> i32 {
+    let Color(r, g, b) = color;
+    let (dr, dg, db) = (r as i32 - other.0, g as i32 - other.1, b as i32 - other.2);
+    dr * dr + dg * dg + db * db
+}
+
+/// How many colors the terminal can show.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColorSupport {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+}
+
+impl ColorSupport {
+    /// Guess from `$COLORTERM` and `$TERM`, as most terminals advertise truecolor there.
+    pub fn detect() -> Self {
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        let term = std::env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorSupport::TrueColor
+        } else if term.contains("256color") {
+            ColorSupport::Ansi256
+        } else {
+            ColorSupport::Ansi16
+        }
+    }
+}
+
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+}
+
+impl std::fmt::Display for Style {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let color = |c: Option<Color>| match c {
+            Some(Color(r, g, b)) => format!("#{:02x}{:02x}{:02x}", r, g, b),
+            None => "NONE".to_string(),
+        };
+        write!(f, "fg={} bg={}", color(self.fg), color(self.bg))?;
+        let attributes: Vec<&str> = [
+            (self.bold, "bold"),
+            (self.italic, "italic"),
+            (self.underline, "underline"),
+            (self.reverse, "reverse"),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| *name)
+        .collect();
+        if !attributes.is_empty() {
+            write!(f, " style={}", attributes.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// Styles of the highlight groups, e.g. `Comment`, `LineNr` or `StatusLineInsert`.
+///
+/// Theme files contain one group per line, in the same form as the arguments of
+/// `:highlight`:
+///
+/// ```text
+/// " comment
+/// Comment fg=#808080 style=italic
+/// StatusLineNormal fg=#000000 bg=#91acd1
+/// ```
+#[derive(Clone)]
+pub struct Theme {
+    pub name: String,
+    groups: HashMap<String, Style>,
+}
+
+impl Theme {
+    pub fn parse(name: &str, source: &str) -> Result<Theme, String> {
+        let mut theme = Theme {
+            name: name.to_string(),
+            groups: HashMap::new(),
+        };
+        for (i, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('"') || line.starts_with('#') {
+                continue;
+            }
+            theme
+                .highlight(line)
+                .map_err(|message| format!("{}:{}: {}", name, i + 1, message))?;
+        }
+        Ok(theme)
+    }
+
+    /// Load a built-in colorscheme, or `{name}.theme` from the `colors` directory
+    /// of the configuration.
+    pub fn load(name: &str) -> Result<Theme, String> {
+        match BUILTIN_THEMES.iter().find(|(n, _)| *n == name) {
+            Some((_, source)) => Theme::parse(name, source),
+            None => {
+                let source = theme_directories()
+                    .iter()
+                    .map(|directory| directory.join(format!("{}.theme", name)))
+                    .find_map(|path| std::fs::read_to_string(path).ok())
+                    .ok_or(format!("Cannot find color scheme '{}'", name))?;
+                Theme::parse(name, &source)
+            }
+        }
+    }
+
+    pub fn style(&self, group: &str) -> Style {
+        self.groups.get(group).cloned().unwrap_or_default()
+    }
+
+    /// Handle the arguments of `:highlight {group} [fg={color}] [bg={color}] [style={attributes}]`.
+    /// `guifg`, `guibg` and `gui` are accepted as in Vim, and `NONE` clears a color.
+    /// Returns the resulting style when only a group is given.
+    pub fn highlight(&mut self, arguments: &str) -> Result<Option<String>, String> {
+        let mut words = arguments.split_whitespace();
+        let group = words.next().ok_or("Group name required")?;
+        let mut style = self.style(group);
+        let mut changed = false;
+        for word in words {
+            let (key, value) = word
+                .split_once('=')
+                .ok_or(format!("Invalid argument: {}", word))?;
+            let color = || -> Result<Option<Color>, String> {
+                if value == "NONE" {
+                    Ok(None)
+                } else {
+                    Color::parse(value)
+                        .map(Some)
+                        .ok_or(format!("Invalid color: {}", value))
+                }
+            };
+            match key {
+                "fg" | "guifg" => style.fg = color()?,
+                "bg" | "guibg" => style.bg = color()?,
+                "style" | "gui" => {
+                    style.bold = false;
+                    style.italic = false;
+                    style.underline = false;
+                    style.reverse = false;
+                    for attribute in value.split(',') {
+                        match attribute {
+                            "bold" => style.bold = true,
+                            "italic" => style.italic = true,
+                            "underline" => style.underline = true,
+                            "reverse" => style.reverse = true,
+                            "NONE" => (),
+                            _ => return Err(format!("Invalid attribute: {}", attribute)),
+                        }
+                    }
+                }
+                _ => return Err(format!("Invalid argument: {}", word)),
+            }
+            changed = true;
+        }
+        if !changed {
+            return Ok(Some(format!("{} {}", group, style)));
+        }
+        self.groups.insert(group.to_string(), style);
+        Ok(None)
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::load("default").unwrap()
+    }
+}
+
+/// Directories searched for `{name}.theme` by `:colorscheme`.
+fn theme_directories() -> Vec<PathBuf> {
+    config_directories()
+        .into_iter()
+        .map(|directory| directory.join("colors"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_highlight() {
+        let mut theme = Theme::parse(
+            "test",
+            "\" comment\nComment fg=#808080 style=italic\nSearch bg=#ffff00",
+        )
+        .unwrap();
+        assert_eq!(theme.style("Comment").fg, Some(Color(128, 128, 128)));
+        assert!(theme.style("Comment").italic);
+        assert_eq!(theme.style("Missing"), Style::default());
+        theme.highlight("Comment guifg=NONE gui=bold").unwrap();
+        assert_eq!(
+            theme.highlight("Comment").unwrap(),
+            Some("Comment fg=NONE bg=NONE style=bold".to_string())
+        );
+        assert!(theme.highlight("Comment fg=red").is_err());
+        assert!(Theme::parse("broken", "Comment fg").is_err());
+    }
+
+    #[test]
+    fn test_builtin_themes_parse() {
+        for (name, source) in BUILTIN_THEMES {
+            assert!(Theme::parse(name, source).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_color_fallback() {
+        assert_eq!(Color(0, 0, 0).to_ansi256(), 16);
+        assert_eq!(Color(255, 0, 0).to_ansi256(), 196);
+        assert_eq!(Color(128, 128, 128).to_ansi256(), 244);
+        assert_eq!(Color(250, 10, 10).to_ansi16(), 9);
+        assert_eq!(Color(10, 10, 10).to_ansi16(), 0);
+    }
+}
@@ -1,59 +1,98 @@
 use crate::text::*;
-use std::io::{BufRead, Error, Write};
+use std::io::Error;
+pub const USAGE: &str = "\
+Usage: rim [arguments] [file ...]
+       rim [arguments] -          read text from stdin
+       rim -d [arguments] file1 file2
+
+Arguments:
+   +                    Start at the last line
+   +{number}            Start at line {number}
+   +/{pattern}          Start at the first occurrence of {pattern}
+   +{command}           Run {command} after loading the files
+   -c {command}         Run {command} after loading the files
+   -d                   Highlight the differences between two files
+   -R                   Read-only mode
+   -u {rimrc}           Use {rimrc} instead of the usual startup file; NONE skips it
+   -h, --help           Show this help and exit
+   --version            Show the version and exit
+";
+
+/// What the command line asks for.
+#[derive(Default)]
 pub struct Config {
-    pub filepath: String,
+    /// Files to edit, in order. The first one is shown.
+    pub files: Vec<String>,
+    /// Ex commands to run after loading, from `+...` and `-c`.
+    pub commands: Vec<String>,
+    pub readonly: bool,
+    /// Startup file given with `-u`.
+    pub rimrc: Option<String>,
+    /// `-` was given: the text comes from stdin.
+    pub read_stdin: bool,
+    /// Text read from stdin, filled in before the editor starts.
+    pub stdin: Option<String>,
+    pub diff: bool,
+    /// Text to print instead of starting the editor, for `--help` and `--version`.
+    pub print: Option<String>,
 }
 
 impl Config {
-    pub fn new(mut args: std::env::Args) -> Result<Config, &'static str> {
+    /// Parse the arguments, the first of which is the program name.
+    pub fn new<I>(mut args: I) -> Result<Config, String>
+    where
+        I: Iterator<Item = String>,
+    {
         args.next();
-        let filepath = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a filename"),
-        };
-        Ok(Config { filepath })
+        let mut config = Config::default();
+        let mut only_files = false;
+        while let Some(arg) = args.next() {
+            if only_files {
+                config.files.push(arg);
+                continue;
+            }
+            match arg.as_str() {
+                "--" => only_files = true,
+                "-h" | "--help" => config.print = Some(USAGE.to_string()),
+                "--version" => config.print = Some(format!("rim {}\n", env!("CARGO_PKG_VERSION"))),
+                "-R" => config.readonly = true,
+                "-d" => config.diff = true,
+                "-" => config.read_stdin = true,
+                "-c" => config
+                    .commands
+                    .push(args.next().ok_or("Argument missing after: \"-c\"")?),
+                "-u" => config.rimrc = Some(args.next().ok_or("Argument missing after: \"-u\"")?),
+                "+" => config.commands.push("$".to_string()),
+                arg if arg.starts_with('+') => config.commands.push(arg[1..].to_string()),
+                arg if arg.starts_with('-') => {
+                    return Err(format!("Unknown option argument: \"{}\"", arg))
+                }
+                _ => config.files.push(arg),
+            }
+        }
+        if config.print.is_some() {
+            return Ok(config);
+        }
+        if config.read_stdin && !config.files.is_empty() {
+            return Err("Cannot edit files and stdin at the same time".to_string());
+        }
+        if config.diff && config.files.len() != 2 {
+            return Err("Diff mode needs two files".to_string());
+        }
+        Ok(config)
     }
 }
 
-pub fn print_status<W>(mut stdout: W, mode: &Mode, args: Vec<String>)
-where
-    W: Write,
-{
-    write!(
-        stdout,
-        "{}{}{}{}",
-        termion::cursor::Goto(0, termion::terminal_size().unwrap().1),
-        termion::clear::CurrentLine,
-        match mode {
-            Mode::Normal => termion::color::Bg(termion::color::Rgb(145, 172, 209)),
-            Mode::Insert => termion::color::Bg(termion::color::Rgb(192, 202, 142)),
-            Mode::Command(_) => termion::color::Bg(termion::color::Rgb(233, 144, 144)),
-        },
-        termion::color::Fg(termion::color::Black),
-    )
-    .unwrap();
-    write!(stdout, "{}", args.join(", ")).unwrap();
-    write!(
-        stdout,
-        "{}{}",
-        termion::color::Fg(termion::color::Reset),
-        termion::color::Bg(termion::color::Reset),
-    )
-    .unwrap();
-}
-
-pub struct IO<R, W>
-where
-    R: BufRead,
-    W: Write,
-{
-    pub stdin: R,
-    pub stdout: W,
-}
-
 pub enum Mode {
     Normal,
     Insert,
+    /// Characters from where Visual mode started to the cursor are selected.
+    Visual,
+    /// Whole lines from where Visual mode started to the cursor are selected.
+    VisualLine,
+    /// Typed characters take the place of those under the cursor, which are kept here to
+    /// be put back by Backspace, or `None` for those typed past the end of the line.
+    Replace(Vec<Option<char>>),
     Command(String),
 }
 
@@ -62,18 +101,51 @@ impl std::fmt::Display for Mode {
         match self {
             Mode::Normal => write!(f, "NORMAL"),
             Mode::Insert => write!(f, "INSERT"),
+            Mode::Visual => write!(f, "VISUAL"),
+            Mode::VisualLine => write!(f, "VISUAL LINE"),
+            Mode::Replace(_) => write!(f, "REPLACE"),
             Mode::Command(command) => write!(f, "COMMAND:{} ", command),
         }
     }
 }
 
-pub fn save_to_file(filepath: &String, contents: &TextState) -> std::result::Result<(), Error> {
+pub fn save_to_file(filepath: &str, contents: &TextState) -> std::result::Result<(), Error> {
     let contents = contents
         .iter()
         .map(|x| x.iter().collect::<String>())
         .collect::<Vec<String>>()
         .join("\n");
     std::fs::write(filepath, contents)?;
-    return Ok(());
+    Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Config, String> {
+        Config::new(
+            std::iter::once("rim")
+                .chain(args.iter().copied())
+                .map(String::from),
+        )
+    }
+
+    #[test]
+    fn test_config() {
+        let config = parse(&["-R", "+42", "a", "-c", "set nu", "+/fn main", "b"]).unwrap();
+        assert_eq!(config.files, ["a", "b"]);
+        assert_eq!(config.commands, ["42", "set nu", "/fn main"]);
+        assert!(config.readonly);
+        assert!(parse(&[]).unwrap().files.is_empty());
+        assert!(parse(&["-"]).unwrap().read_stdin);
+        assert_eq!(parse(&["--", "-R"]).unwrap().files, ["-R"]);
+        assert_eq!(parse(&["+"]).unwrap().commands, ["$"]);
+        assert!(parse(&["--version", "-x"]).is_err());
+        assert!(parse(&["-x", "--help"]).is_err());
+        assert!(parse(&["--help", "a"]).unwrap().print.is_some());
+        assert!(parse(&["-c"]).is_err());
+        assert!(parse(&["-d", "a"]).is_err());
+        assert!(parse(&["-", "a"]).is_err());
+    }
+}
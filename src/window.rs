@@ -0,0 +1,375 @@
+use crate::screen::*;
+use std::cmp;
+
+/// Rows a window needs at least: one for text and its status line.
+const MIN_HEIGHT: usize = 2;
+const MIN_WIDTH: usize = 1;
+
+/// A view onto a buffer. The cursor, the scroll position and the window options live
+/// in `screen`, whose size is that of the window including its status line.
+pub struct Window {
+    pub id: usize,
+    /// Number of the buffer shown.
+    pub buffer: usize,
+    pub screen: ScreenState,
+}
+
+/// A tab page: a set of windows and how they divide the terminal. The current tab page
+/// is kept by the editor itself.
+pub struct TabPage {
+    /// The window which has the cursor when the tab page is current.
+    pub window: Window,
+    pub windows: Vec<Window>,
+    pub tree: WindowTree,
+}
+
+impl TabPage {
+    pub fn all_windows(&self) -> impl Iterator<Item = &Window> {
+        std::iter::once(&self.window).chain(self.windows.iter())
+    }
+
+    /// Close the windows showing buffer `number`. Returns `false` when no window is left.
+    pub fn remove_buffer(&mut self, number: usize) -> bool {
+        let closing: Vec<usize> = self
+            .windows
+            .iter()
+            .filter(|window| window.buffer == number)
+            .map(|window| window.id)
+            .collect();
+        for id in closing {
+            self.windows.retain(|window| window.id != id);
+            self.tree.remove(id);
+        }
+        if self.window.buffer == number {
+            match self.windows.pop() {
+                Some(window) => {
+                    self.tree.remove(self.window.id);
+                    self.window = window;
+                }
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+/// Part of the terminal, in columns and rows.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// How the windows divide the terminal.
+#[derive(PartialEq, Debug)]
+pub enum WindowTree {
+    Window(usize),
+    /// Parts side by side when `vertical`, as after `:vsplit`, otherwise above each
+    /// other. Each part comes with its width or height; the columns of the borders
+    /// between parts side by side are not counted.
+    Split {
+        vertical: bool,
+        parts: Vec<(WindowTree, usize)>,
+    },
+}
+
+impl WindowTree {
+    /// The rectangle of every window when the tree fills `area`.
+    pub fn layout(&self, area: Rect) -> Vec<(usize, Rect)> {
+        match self {
+            WindowTree::Window(id) => vec![(*id, area)],
+            WindowTree::Split { vertical, parts } => {
+                let mut result = Vec::new();
+                let mut offset = 0;
+                for (part, size) in parts {
+                    let rect = if *vertical {
+                        Rect {
+                            x: area.x + offset,
+                            width: *size,
+                            ..area
+                        }
+                    } else {
+                        Rect {
+                            y: area.y + offset,
+                            height: *size,
+                            ..area
+                        }
+                    };
+                    result.extend(part.layout(rect));
+                    offset += size + if *vertical { 1 } else { 0 };
+                }
+                result
+            }
+        }
+    }
+
+    /// Put window `new` before window `target`, above it or left of it when `vertical`,
+    /// sharing its room. `size` is the height or width of `target`.
+    /// Returns `false` when there is not enough room.
+    pub fn split(&mut self, target: usize, new: usize, vertical: bool, size: usize) -> bool {
+        match self {
+            WindowTree::Window(id) if *id == target => match halves(size, vertical) {
+                Some((first, second)) => {
+                    *self = WindowTree::Split {
+                        vertical,
+                        parts: vec![
+                            (WindowTree::Window(new), first),
+                            (WindowTree::Window(target), second),
+                        ],
+                    };
+                    true
+                }
+                None => false,
+            },
+            WindowTree::Window(_) => false,
+            WindowTree::Split {
+                vertical: direction,
+                parts,
+            } => {
+                let index = parts
+                    .iter()
+                    .position(|(part, _)| *part == WindowTree::Window(target));
+                match index {
+                    Some(i) if *direction == vertical => match halves(parts[i].1, vertical) {
+                        Some((first, second)) => {
+                            parts[i].1 = second;
+                            parts.insert(i, (WindowTree::Window(new), first));
+                            true
+                        }
+                        None => false,
+                    },
+                    _ => parts
+                        .iter_mut()
+                        .any(|(part, _)| part.split(target, new, vertical, size)),
+                }
+            }
+        }
+    }
+
+    /// Remove window `target`, giving its room to the part before it, or after it
+    /// when it is the first. Returns whether the window was found.
+    pub fn remove(&mut self, target: usize) -> bool {
+        let (vertical, parts) = match self {
+            WindowTree::Window(_) => return false,
+            WindowTree::Split { vertical, parts } => (*vertical, parts),
+        };
+        let index = parts
+            .iter()
+            .position(|(part, _)| *part == WindowTree::Window(target));
+        let found = match index {
+            Some(i) => {
+                let (_, size) = parts.remove(i);
+                let neighbour = i.saturating_sub(1);
+                parts[neighbour].1 += size + if vertical { 1 } else { 0 };
+                true
+            }
+            None => parts.iter_mut().any(|(part, _)| part.remove(target)),
+        };
+        if parts.len() == 1 {
+            *self = parts.remove(0).0;
+        }
+        found
+    }
+
+    /// Grow window `target` by `delta` rows, or columns when `vertical`, taking them from
+    /// the part after it, or before it when it is the last. Sizes stay at least the minimum.
+    pub fn resize(&mut self, target: usize, vertical: bool, delta: isize) -> bool {
+        let (direction, parts) = match self {
+            WindowTree::Window(_) => return false,
+            WindowTree::Split { vertical, parts } => (*vertical, parts),
+        };
+        // Resize inside the part containing the window first, then here.
+        let index = match parts.iter_mut().position(|(part, _)| part.contains(target)) {
+            Some(index) => index,
+            None => return false,
+        };
+        if parts[index].0.resize(target, vertical, delta) {
+            return true;
+        }
+        if direction != vertical || parts.len() < 2 {
+            return false;
+        }
+        let other = if index + 1 < parts.len() {
+            index + 1
+        } else {
+            index - 1
+        };
+        let minimum = if vertical { MIN_WIDTH } else { MIN_HEIGHT };
+        let delta = cmp::max(
+            cmp::min(delta, parts[other].1 as isize - minimum as isize),
+            minimum as isize - parts[index].1 as isize,
+        );
+        parts[index].1 = (parts[index].1 as isize + delta) as usize;
+        parts[other].1 = (parts[other].1 as isize - delta) as usize;
+        true
+    }
+
+    /// Give all parts of each split the same size, for `Ctrl-w =`.
+    pub fn equalize(&mut self, width: usize, height: usize) {
+        if let WindowTree::Split { vertical, parts } = self {
+            let total = if *vertical { width } else { height };
+            let count = parts.len();
+            for (i, (_, size)) in parts.iter_mut().enumerate() {
+                *size = share(total, count, i, *vertical);
+            }
+            for (part, size) in parts.iter_mut() {
+                if *vertical {
+                    part.equalize(*size, height);
+                } else {
+                    part.equalize(width, *size);
+                }
+            }
+        }
+    }
+
+    /// Scale the sizes so that the tree fills `width` and `height`, as after the terminal
+    /// was resized or a window was removed.
+    pub fn fit(&mut self, width: usize, height: usize) {
+        if let WindowTree::Split { vertical, parts } = self {
+            let (total, minimum) = if *vertical {
+                (width.saturating_sub(parts.len() - 1), MIN_WIDTH)
+            } else {
+                (height, MIN_HEIGHT)
+            };
+            let current: usize = parts.iter().map(|(_, size)| size).sum();
+            if current != total {
+                let mut left = total;
+                let count = parts.len();
+                for (i, (_, size)) in parts.iter_mut().enumerate() {
+                    *size = if i + 1 == count {
+                        left
+                    } else {
+                        let scaled = *size * total / cmp::max(current, 1);
+                        cmp::min(cmp::max(scaled, minimum), left)
+                    };
+                    left -= *size;
+                }
+            }
+            for (part, size) in parts.iter_mut() {
+                if *vertical {
+                    part.fit(*size, height);
+                } else {
+                    part.fit(width, *size);
+                }
+            }
+        }
+    }
+
+    pub fn contains(&self, target: usize) -> bool {
+        match self {
+            WindowTree::Window(id) => *id == target,
+            WindowTree::Split { parts, .. } => parts.iter().any(|(part, _)| part.contains(target)),
+        }
+    }
+}
+
+/// Sizes of the new and the old window when a window of `size` rows or columns is split,
+/// or `None` if it is too small.
+fn halves(size: usize, vertical: bool) -> Option<(usize, usize)> {
+    if vertical {
+        // One column goes to the border.
+        let room = size.checked_sub(1).filter(|&room| room >= 2 * MIN_WIDTH)?;
+        Some((room - room / 2, room / 2))
+    } else if size >= 2 * MIN_HEIGHT {
+        Some((size - size / 2, size / 2))
+    } else {
+        None
+    }
+}
+
+/// Size of part `index` when `count` parts share `total` rows or columns equally.
+fn share(total: usize, count: usize, index: usize, vertical: bool) -> usize {
+    let room = if vertical {
+        total.saturating_sub(count - 1)
+    } else {
+        total
+    };
+    room / count + if index < room % count { 1 } else { 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AREA: Rect = Rect {
+        x: 0,
+        y: 0,
+        width: 21,
+        height: 10,
+    };
+
+    #[test]
+    fn test_split_and_remove() {
+        let mut tree = WindowTree::Window(1);
+        assert!(tree.split(1, 2, true, 21));
+        assert!(tree.split(1, 3, false, 10));
+        assert_eq!(
+            tree.layout(AREA),
+            [
+                (
+                    2,
+                    Rect {
+                        x: 0,
+                        y: 0,
+                        width: 10,
+                        height: 10
+                    }
+                ),
+                (
+                    3,
+                    Rect {
+                        x: 11,
+                        y: 0,
+                        width: 10,
+                        height: 5
+                    }
+                ),
+                (
+                    1,
+                    Rect {
+                        x: 11,
+                        y: 5,
+                        width: 10,
+                        height: 5
+                    }
+                ),
+            ]
+        );
+        assert!(tree.resize(1, false, 2));
+        assert_eq!(tree.layout(AREA)[2].1.height, 7);
+        assert!(tree.resize(1, false, 10));
+        assert_eq!(tree.layout(AREA)[1].1.height, 2);
+        tree.equalize(21, 10);
+        assert_eq!(tree.layout(AREA)[1].1.height, 5);
+        assert!(tree.remove(3));
+        assert_eq!(
+            tree.layout(AREA)[1],
+            (
+                1,
+                Rect {
+                    x: 11,
+                    y: 0,
+                    width: 10,
+                    height: 10
+                }
+            )
+        );
+        assert!(tree.remove(2));
+        assert_eq!(tree, WindowTree::Window(1));
+        assert!(!tree.split(1, 2, false, 3));
+    }
+
+    #[test]
+    fn test_fit() {
+        let mut tree = WindowTree::Window(1);
+        tree.split(1, 2, false, 10);
+        tree.fit(21, 20);
+        let heights: Vec<usize> = tree.layout(AREA).iter().map(|(_, r)| r.height).collect();
+        assert_eq!(heights, [10, 10]);
+        tree.fit(21, 3);
+        let heights: Vec<usize> = tree.layout(AREA).iter().map(|(_, r)| r.height).collect();
+        assert_eq!(heights, [2, 1]);
+    }
+}
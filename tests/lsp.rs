@@ -0,0 +1,201 @@
+//! Tests of the language server client, against this test binary run as a language
+//! server with `lsp server {script} {log}`.
+
+use rim::editor::Editor;
+use rim::json::Json;
+use rim::lsp::{read_message, write_message};
+use rim::terminal::VirtualTerminal;
+use rim::util::Config;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{stdin, stdout, BufReader, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SCRIPT: &str = r#"
+textDocument/publishDiagnostics [{"range": {"start": {"line": 1, "character": 12}, "end": {"line": 1, "character": 15}}, "severity": 1, "message": "cannot find value `foo`"}]
+textDocument/hover {"contents": {"kind": "markdown", "value": "fn foo()"}}
+textDocument/definition {"uri": "$URI", "range": {"start": {"line": 3, "character": 3}, "end": {"line": 3, "character": 6}}}
+textDocument/references [{"uri": "$URI", "range": {"start": {"line": 1, "character": 12}}}, {"uri": "$URI", "range": {"start": {"line": 3, "character": 3}}}]
+textDocument/completion {"isIncomplete": false, "items": [{"label": "bar", "kind": 3}, {"label": "baz", "kind": 6}]}
+textDocument/rename {"changes": {"$URI": [{"range": {"start": {"line": 1, "character": 12}, "end": {"line": 1, "character": 15}}, "newText": "bar"}, {"range": {"start": {"line": 3, "character": 3}, "end": {"line": 3, "character": 6}}, "newText": "bar"}]}}
+"#;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("server") {
+        serve(&args[2], &args[3]);
+    } else {
+        test_language_server();
+        println!("test_language_server ... ok");
+    }
+}
+
+fn test_language_server() {
+    let dir = std::env::temp_dir().join(format!("rim-lsp-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("main.rs");
+    fs::write(&path, "fn main() {\n    let x = foo;\n}\nfn foo() {}").unwrap();
+    fs::write(dir.join("script"), SCRIPT).unwrap();
+    let log = dir.join("log");
+    let config = Config {
+        files: vec![path.to_str().unwrap().to_string()],
+        ..Default::default()
+    };
+    let mut editor = Editor::new(VirtualTerminal::new(60, 8), config);
+    editor.run_commands(&[format!(
+        "LspServer rust {} server {} {}",
+        env::current_exe().unwrap().display(),
+        dir.join("script").display(),
+        log.display()
+    )]);
+
+    // Redrawing does not wait for the server to initialize: the document is opened at a
+    // redraw after the response came, and the diagnostics published for it are shown.
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !editor.terminal().row_text(1).starts_with('E') && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(10));
+        editor.editor_loop();
+    }
+    assert!(editor.terminal().row_text(1).starts_with('E'));
+    editor.terminal_mut().feed("K");
+    editor.editor_loop();
+    let terminal = editor.terminal();
+    assert!(terminal.row_text(7).contains("fn foo()"));
+    assert_eq!(terminal.row_text(0), "  fn main() {");
+    assert_eq!(
+        terminal.row_text(1),
+        "E     let x = foo; cannot find value `foo`"
+    );
+
+    editor.terminal_mut().feed("gd");
+    editor.editor_loop();
+    assert_eq!(editor.cursor(), (3, 3));
+
+    editor.terminal_mut().feed("gr");
+    editor.editor_loop();
+    let rows: Vec<String> = (0..8).map(|y| editor.terminal().row_text(y)).collect();
+    assert!(rows
+        .iter()
+        .any(|row| row.ends_with("main.rs:2:13: let x = foo;")));
+    assert!(rows
+        .iter()
+        .any(|row| row.ends_with("main.rs:4:4: fn foo() {}")));
+
+    editor.terminal_mut().feed("\n:LspRename bar\n");
+    editor.editor_loop();
+    let lines: Vec<String> = editor.text().iter().map(|l| l.iter().collect()).collect();
+    assert_eq!(
+        lines,
+        ["fn main() {", "    let x = bar;", "}", "fn bar() {}"]
+    );
+
+    editor.terminal_mut().feed("ob\x18\x0f");
+    editor.editor_loop();
+    assert_eq!(editor.text()[4].iter().collect::<String>(), "bar");
+    assert_eq!(editor.terminal().row_text(5), "   bar Function");
+    assert_eq!(editor.terminal().row_text(6), "   baz Variable");
+    editor.terminal_mut().feed("\x1b");
+    editor.editor_loop();
+
+    // The servers are shut down with the editor, after handling what they were sent.
+    drop(editor);
+    let log = fs::read_to_string(log).unwrap();
+    let methods: Vec<&str> = log
+        .lines()
+        .filter_map(|line| line.split("\"method\":\"").nth(1))
+        .filter_map(|rest| rest.split('"').next())
+        .collect();
+    assert_eq!(
+        methods,
+        [
+            "initialize",
+            "initialized",
+            "textDocument/didOpen",
+            "textDocument/hover",
+            "textDocument/definition",
+            "textDocument/references",
+            "textDocument/rename",
+            "textDocument/didChange",
+            "textDocument/didChange",
+            "textDocument/didChange",
+            "textDocument/completion",
+            "textDocument/didChange",
+            "shutdown",
+            "exit"
+        ]
+    );
+    assert!(log.contains(
+        r#""contentChanges":[{"range":{"start":{"line":0,"character":11},"end":{"line":3,"character":11}},"text":"\n    let x = bar;\n}\nfn bar() {}"}]"#
+    ));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+/// Answer as `script` tells: its lines are a method and the JSON of its result, e.g.
+/// `textDocument/hover {"contents": "fn main()"}`. Requests get that result, or `null` if
+/// the script has none, with `$URI` replaced by the URI of the document of the request.
+/// The `textDocument/publishDiagnostics` line gives the diagnostics published whenever
+/// a document is opened or changed. Every message received is appended to `log`, one
+/// per line.
+fn serve(script: &str, log: &str) {
+    let script = fs::read_to_string(script).unwrap();
+    let results: Vec<(&str, &str)> = script
+        .lines()
+        .filter_map(|line| line.trim().split_once(' '))
+        .collect();
+    let result = |method: &str, uri: &str| {
+        results
+            .iter()
+            .find(|(name, _)| *name == method)
+            .map(|(_, result)| Json::parse(&result.replace("$URI", uri)).unwrap())
+    };
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log)
+        .unwrap();
+    let mut reader = BufReader::new(stdin());
+    let mut stdout = stdout();
+    while let Some(message) = read_message(&mut reader) {
+        writeln!(log, "{}", message).unwrap();
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let uri = message
+            .at(&["params", "textDocument", "uri"])
+            .and_then(Json::as_str)
+            .unwrap_or("");
+        match method {
+            "exit" => return,
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                if let Some(diagnostics) = result("textDocument/publishDiagnostics", uri) {
+                    let params =
+                        Json::object(vec![("uri", uri.into()), ("diagnostics", diagnostics)]);
+                    let notification = Json::object(vec![
+                        ("jsonrpc", "2.0".into()),
+                        ("method", "textDocument/publishDiagnostics".into()),
+                        ("params", params),
+                    ]);
+                    write_message(&mut stdout, &notification).unwrap();
+                }
+            }
+            _ => (),
+        }
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => continue,
+        };
+        let default = match method {
+            "initialize" => Json::parse(
+                r#"{"capabilities": {"textDocumentSync": 2, "hoverProvider": true,
+                    "definitionProvider": true, "referencesProvider": true, "renameProvider": true}}"#,
+            )
+            .unwrap(),
+            _ => Json::Null,
+        };
+        let response = Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("id", id),
+            ("result", result(method, uri).unwrap_or(default)),
+        ]);
+        write_message(&mut stdout, &response).unwrap();
+    }
+}